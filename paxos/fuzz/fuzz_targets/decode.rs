@@ -0,0 +1,11 @@
+#![no_main]
+// Arbitrary bytes into the decoder: it may only return Ok or a DecodeError,
+// never panic. Whatever does decode has to re-encode to the exact same bytes.
+use libfuzzer_sys::fuzz_target;
+use paxos_state_machine::wire;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = wire::decode::<u64>(data) {
+        assert_eq!(wire::encode(&msg), data);
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+// Same as `decode` but with a length-prefixed value type, so the length
+// checks (oversized prefixes, short bodies) get exercised too.
+use libfuzzer_sys::fuzz_target;
+use paxos_state_machine::wire;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = wire::decode::<Vec<u8>>(data) {
+        assert_eq!(wire::encode(&msg), data);
+    }
+});
@@ -0,0 +1,86 @@
+// Decisions per second through a Multi-Paxos pipeline of in-memory Nodes.
+//
+// Everything runs on the simulated network with fixed latency and no
+// faults, so each configuration does exactly the same work on every run;
+// only the wall clock around it varies. Run with
+//
+//     cargo bench -p paxos-state-machine --bench consensus
+//
+// Node 1 is a stable leader: it gets its promise quorum with one
+// PrepareFrom before anything is timed, so every slot measured costs just
+// its AcceptProposals and the acks. Slots go out `depth` at a time and
+// each batch is waited for, until the leader's learner has it; at depth 1
+// the time per slot is the per-slot latency.
+use std::collections::HashSet;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use paxos_state_machine::{node::Node, sim::Network, types::*};
+
+const LEADER: NodeId = 1;
+// Phase timeout; a few round trips at the default 1ms latency
+const TIMER_MS: u64 = 20;
+const CLUSTER_SIZES: &[u64] = &[3, 5];
+const DEPTHS: &[u64] = &[1, 8, 32];
+// Slots decided per timed iteration
+const SLOTS: u64 = 256;
+
+/// A cluster whose leader already holds its promise quorum.
+struct Pipeline {
+    net: Network<u64, Node<u64>>,
+    next_slot: Slot,
+}
+
+impl Pipeline {
+    fn new(n: u64) -> Self {
+        let ctx = NodeContext::new(n);
+        let ids: Vec<NodeId> = (1..=n).collect();
+        let mut net = Network::new();
+        for &id in &ids {
+            let learners: HashSet<NodeId> = ids.iter().copied().collect();
+            let node = Node::new(id, ctx.clone(), ids.clone(), learners, 0, TIMER_MS, (n / 2 + 1) as usize).unwrap();
+            net.add_node(id, node);
+        }
+        let actions = net.node_mut(LEADER).unwrap().lead_from(0);
+        net.submit(LEADER, actions);
+        while !net.node(LEADER).unwrap().proposer().is_leading() {
+            assert!(net.step(), "no promise quorum for the leader");
+        }
+        Self { net, next_slot: 0 }
+    }
+
+    /// Propose `slots` more slots, `depth` at a time, waiting for each batch.
+    fn run(&mut self, slots: u64, depth: u64) {
+        let end = self.next_slot + slots;
+        while self.next_slot < end {
+            let batch = self.next_slot..(self.next_slot + depth).min(end);
+            self.next_slot = batch.end;
+            for slot in batch.clone() {
+                let actions = self.net.node_mut(LEADER).unwrap().propose(slot, slot);
+                self.net.submit(LEADER, actions);
+            }
+            let mut pending: Vec<Slot> = batch.collect();
+            while !pending.is_empty() {
+                assert!(self.net.step(), "network ran dry with {} slots undecided", pending.len());
+                let learner = self.net.node(LEADER).unwrap().learner();
+                pending.retain(|s| learner.get_chosen(*s).is_none());
+            }
+        }
+        assert!(self.net.node(LEADER).unwrap().proposer().is_leading(), "leader lost its promise quorum");
+    }
+}
+
+fn pipeline(c: &mut Criterion) {
+    for &n in CLUSTER_SIZES {
+        let mut group = c.benchmark_group(format!("pipeline/{n}_nodes"));
+        group.throughput(Throughput::Elements(SLOTS));
+        for &depth in DEPTHS {
+            group.bench_with_input(BenchmarkId::new("depth", depth), &depth, |b, &depth| {
+                b.iter_batched_ref(|| Pipeline::new(n), |p| p.run(SLOTS, depth), BatchSize::LargeInput);
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
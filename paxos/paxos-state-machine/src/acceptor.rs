@@ -1,17 +1,99 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 
 use crate::{
+    config::PaxosConfig,
+    log_store::{LogStore, RoleLog},
     msg::PaxosMsg,
     proposer::Proposal,
     types::*,
+    wire::{self, DecodeError, Wire},
 };
 
+/// Counters bumped as the acceptor handles messages. Lots of nacks relative
+/// to grants usually means proposers are dueling.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AcceptorMetrics {
+    pub prepares_seen: u64,
+    pub promises_granted: u64,
+    pub accepts_granted: u64,
+    pub nacks_sent: u64,
+}
+
+/// Everything an acceptor must not forget across a restart, as one record
+/// so a storage backend can write it in a single fsync.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AcceptorPersisted<V> {
+    pub latest_promise: Option<ProposalId>,
+    pub accepted: BTreeMap<Slot, Proposal<V>>,
+}
+
+impl<V: Wire> AcceptorPersisted<V> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+
+    /// Leftover bytes are an error, as with `wire::decode`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        wire::decode_exact(bytes)
+    }
+}
+
+/// Durable home for the acceptor's state. `persist` is handed the whole
+/// record whenever the promise or an accepted slot changed, before any
+/// reply to the message that changed it goes out, and must not return
+/// until it's on disk. A batch fed through `on_events` is persisted once.
+pub trait AcceptorStore<V> {
+    fn persist(&mut self, state: &AcceptorPersisted<V>);
+}
+
+/// What a refused Prepare or AcceptProposal gets back.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NackMode {
+    /// Nothing. The proposer only finds out when its phase timer runs out,
+    /// as integrations that predate Nack expect.
+    #[default]
+    Silent,
+    /// A `Nack` naming the promise that refused it, which the proposer
+    /// outbids on its next round instead of waiting out its timer.
+    Enabled,
+}
+
+/// A Prepare or AcceptProposal we refused, and the promise that refused it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rejection {
+    pub proposer: NodeId,
+    pub attempted: ProposalId,
+    pub promised: ProposalId,
+}
+
 pub struct Acceptor<V> {
     context: NodeContext,
     node_id: NodeId,
-    latest_accepted_proposal: Option<Proposal<V>>,
+    // One promise covers every slot; accepted proposals are per slot.
+    accepted: RoleLog<Proposal<V>>,
     latest_promise: Option<ProposalId>,
-    learners: HashSet<NodeId>,
+    // Ordered, so broadcasts go out (and runs replay) in id order
+    learners: BTreeSet<NodeId>,
+    metrics: AcceptorMetrics,
+    // Most recent last; capacity 0 means we don't keep any
+    rejections: VecDeque<Rejection>,
+    rejection_capacity: usize,
+    // Joined a running cluster and hasn't been bootstrapped yet
+    awaiting_bootstrap: bool,
+    // Promise with just the accepted id; the value goes out on FetchAccepted
+    lazy_promises: bool,
+    // Report accepts to this learner only, instead of all of `learners`
+    distinguished_learner: Option<NodeId>,
+    nack_mode: NackMode,
+    // Highest `committed_up_to` a proposer has told us
+    committed_up_to: Option<Slot>,
+    store: Option<Box<dyn AcceptorStore<V> + Send>>,
+    // Durable state changed since the last persist
+    dirty: bool,
+    // Inside on_events: persist once at the end, not per message
+    batching: bool,
 }
 
 impl<V: Clone> Acceptor<V> {
@@ -19,19 +101,205 @@ impl<V: Clone> Acceptor<V> {
         Self {
             node_id,
             context,
-            latest_accepted_proposal: None,
+            accepted: RoleLog::new(),
             latest_promise: None,
-            learners,
+            learners: learners.into_iter().collect(),
+            metrics: AcceptorMetrics::default(),
+            rejections: VecDeque::new(),
+            rejection_capacity: 0,
+            awaiting_bootstrap: false,
+            lazy_promises: false,
+            distinguished_learner: None,
+            nack_mode: NackMode::Silent,
+            committed_up_to: None,
+            store: None,
+            dirty: false,
+            batching: false,
+        }
+    }
+    /// `new`, with settings from `config`.
+    pub fn with_config(node_id: NodeId, context: NodeContext, learners: HashSet<NodeId>, config: &PaxosConfig) -> Self {
+        let mut acceptor = Self::new(node_id, context, learners);
+        acceptor.set_lazy_promises(config.lazy_promises);
+        acceptor.set_nack_mode(config.nack_mode);
+        acceptor
+    }
+    /// An acceptor joining a cluster that may already have decided slots.
+    /// With empty state it could promise an id below one those decisions
+    /// were made at, so it stays silent until `bootstrap`.
+    pub fn joining(node_id: NodeId, context: NodeContext, learners: HashSet<NodeId>) -> Self {
+        Self { awaiting_bootstrap: true, ..Self::new(node_id, context, learners) }
+    }
+    /// `new`, starting out having promised `promise` and accepted
+    /// `accepted`, e.g. to reproduce a reported state in a test. A promise
+    /// below one of the accepted ids is raised to it: we can't have accepted
+    /// past our own promise.
+    pub fn with_state(
+        node_id: NodeId,
+        context: NodeContext,
+        learners: HashSet<NodeId>,
+        promise: Option<ProposalId>,
+        accepted: impl IntoIterator<Item = (Slot, Proposal<V>)>,
+    ) -> Self {
+        let mut acceptor = Self::new(node_id, context, learners);
+        acceptor.restore(AcceptorPersisted { latest_promise: promise, accepted: accepted.into_iter().collect() });
+        acceptor.latest_promise = acceptor.watermark();
+        acceptor
+    }
+    /// Highest proposal id we've promised or accepted: what a joining
+    /// acceptor needs from us.
+    pub fn watermark(&self) -> Option<ProposalId> {
+        let accepted = self.accepted.range(0).map(|(_, p)| p.id).max();
+        self.latest_promise.max(accepted)
+    }
+    /// Raise our promise to `watermark` and start participating. Feed it the
+    /// highest `watermark()` of a quorum of existing acceptors; since every
+    /// decision reached a quorum, that covers all of them. Calling it again
+    /// only ever raises the promise.
+    pub fn bootstrap(&mut self, watermark: Option<ProposalId>) {
+        self.dirty |= self.latest_promise < watermark;
+        self.latest_promise = self.latest_promise.max(watermark);
+        self.awaiting_bootstrap = false;
+        self.flush();
+    }
+    pub fn is_bootstrapped(&self) -> bool {
+        !self.awaiting_bootstrap
+    }
+    /// Answer Prepares with `PromiseIdOnly`, leaving the (possibly large)
+    /// accepted value out until a proposer asks for it with `FetchAccepted`.
+    /// It only ever needs the value of the highest id in its quorum. A slot
+    /// with nothing accepted gets `PromiseEmpty` either way.
+    pub fn set_lazy_promises(&mut self, lazy: bool) {
+        self.lazy_promises = lazy;
+    }
+    /// Whether refusals are dropped (the default) or answered with a Nack.
+    /// Refusals land in the rejection log either way.
+    pub fn set_nack_mode(&mut self, mode: NackMode) {
+        self.nack_mode = mode;
+    }
+    /// Send our Accepted to `learner` alone rather than to every learner,
+    /// cutting acceptor -> learner traffic from N x M to N. That learner
+    /// decides and tells the rest: give it `Learner::relay_to` them, whose
+    /// first Learn then decides at the default relay threshold. None (the
+    /// default) goes back to telling every learner.
+    pub fn set_distinguished_learner(&mut self, learner: Option<NodeId>) {
+        self.distinguished_learner = learner;
+    }
+    /// Keep accepted proposals in `store` instead of in memory, e.g. on
+    /// disk. Set it before any traffic; anything accepted so far is
+    /// dropped, not copied over. The promise isn't part of the log.
+    pub fn set_log_store(&mut self, store: impl LogStore<Proposal<V>> + Send + 'static) {
+        self.accepted.set_backend(Box::new(store));
+    }
+    /// Persist through `store` before replying; see `AcceptorStore`.
+    pub fn set_store(&mut self, store: impl AcceptorStore<V> + Send + 'static) {
+        self.store = Some(Box::new(store));
+    }
+    /// Remember the last `capacity` rejections for `recent_rejections`.
+    /// Off (0) by default.
+    pub fn set_rejection_log(&mut self, capacity: usize) {
+        self.rejection_capacity = capacity;
+        while self.rejections.len() > capacity {
+            self.rejections.pop_front();
+        }
+    }
+    /// Oldest first.
+    pub fn recent_rejections(&self) -> impl Iterator<Item = &Rejection> + '_ {
+        self.rejections.iter()
+    }
+    pub fn metrics(&self) -> AcceptorMetrics {
+        self.metrics
+    }
+    /// Clear metrics and the rejection log. The promise and accepted slots
+    /// are durable state and stay; see `reset_durable`.
+    pub fn reset(&mut self) {
+        self.metrics = AcceptorMetrics::default();
+        self.rejections.clear();
+    }
+    /// `reset`, and forget every promise and accepted value too. Only safe
+    /// when no proposer can still be counting on our old votes, e.g. between
+    /// test cases.
+    pub fn reset_durable(&mut self) {
+        self.reset();
+        self.latest_promise = None;
+        self.accepted.clear();
+    }
+    /// Every slot up to and including this one is decided, as piggybacked
+    /// on AcceptProposals (see `Proposer::set_piggyback_commits`).
+    pub fn committed_up_to(&self) -> Option<Slot> {
+        self.committed_up_to
+    }
+    /// The promise we're holding, covering every slot. It only ever rises:
+    /// there's deliberately no way to expire one held for a proposer that
+    /// looks dead. A wrongly suspected proposer may already have counted it
+    /// toward its quorum, and a lower round let through after the fact could
+    /// get a different value chosen in the same slot.
+    pub fn promised(&self) -> Option<ProposalId> {
+        self.latest_promise
+    }
+    /// What we last accepted in `slot`.
+    pub fn accepted(&self, slot: Slot) -> Option<&Proposal<V>> {
+        self.accepted.get(slot)
+    }
+    /// Would we promise `proposal_id` right now?
+    pub fn can_promise(&self, proposal_id: ProposalId) -> bool {
+        self.latest_promise.is_none_or(|p| proposal_id >= p)
+    }
+    /// Would we accept a value proposed as `proposal_id` right now?
+    pub fn can_accept(&self, proposal_id: ProposalId) -> bool {
+        self.latest_promise.is_none_or(|p| proposal_id >= p)
+    }
+    /// Our durable state, to write out before answering anything.
+    pub fn persisted(&self) -> AcceptorPersisted<V> {
+        let accepted = self.accepted.range(0).map(|(slot, p)| (slot, p.clone())).collect();
+        AcceptorPersisted { latest_promise: self.latest_promise, accepted }
+    }
+    /// Pick up from a `persisted()` record after a restart. Replaces the
+    /// promise and every accepted slot; metrics and settings are kept.
+    pub fn restore(&mut self, state: AcceptorPersisted<V>) {
+        self.latest_promise = state.latest_promise;
+        self.accepted.clear();
+        for (slot, p) in state.accepted {
+            self.accepted.put(slot, p);
         }
     }
+    pub fn context(&self) -> &NodeContext {
+        &self.context
+    }
+    fn flush(&mut self) {
+        if !std::mem::take(&mut self.dirty) {
+            return;
+        }
+        let state = self.persisted();
+        if let Some(store) = &mut self.store {
+            store.persist(&state);
+        }
+    }
+    fn nack(&mut self, to: NodeId, slot: Slot, proposal_id: ProposalId) -> Vec<Action<V>> {
+        // Only reachable when a promise exists, it's what refused us.
+        let Some(promised) = self.latest_promise else { return vec![] };
+        if self.rejection_capacity > 0 {
+            if self.rejections.len() == self.rejection_capacity {
+                self.rejections.pop_front();
+            }
+            self.rejections.push_back(Rejection { proposer: to, attempted: proposal_id, promised });
+        }
+        if self.nack_mode == NackMode::Silent {
+            return vec![];
+        }
+        self.metrics.nacks_sent += 1;
+        vec![Action::Send { to, from: self.node_id, msg: PaxosMsg::Nack { slot, proposal_id, promised } }]
+    }
     fn learners_broadcast(&self, msg: PaxosMsg<V>) -> Vec<Action<V>>
     where
         PaxosMsg<V>: Clone,
     {
+        if let Some(to) = self.distinguished_learner {
+            return vec![Action::Send { to, from: self.node_id, msg }];
+        }
         self.learners
             .iter()
-            .copied()
-            .map(|to| Action::Send { to, from: self.node_id, msg: msg.clone() })
+            .map(|&to| Action::Send { to, from: self.node_id, msg: msg.clone() })
             .collect()
     }
 }
@@ -45,48 +313,116 @@ where
     }
 
     fn on_message(&mut self, from: NodeId, msg: PaxosMsg<V>) -> Vec<Action<V>> {
+        let actions = self.handle(from, msg);
+        if !self.batching {
+            self.flush();
+        }
+        actions
+    }
+
+    /// The whole batch is applied, then persisted once, then answered: one
+    /// fsync for a leader's burst of Prepares instead of one each.
+    fn on_events(&mut self, events: Vec<Event<V>>, dedup: bool) -> Vec<Action<V>>
+    where
+        V: PartialEq,
+    {
+        self.batching = true;
+        let mut out: Vec<Action<V>> = Vec::new();
+        for e in events {
+            for a in self.on_event(e) {
+                if dedup && matches!(a, Action::Send { .. }) && out.contains(&a) {
+                    continue;
+                }
+                out.push(a);
+            }
+        }
+        self.batching = false;
+        self.flush();
+        out
+    }
+
+    fn on_timeout(&mut self, _id: TimerId) -> Vec<Action<V>> {
+        vec![]
+    }
+}
+
+impl<V: Clone> Acceptor<V>
+where
+    PaxosMsg<V>: Clone,
+{
+    fn handle(&mut self, from: NodeId, msg: PaxosMsg<V>) -> Vec<Action<V>> {
+        if self.awaiting_bootstrap {
+            return vec![];
+        }
         match msg {
             // PREPARE: promise if proposal_id >= latest_promise
-            PaxosMsg::Prepare { proposal_id, from: proposer } => {
-                let can_promise = self
-                    .latest_promise
-                    .map_or(true, |p| proposal_id >= p);
-
-                if can_promise {
+            PaxosMsg::Prepare { slot, proposal_id, from: proposer } => {
+                self.metrics.prepares_seen += 1;
+                if self.can_promise(proposal_id) {
+                    self.dirty |= self.latest_promise != Some(proposal_id);
                     self.latest_promise = Some(proposal_id);
-                    return vec![Action::Send {
-                        to: proposer,
-                        from: self.node_id,
-                        msg: PaxosMsg::Promise {
-                            accepted_proposal: self.latest_accepted_proposal.clone(),
+                    self.metrics.promises_granted += 1;
+                    let msg = if !self.accepted.contains(slot) {
+                        PaxosMsg::PromiseEmpty { slot, proposal_response: proposal_id }
+                    } else if self.lazy_promises {
+                        PaxosMsg::PromiseIdOnly {
+                            slot,
+                            accepted_id: self.accepted.get(slot).map(|p| p.id),
                             proposal_response: proposal_id,
-                        },
-                    }];
+                        }
+                    } else {
+                        PaxosMsg::Promise {
+                            slot,
+                            accepted_proposal: self.accepted.get(slot).cloned(),
+                            proposal_response: proposal_id,
+                        }
+                    };
+                    return vec![Action::Send { to: proposer, from: self.node_id, msg }];
                 }
-                vec![]
+                self.nack(proposer, slot, proposal_id)
             }
-            PaxosMsg::AcceptProposal { proposal_id, value } => {
-                let can_accept = self
-                    .latest_promise
-                    .map_or(true, |p| proposal_id >= p);
-
-                if !can_accept {
-                    return vec![]; // or NACK if you have one
+            // Our one promise already covers every slot; this just reports
+            // the whole tail of what we accepted under it. Lazy promises
+            // don't apply, it always carries the values.
+            PaxosMsg::PrepareFrom { from_slot, proposal_id, from: proposer } => {
+                self.metrics.prepares_seen += 1;
+                if !self.can_promise(proposal_id) {
+                    return self.nack(proposer, from_slot, proposal_id);
                 }
-                let accepted = Proposal { id: proposal_id, value: value.clone() };
+                self.dirty |= self.latest_promise != Some(proposal_id);
                 self.latest_promise = Some(proposal_id);
-                self.latest_accepted_proposal = Some(accepted.clone());
-                return self.learners_broadcast(PaxosMsg::Learn {
-                    proposal_id,
-                    value
-                });
-
+                self.metrics.promises_granted += 1;
+                let accepted = self.accepted.range(from_slot).map(|(slot, p)| (slot, p.clone())).collect();
+                let msg = PaxosMsg::PromiseFrom { from_slot, proposal_response: proposal_id, accepted };
+                vec![Action::Send { to: proposer, from: self.node_id, msg }]
             }
+            PaxosMsg::AcceptProposal { slot, proposal_id, value, committed_up_to, request_id } => {
+                // A decided slot stays decided, whoever's accept tells us
+                self.committed_up_to = self.committed_up_to.max(committed_up_to);
+                if !self.can_accept(proposal_id) {
+                    return self.nack(from, slot, proposal_id);
+                }
+                self.metrics.accepts_granted += 1;
+                let accepted = Proposal { id: proposal_id, value };
+                self.latest_promise = Some(proposal_id);
+                self.accepted.put(slot, accepted.clone());
+                self.dirty = true;
+                // Ack the proposer, then tell the learners.
+                let msg = PaxosMsg::Accepted { slot, proposal: accepted, request_id };
+                let mut actions = vec![Action::Send { to: from, from: self.node_id, msg: msg.clone() }];
+                actions.extend(self.learners_broadcast(msg));
+                actions
+            }
+            PaxosMsg::FetchAccepted { slot, proposal_id } => match self.accepted.get(slot) {
+                // Anything else means we've moved on, and so has that round.
+                Some(p) if p.id == proposal_id => vec![Action::Send {
+                    to: from,
+                    from: self.node_id,
+                    msg: PaxosMsg::AcceptedValue { slot, proposal: p.clone() },
+                }],
+                _ => vec![],
+            },
             _ => vec![],
         }
     }
-
-    fn on_timeout(&mut self, _id: TimerId) -> Vec<Action<V>> {
-        vec![]
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,64 @@
+// The bridge from the learner's chosen log to application state.
+//
+// An `Applier` owns the user's state and hands it each chosen command in
+// slot order, exactly once. Slots decided out of order wait until every slot
+// before them is decided too, so the state only ever reflects a gap-free
+// prefix of the log.
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use crate::{learner::Learner, types::Slot};
+
+/// Applies one chosen command to the state `S`.
+pub trait Apply<S, V> {
+    fn apply(&mut self, state: &mut S, slot: Slot, cmd: &V);
+}
+
+impl<S, V, F: FnMut(&mut S, Slot, &V)> Apply<S, V> for F {
+    fn apply(&mut self, state: &mut S, slot: Slot, cmd: &V) {
+        self(state, slot, cmd)
+    }
+}
+
+pub struct Applier<S, V, A> {
+    state: S,
+    apply: A,
+    // Next slot to apply; everything below it has been
+    next: Slot,
+    _cmd: PhantomData<fn(&V)>,
+}
+
+impl<S, V, A> Applier<S, V, A>
+where
+    V: Clone + Eq + Hash,
+    A: Apply<S, V>,
+{
+    pub fn new(state: S, apply: A) -> Self {
+        Self { state, apply, next: 0, _cmd: PhantomData }
+    }
+
+    /// Apply every slot `learner` has chosen from our applied index on,
+    /// stopping at the first it hasn't. How many were applied. Call it
+    /// after handing the learner messages.
+    pub fn catch_up(&mut self, learner: &Learner<V>) -> usize {
+        let start = self.next;
+        while let Some(cmd) = learner.get_chosen(self.next) {
+            self.apply.apply(&mut self.state, self.next, cmd);
+            self.next += 1;
+        }
+        (self.next - start) as usize
+    }
+
+    /// Slots applied so far: `0..applied_index()`.
+    pub fn applied_index(&self) -> Slot {
+        self.next
+    }
+
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    pub fn into_state(self) -> S {
+        self.state
+    }
+}
@@ -0,0 +1,63 @@
+// Client-side helpers that talk to the cluster without proposing.
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::{msg::PaxosMsg, types::*};
+
+/// Cheap "has anything been chosen in `slot`?" probe. Send `broadcast` to
+/// the learners, feed their `ChosenReply`s to `on_reply`, and trust `value`
+/// only once a quorum of them agree.
+pub struct ChosenQuery<V> {
+    slot: Slot,
+    quorum: usize,
+    replies: HashMap<NodeId, Option<V>>,
+}
+
+impl<V> ChosenQuery<V>
+where
+    V: Clone + Eq + Hash,
+{
+    pub fn new(slot: Slot, quorum: usize) -> Self {
+        Self { slot, quorum, replies: HashMap::new() }
+    }
+
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    pub fn broadcast(&self, from: NodeId, learners: impl IntoIterator<Item = NodeId>) -> Vec<Action<V>> {
+        learners
+            .into_iter()
+            .map(|to| Action::Send { to, from, msg: PaxosMsg::QueryChosen { slot: self.slot } })
+            .collect()
+    }
+
+    /// Record a reply; anything that isn't a `ChosenReply` for our slot is
+    /// ignored. A learner's later reply replaces its earlier one.
+    pub fn on_reply(&mut self, from: NodeId, msg: PaxosMsg<V>) {
+        if let PaxosMsg::ChosenReply { slot, value } = msg {
+            if slot == self.slot {
+                self.replies.insert(from, value);
+            }
+        }
+    }
+
+    pub fn replies(&self) -> usize {
+        self.replies.len()
+    }
+
+    /// The value a quorum of learners reported as chosen. `None` means no
+    /// quorum agrees on one yet, not that nothing was chosen: a learner may
+    /// simply not have heard.
+    pub fn value(&self) -> Option<&V> {
+        let mut counts: HashMap<&V, usize> = HashMap::new();
+        for v in self.replies.values().flatten() {
+            let n = counts.entry(v).or_default();
+            *n += 1;
+            if *n >= self.quorum {
+                return Some(v);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,135 @@
+// Client-facing wrapper around a simulated cluster of full nodes.
+//
+// `propose` hands a value to the current leader and returns a future that
+// resolves once the leader has learned the slot it went into. Polling the
+// future is what drives the network, a bounded number of steps per poll, so
+// any executor (or a plain poll loop) will do.
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::{node::Node, sim::Network, types::*};
+
+/// Network steps a `Propose` runs before yielding back to its executor.
+const STEPS_PER_POLL: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProposeError<V> {
+    /// The slot was decided, but for someone else's value.
+    OtherValueChosen(V),
+    /// The leader is crashed (or not in the network).
+    LeaderDown { leader: NodeId },
+    /// The network ran dry before the slot was decided.
+    Stalled { slot: Slot },
+}
+
+impl<V: fmt::Debug> fmt::Display for ProposeError<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProposeError::OtherValueChosen(v) => write!(f, "another value was chosen: {v:?}"),
+            ProposeError::LeaderDown { leader } => write!(f, "leader {leader} is down"),
+            ProposeError::Stalled { slot } => write!(f, "no decision for slot {slot}"),
+        }
+    }
+}
+
+impl<V: fmt::Debug> std::error::Error for ProposeError<V> {}
+
+pub struct Cluster<V> {
+    net: Network<V, Node<V>>,
+    leader: NodeId,
+}
+
+impl<V> Cluster<V>
+where
+    V: Clone + Eq + Hash,
+{
+    pub fn new(net: Network<V, Node<V>>, leader: NodeId) -> Self {
+        Self { net, leader }
+    }
+
+    pub fn leader(&self) -> NodeId {
+        self.leader
+    }
+
+    pub fn set_leader(&mut self, leader: NodeId) {
+        self.leader = leader;
+    }
+
+    pub fn network(&self) -> &Network<V, Node<V>> {
+        &self.net
+    }
+
+    pub fn network_mut(&mut self) -> &mut Network<V, Node<V>> {
+        &mut self.net
+    }
+
+    /// First slot the leader hasn't learned a value for.
+    pub fn next_slot(&self) -> Slot {
+        let Some(node) = self.net.node(self.leader) else { return 0 };
+        node.learner().first_gap()
+    }
+
+    /// Propose `v` at the leader in `next_slot()`. Resolves to `v` once the
+    /// leader learns it, or `OtherValueChosen` if the slot went to a
+    /// competing value.
+    pub fn propose(&mut self, v: V) -> Propose<'_, V> {
+        let slot = self.next_slot();
+        Propose { cluster: self, slot, value: v, submitted: false }
+    }
+}
+
+/// Future returned by `Cluster::propose`.
+pub struct Propose<'a, V> {
+    cluster: &'a mut Cluster<V>,
+    slot: Slot,
+    value: V,
+    // Handed to the leader on first poll, not at construction
+    submitted: bool,
+}
+
+impl<V> Propose<'_, V> {
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+}
+
+// Nothing is pin-projected; the value is only ever cloned out.
+impl<V> Unpin for Propose<'_, V> {}
+
+impl<V> Future for Propose<'_, V>
+where
+    V: Clone + Eq + Hash,
+{
+    type Output = Result<V, ProposeError<V>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let leader = this.cluster.leader;
+        let net = &mut this.cluster.net;
+        if net.is_down(leader) || net.node(leader).is_none() {
+            return Poll::Ready(Err(ProposeError::LeaderDown { leader }));
+        }
+        if !this.submitted {
+            this.submitted = true;
+            let actions = net.node_mut(leader).unwrap().propose(this.slot, this.value.clone());
+            net.submit(leader, actions);
+        }
+        for _ in 0..STEPS_PER_POLL {
+            if let Some(chosen) = net.node(leader).and_then(|n| n.learner().get_chosen(this.slot)) {
+                return Poll::Ready(if *chosen == this.value {
+                    Ok(chosen.clone())
+                } else {
+                    Err(ProposeError::OtherValueChosen(chosen.clone()))
+                });
+            }
+            if !net.step() {
+                return Poll::Ready(Err(ProposeError::Stalled { slot: this.slot }));
+            }
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
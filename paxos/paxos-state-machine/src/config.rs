@@ -0,0 +1,60 @@
+// Every role's tunables in one place, for the `with_config` constructors.
+//
+// The positional `new`s and the `set_x` setters still work; a config is just
+// the same settings gathered up, with the defaults those constructors use.
+use crate::{acceptor::NackMode, proposer::BackoffConfig, types::NodeContext};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PaxosConfig {
+    /// Quorum for both of the proposer's phases and the learner's accept
+    /// count. None: a majority of `number_of_nodes`.
+    pub quorum: Option<usize>,
+    /// Proposer phase timer; `base_ms` is the initial timeout.
+    pub backoff: BackoffConfig,
+    /// See `Proposer::set_accept_retries`.
+    pub accept_retries: u32,
+    /// See `Proposer::set_broadcast_budget`; 0 is no limit.
+    pub broadcast_budget: u32,
+    /// Surface anomalies as `Action::Error` (proposer and learner).
+    pub strict: bool,
+    /// See `Proposer::set_loopback`.
+    pub loopback: bool,
+    /// See `Acceptor::set_lazy_promises`.
+    pub lazy_promises: bool,
+    /// See `Acceptor::set_nack_mode`.
+    pub nack_mode: NackMode,
+    /// See `Learner::set_stall_timeout`; 0 is off.
+    pub stall_timeout_ms: u64,
+    /// See `Learner::set_relay_threshold`; 0 decides on acks or the first Learn.
+    pub relay_threshold: usize,
+    /// See `Learner::set_catch_up_limit`; 0 is no limit.
+    pub catch_up_max_entries: usize,
+    /// See `Learner::set_max_inflight`; 0 is no limit.
+    pub max_inflight: usize,
+}
+
+impl Default for PaxosConfig {
+    fn default() -> Self {
+        Self {
+            quorum: None,
+            backoff: BackoffConfig::default(),
+            accept_retries: 2,
+            broadcast_budget: 0,
+            strict: false,
+            loopback: false,
+            lazy_promises: false,
+            nack_mode: NackMode::Silent,
+            stall_timeout_ms: 0,
+            relay_threshold: 0,
+            catch_up_max_entries: 0,
+            max_inflight: 0,
+        }
+    }
+}
+
+impl PaxosConfig {
+    /// `quorum`, or a majority of `ctx`'s nodes.
+    pub fn quorum_for(&self, ctx: &NodeContext) -> usize {
+        self.quorum.unwrap_or((ctx.number_of_nodes / 2 + 1) as usize)
+    }
+}
@@ -0,0 +1,255 @@
+// Randomized safety checks against the reference model of Paxos.
+//
+// Each run builds a cluster of full nodes on the sim network and throws a
+// seeded schedule of proposals, message drops, reordering (jitter) and node
+// crash/recover at it. After every step the learners are compared with what
+// has been observed so far: a slot may only ever hold one value across the
+// whole cluster, and a value a node has learned must stay learned.
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::{
+    node::Node,
+    rng::Rng,
+    sim::{Faults, Network},
+    types::*,
+};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Random runs, one per seed starting at `first_seed`.
+    pub iterations: u64,
+    pub first_seed: u64,
+    pub nodes: u64,
+    /// Nodes 1..=proposers compete for every slot.
+    pub proposers: u64,
+    pub slots: u64,
+    pub drop_rate: f64,
+    pub jitter_ms: u64,
+    /// Crash/recover cycles per run.
+    pub restarts: u32,
+    /// Virtual time a run may take.
+    pub horizon_ms: u64,
+    /// Also require every node to learn every slot by the end. Only sensible
+    /// for fault-free configs.
+    pub expect_progress: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            iterations: 200,
+            first_seed: 0,
+            nodes: 5,
+            proposers: 3,
+            slots: 3,
+            drop_rate: 0.1,
+            jitter_ms: 20,
+            restarts: 2,
+            horizon_ms: 30_000,
+            expect_progress: false,
+        }
+    }
+}
+
+impl Config {
+    /// One proposer, no faults: every slot must be learned everywhere. With
+    /// acceptors announcing accepts as `Learn` (which learners ignore) no
+    /// learner ever decided, and this is the run that catches it.
+    pub fn learn_accepted_regression() -> Self {
+        Self {
+            iterations: 1,
+            first_seed: 0x1ea2_acce,
+            nodes: 3,
+            proposers: 1,
+            slots: 2,
+            drop_rate: 0.0,
+            jitter_ms: 0,
+            restarts: 0,
+            horizon_ms: 10_000,
+            expect_progress: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// Two nodes learned different values for the same slot.
+    Conflict { seed: u64, slot: Slot, first: u64, second: u64, node: NodeId },
+    /// `node` had learned `slot` and then lost it.
+    Unchosen { seed: u64, slot: Slot, node: NodeId },
+    /// `expect_progress` was set and `node` never learned `slot`.
+    NoDecision { seed: u64, slot: Slot, node: NodeId },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Conflict { seed, slot, first, second, node } => {
+                write!(f, "seed {seed}: node {node} learned {second} in slot {slot}, but {first} was chosen")
+            }
+            Violation::Unchosen { seed, slot, node } => {
+                write!(f, "seed {seed}: node {node} forgot the value chosen in slot {slot}")
+            }
+            Violation::NoDecision { seed, slot, node } => {
+                write!(f, "seed {seed}: node {node} never learned slot {slot}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub runs: u64,
+    pub steps: u64,
+    /// (node, slot) pairs learned, summed over runs.
+    pub learned: u64,
+    pub dropped: u64,
+}
+
+/// The regression runs, then `config.iterations` random ones. Stops at the
+/// first violation.
+pub fn run(config: &Config) -> Result<Report, Violation> {
+    let mut report = run_seed(&Config::learn_accepted_regression(), Config::learn_accepted_regression().first_seed)?;
+    for i in 0..config.iterations {
+        let r = run_seed(config, config.first_seed.wrapping_add(i))?;
+        report.runs += r.runs;
+        report.steps += r.steps;
+        report.learned += r.learned;
+        report.dropped += r.dropped;
+    }
+    Ok(report)
+}
+
+enum Op {
+    Init(NodeId),
+    Propose(NodeId, Slot),
+    Crash(NodeId),
+    Recover(NodeId),
+}
+
+/// A value no other proposer would pick for the same slot.
+fn value_for(node: NodeId, slot: Slot) -> u64 {
+    node * 1_000_000 + slot
+}
+
+/// One run with the schedule drawn from `seed`; replaying a seed replays the
+/// run exactly.
+pub fn run_seed(config: &Config, seed: u64) -> Result<Report, Violation> {
+    let ids: Vec<NodeId> = (1..=config.nodes).collect();
+    let ctx = NodeContext::new(config.nodes);
+    let quorum = (config.nodes / 2 + 1) as usize;
+    let mut net: Network<u64, Node<u64>> = Network::new();
+    net.set_faults(Faults { drop_rate: config.drop_rate, jitter_ms: config.jitter_ms }, seed);
+    for &id in &ids {
+        let learners: HashSet<NodeId> = ids.iter().copied().collect();
+        let node = Node::new(id, ctx.clone(), ids.clone(), learners, value_for(id, 0), 50, quorum);
+        net.add_node(id, node.expect("every node is a peer"));
+    }
+
+    // The schedule has its own stream so changing fault settings doesn't
+    // reshuffle it.
+    let mut rng = Rng::new(seed ^ 0x5eed_5c4e_d01e);
+    let mut ops = Vec::new();
+    for p in 1..=config.proposers.min(config.nodes) {
+        ops.push((rng.below(50), Op::Init(p)));
+        for slot in 1..config.slots {
+            ops.push((rng.below(config.horizon_ms / 4 + 1), Op::Propose(p, slot)));
+        }
+    }
+    for _ in 0..config.restarts {
+        let node = 1 + rng.below(config.nodes);
+        let at = rng.below(config.horizon_ms / 2 + 1);
+        ops.push((at, Op::Crash(node)));
+        ops.push((at + 1 + rng.below(2_000), Op::Recover(node)));
+    }
+    ops.sort_by_key(|(at, _)| *at);
+    let mut ops = ops.into_iter().peekable();
+
+    let mut checker = Checker { seed, chosen: BTreeMap::new(), learned: HashSet::new() };
+    let mut steps = 0;
+    loop {
+        let next_net = net.next_event_at().filter(|at| *at <= config.horizon_ms);
+        match (ops.peek().map(|(at, _)| *at), next_net) {
+            (Some(op_at), n) if n.is_none_or(|n| op_at < n) => {
+                let (at, op) = ops.next().unwrap();
+                net.run_until(at); // nothing due before `at`; just moves the clock
+                apply(&mut net, op);
+            }
+            (_, Some(_)) => {
+                net.step();
+                steps += 1;
+            }
+            (_, None) => break,
+        }
+        checker.check(&net, &ids, config.slots)?;
+    }
+
+    if config.expect_progress {
+        for &node in &ids {
+            for slot in 0..config.slots {
+                if !checker.learned.contains(&(node, slot)) {
+                    return Err(Violation::NoDecision { seed, slot, node });
+                }
+            }
+        }
+    }
+    Ok(Report { runs: 1, steps, learned: checker.learned.len() as u64, dropped: net.dropped() })
+}
+
+fn apply(net: &mut Network<u64, Node<u64>>, op: Op) {
+    match op {
+        Op::Init(node) => net.init_node(node),
+        Op::Propose(node, slot) => {
+            let Some(n) = net.node_mut(node) else { return };
+            let actions = n.propose(slot, value_for(node, slot));
+            net.submit(node, actions);
+        }
+        Op::Crash(node) => {
+            net.crash(node);
+            if let Some(n) = net.node_mut(node) {
+                // Timers are gone with the crash; nothing to cancel.
+                n.proposer_mut().step_down();
+            }
+        }
+        Op::Recover(node) => {
+            net.recover(node);
+            let Some(n) = net.node_mut(node) else { return };
+            let actions = n.proposer_mut().activate();
+            net.submit(node, actions);
+        }
+    }
+}
+
+struct Checker {
+    seed: u64,
+    chosen: BTreeMap<Slot, u64>,
+    learned: HashSet<(NodeId, Slot)>,
+}
+
+impl Checker {
+    fn check(&mut self, net: &Network<u64, Node<u64>>, ids: &[NodeId], slots: u64) -> Result<(), Violation> {
+        let seed = self.seed;
+        for &node in ids {
+            let Some(n) = net.node(node) else { continue };
+            for slot in 0..slots {
+                match n.learner().get_chosen(slot) {
+                    Some(&v) => {
+                        let first = *self.chosen.entry(slot).or_insert(v);
+                        if first != v {
+                            return Err(Violation::Conflict { seed, slot, first, second: v, node });
+                        }
+                        self.learned.insert((node, slot));
+                    }
+                    None if self.learned.contains(&(node, slot)) => {
+                        return Err(Violation::Unchosen { seed, slot, node });
+                    }
+                    None => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+// Deterministic leader election on top of the failure detector.
+//
+// The leader is simply the lowest member id that isn't suspected. Every node
+// runs the same rule over its own detector, so once the detectors agree the
+// nodes agree. Only the leader keeps its proposer active; Paxos keeps things
+// safe while detectors disagree, this only cuts down on dueling.
+use std::collections::BTreeSet;
+
+use crate::{failure_detector::FailureDetector, proposer::Proposer, types::*};
+
+pub struct Election {
+    node_id: NodeId,
+    members: BTreeSet<NodeId>,
+    leader: Option<NodeId>,
+    // Bumped every time the leader changes
+    round: u64,
+}
+
+impl Election {
+    pub fn new(node_id: NodeId, members: impl IntoIterator<Item = NodeId>) -> Self {
+        let mut members: BTreeSet<NodeId> = members.into_iter().collect();
+        members.insert(node_id);
+        Self { node_id, members, leader: None, round: 0 }
+    }
+
+    pub fn leader(&self) -> Option<NodeId> {
+        self.leader
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.leader == Some(self.node_id)
+    }
+
+    /// Re-run the rule against `fd`. Returns the new leader if it changed.
+    /// We never suspect ourselves.
+    pub fn update(&mut self, fd: &FailureDetector, now_ms: u64) -> Option<NodeId> {
+        let leader = self
+            .members
+            .iter()
+            .copied()
+            .find(|n| *n == self.node_id || !fd.is_suspected(*n, now_ms));
+        if leader == self.leader {
+            return None;
+        }
+        self.leader = leader;
+        self.round += 1;
+        leader
+    }
+
+    /// `update`, then wake or idle the local proposer to match.
+    pub fn drive<V: Clone>(&mut self, fd: &FailureDetector, now_ms: u64, proposer: &mut Proposer<V>) -> Vec<Action<V>> {
+        self.update(fd, now_ms);
+        if self.is_leader() {
+            proposer.activate()
+        } else {
+            proposer.step_down()
+        }
+    }
+}
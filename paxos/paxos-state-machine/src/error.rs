@@ -0,0 +1,68 @@
+// Protocol-level anomalies a role can report instead of silently dropping.
+use std::fmt;
+
+use crate::types::{NodeId, ProposalId, Slot};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PaxosError {
+    /// A Promise answered a proposal id we aren't running in `slot`
+    /// (`current` is the one we are, if any).
+    PromiseForUnknownRound { slot: Slot, got: ProposalId, current: Option<ProposalId> },
+    /// A caller-supplied round at or below one we've already used; `next`
+    /// is the lowest we'd take.
+    RoundTooLow { round: u64, next: u64 },
+    /// `node` reported two different values accepted under `pid` in `slot`.
+    Equivocation { node: NodeId, slot: Slot, pid: ProposalId },
+    /// A learner got an Accepted for `slot` from `node`, which isn't one of
+    /// its acceptors.
+    AckFromNonAcceptor { node: NodeId, slot: Slot },
+    /// A learner already tracking `max` undecided slots dropped the votes
+    /// it had for `slot`, the least recently heard from, to make room.
+    TooManyInflight { slot: Slot, max: usize },
+    /// What got chosen in `slot` was never seen in any AcceptProposal for
+    /// it: corrupted state or a forged quorum.
+    SafetyViolation { slot: Slot },
+    /// `node` relayed a Learn for `slot` with a value other than the one
+    /// we'd already chosen there.
+    ConflictingLearn { node: NodeId, slot: Slot },
+    /// Our own proposer sent an AcceptProposal for `slot` with a value other
+    /// than the one our learner knows was chosen there. Caught (and the
+    /// accept dropped) by `Node::set_safety_tripwire`.
+    ProposedOverChosen { slot: Slot },
+}
+
+impl fmt::Display for PaxosError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaxosError::PromiseForUnknownRound { slot, got, current: Some(current) } => {
+                write!(f, "promise for {got:?} in slot {slot}, but our round there is {current:?}")
+            }
+            PaxosError::PromiseForUnknownRound { slot, got, current: None } => {
+                write!(f, "promise for {got:?} in slot {slot}, where we have no round")
+            }
+            PaxosError::RoundTooLow { round, next } => {
+                write!(f, "round {round} already used, next free round is {next}")
+            }
+            PaxosError::Equivocation { node, slot, pid } => {
+                write!(f, "node {node} reported two values for {pid:?} in slot {slot}")
+            }
+            PaxosError::AckFromNonAcceptor { node, slot } => {
+                write!(f, "accept for slot {slot} from node {node}, which is not an acceptor")
+            }
+            PaxosError::TooManyInflight { slot, max } => {
+                write!(f, "over {max} undecided slots, dropped the votes for slot {slot}")
+            }
+            PaxosError::SafetyViolation { slot } => {
+                write!(f, "value chosen in slot {slot} was never proposed there")
+            }
+            PaxosError::ConflictingLearn { node, slot } => {
+                write!(f, "node {node} relayed a different value for slot {slot} than the one chosen")
+            }
+            PaxosError::ProposedOverChosen { slot } => {
+                write!(f, "proposer tried to get a different value accepted in decided slot {slot}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PaxosError {}
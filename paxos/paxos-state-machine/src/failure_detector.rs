@@ -0,0 +1,46 @@
+// Timeout-based failure detector over virtual time.
+//
+// The caller feeds it heartbeats (any message from a peer counts) and the
+// current time in ms; a peer we haven't heard from for `timeout_ms` is
+// suspected. Peers can also be suspected explicitly, e.g. after a transport
+// gave up on them. Nodes we've never heard of are presumed alive.
+use std::collections::{HashMap, HashSet};
+
+use crate::types::NodeId;
+
+pub struct FailureDetector {
+    timeout_ms: u64,
+    last_heard: HashMap<NodeId, u64>,
+    suspected: HashSet<NodeId>,
+}
+
+impl FailureDetector {
+    pub fn new(timeout_ms: u64) -> Self {
+        Self { timeout_ms, last_heard: HashMap::new(), suspected: HashSet::new() }
+    }
+
+    /// We heard from `node` at `now_ms`; this also clears an explicit suspicion.
+    pub fn heartbeat(&mut self, node: NodeId, now_ms: u64) {
+        self.suspected.remove(&node);
+        let t = self.last_heard.entry(node).or_insert(now_ms);
+        *t = (*t).max(now_ms);
+    }
+
+    pub fn suspect(&mut self, node: NodeId) {
+        self.suspected.insert(node);
+    }
+
+    pub fn is_suspected(&self, node: NodeId, now_ms: u64) -> bool {
+        if self.suspected.contains(&node) {
+            return true;
+        }
+        self.last_heard
+            .get(&node)
+            .is_some_and(|t| now_ms.saturating_sub(*t) > self.timeout_ms)
+    }
+
+    /// The subset of `nodes` not currently suspected, in input order.
+    pub fn alive<'a>(&'a self, nodes: impl IntoIterator<Item = &'a NodeId>, now_ms: u64) -> Vec<NodeId> {
+        nodes.into_iter().copied().filter(|n| !self.is_suspected(*n, now_ms)).collect()
+    }
+}
@@ -1,47 +1,517 @@
 // src/learner.rs
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+#[cfg(feature = "wait-for")]
+use std::future::Future;
 use std::hash::Hash;
-use crate::{types::*, msg::PaxosMsg};
+#[cfg(feature = "wait-for")]
+use std::pin::Pin;
+#[cfg(feature = "wait-for")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "wait-for")]
+use std::task::{Context, Poll, Waker};
+use crate::{types::*, msg::PaxosMsg, node_set::NodeSet, error::PaxosError, log_store::{LogStore, RoleLog}, config::PaxosConfig};
+
+/// Most slots past its base one `Learner::digest` describes: a 1 KiB
+/// bitmap.
+pub const DIGEST_WINDOW: Slot = 8 * 1024;
+
+/// Resolves with the value chosen in a slot; see `Learner::wait_for`. Not
+/// tied to any runtime: `decide` wakes whichever task last polled it.
+/// Needs the `wait-for` feature, on by default.
+#[cfg(feature = "wait-for")]
+pub struct WaitFor<V> {
+    shared: Arc<Mutex<Waiter<V>>>,
+}
+
+#[cfg(feature = "wait-for")]
+struct Waiter<V> {
+    value: Option<V>,
+    waker: Option<Waker>,
+}
+
+#[cfg(feature = "wait-for")]
+impl<V> Future for WaitFor<V> {
+    type Output = V;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<V> {
+        let mut w = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        match w.value.take() {
+            Some(v) => Poll::Ready(v),
+            None => {
+                w.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// How far along the learner's log is, as of when `Learner::stats` was
+/// called.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LearnerStats {
+    /// Slots decided.
+    pub chosen: u64,
+    /// Undecided slots with votes coming in.
+    pub inflight: u64,
+    /// Undecided slots below the highest decided one.
+    pub gaps: u64,
+}
+
 pub struct Learner<V> {
     node_id: NodeId,
+    context: NodeContext,
     quorum: usize,
-    acks: HashMap<ProposalId, HashSet<NodeId>>,
-    chosen: HashMap<ProposalId, V>,
+    // A quorum only counts within one proposal id, never pooled by value.
+    // Of acceptors A, B, C, say A accepts v under id 1, C accepts w under 2
+    // and B accepts v under 3: A and B hold v, but round 4, promised by A
+    // and C, must adopt w (the highest) and can still get it chosen.
+    acks: HashMap<(Slot, ProposalId), NodeSet>,
+    // What each acceptor said it accepted, to catch one reporting two
+    // values under the same id. None once it has.
+    reported: HashMap<(NodeId, Slot, ProposalId), Option<V>>,
+    chosen: RoleLog<V>,
+    // The proposal id each slot was chosen under, where we know it
+    chosen_ids: HashMap<Slot, ProposalId>,
+    // Learners we tell (via Learn) whenever we decide a slot
+    relay_to: Vec<NodeId>,
+    // 0: acceptor acks decide, and so does any one learner's Learn relay.
+    // Otherwise it takes this many distinct learners' relays, and our own
+    // ack quorum is only relayed.
+    relay_threshold: usize,
+    relays: HashMap<(Slot, ProposalId), NodeSet>,
+    // Slots whose ack quorum we've seen (and relayed) but not yet chosen
+    relayed: HashSet<Slot>,
+    // 0: off. Otherwise slots not chosen this long after their first ack
+    // are reported with LearnStalled.
+    stall_ms: u64,
+    stall_timers: HashMap<Slot, TimerId>,
+    next_timer_seq: u64,
+    // Tell the proposer behind a decided id, with PaxosMsg::Chosen
+    notify_proposers: bool,
+    // Most entries we put in one CatchUpResponse; 0 means no limit
+    catch_up_max_entries: usize,
+    // Only acks from these count. None: anyone's do.
+    acceptors: Option<HashSet<NodeId>>,
+    // Report dropped acks from non-acceptors as Action::Error
+    strict: bool,
+    // 0: no limit. Otherwise the most undecided slots we hold votes for;
+    // `inflight` has them least recently heard from first.
+    max_inflight: usize,
+    inflight: VecDeque<Slot>,
+    // Debugging aid: every (slot, value) seen in an AcceptProposal, when
+    // set_check_proposed is on
+    proposed: Option<HashSet<(Slot, V)>>,
+    // wait_for futures still pending, per slot
+    #[cfg(feature = "wait-for")]
+    waiters: HashMap<Slot, Vec<Arc<Mutex<Waiter<V>>>>>,
+    // Audit aid: the quorum behind each slot decided on acks, when
+    // set_retain_acks is on
+    retained_acks: Option<HashMap<Slot, HashSet<NodeId>>>,
 }
 impl<V> Learner<V>
 where
     V: Clone + Eq + Hash,
 {
-    pub fn new(node_id: NodeId, context: NodeContext) -> Self {
-        let quorum = (context.number_of_nodes / 2 + 1) as usize;
+    /// `quorum` is the accept quorum: how many acceptor acks make a value
+    /// chosen. A majority of `number_of_nodes` for classic Paxos, but with
+    /// flexible quorums it can be anything that intersects every Phase 1
+    /// quorum.
+    pub fn new(node_id: NodeId, context: NodeContext, quorum: usize) -> Self {
         Self {
             node_id,
+            context,
             quorum,
             acks: HashMap::new(),
-            chosen: HashMap::new(),
+            reported: HashMap::new(),
+            chosen: RoleLog::new(),
+            chosen_ids: HashMap::new(),
+            relay_to: Vec::new(),
+            relay_threshold: 0,
+            relays: HashMap::new(),
+            relayed: HashSet::new(),
+            stall_ms: 0,
+            stall_timers: HashMap::new(),
+            next_timer_seq: 0,
+            notify_proposers: false,
+            catch_up_max_entries: 0,
+            acceptors: None,
+            strict: false,
+            max_inflight: 0,
+            inflight: VecDeque::new(),
+            #[cfg(feature = "wait-for")]
+            waiters: HashMap::new(),
+            proposed: None,
+            retained_acks: None,
+        }
+    }
+    /// `new`, starting out with `chosen` already decided, as if learned
+    /// through catch-up (so without proposal ids).
+    pub fn with_state(node_id: NodeId, context: NodeContext, quorum: usize, chosen: impl IntoIterator<Item = (Slot, V)>) -> Self {
+        let mut learner = Self::new(node_id, context, quorum);
+        for (slot, v) in chosen {
+            learner.chosen.put(slot, v);
+        }
+        learner
+    }
+    /// `new`, with the quorum and the rest taken from `config`.
+    pub fn with_config(node_id: NodeId, context: NodeContext, config: &PaxosConfig) -> Self {
+        let quorum = config.quorum_for(&context);
+        let mut learner = Self::new(node_id, context, quorum);
+        learner.set_stall_timeout(config.stall_timeout_ms);
+        learner.set_relay_threshold(config.relay_threshold);
+        learner.set_catch_up_limit(config.catch_up_max_entries);
+        learner.set_max_inflight(config.max_inflight);
+        learner.set_strict(config.strict);
+        learner
+    }
+    /// Hold votes for at most `max` undecided slots; past that, the slot
+    /// least recently heard from loses what it had (and its stall timer),
+    /// reported as `PaxosError::TooManyInflight` when strict. Its acks then
+    /// count from scratch. Decided slots don't count. 0, the default, is no
+    /// limit.
+    pub fn set_max_inflight(&mut self, max: usize) {
+        self.max_inflight = max;
+    }
+    /// Keep, for every slot whose ack quorum we saw, which acceptors made it
+    /// up, for `acked_by`. Costs a set per slot for as long as we run; off
+    /// by default, when acks are dropped as soon as the slot is decided.
+    pub fn set_retain_acks(&mut self, on: bool) {
+        self.retained_acks = on.then(HashMap::new);
+    }
+    /// The acceptors whose acks made `slot`'s quorum, if `set_retain_acks`
+    /// is on and we saw one. Acks arriving after it aren't added.
+    pub fn acked_by(&self, slot: Slot) -> Option<&HashSet<NodeId>> {
+        self.retained_acks.as_ref()?.get(&slot)
+    }
+    /// Keep chosen values in `store` instead of in memory. Set it before
+    /// any traffic: what was chosen so far is dropped, not copied over.
+    pub fn set_log_store(&mut self, store: impl LogStore<V> + Send + 'static) {
+        self.chosen.set_backend(Box::new(store));
+    }
+    /// Report `Action::LearnStalled` for a slot still undecided `ms` after
+    /// its first ack. 0 (the default) turns it off.
+    pub fn set_stall_timeout(&mut self, ms: u64) {
+        self.stall_ms = ms;
+    }
+    /// Only report a slot chosen once `n` distinct learners have relayed it
+    /// with `PaxosMsg::Learn` (our own relay counts if we're in `relay_to`).
+    /// An acceptor ack quorum is then just relayed, not reported. 0, the
+    /// default, decides on an ack quorum or on the first Learn, whichever
+    /// comes first: a learner only relays what it saw a quorum accept.
+    pub fn set_relay_threshold(&mut self, n: usize) {
+        self.relay_threshold = n;
+    }
+    /// Answer a `CatchUpRequest` with at most `max_entries` slots at a time;
+    /// the response's `next` says where to pick up. 0 (the default) sends
+    /// the whole range at once.
+    pub fn set_catch_up_limit(&mut self, max_entries: usize) {
+        self.catch_up_max_entries = max_entries;
+    }
+    /// Only count `Accepted` from these nodes, so a misconfigured or spoofed
+    /// sender can't make up part of a quorum. By default anyone's ack counts.
+    pub fn set_acceptors(&mut self, acceptors: impl IntoIterator<Item = NodeId>) {
+        self.acceptors = Some(acceptors.into_iter().collect());
+    }
+    /// Report acks we drop for coming from a non-acceptor as `Action::Error`
+    /// instead of dropping them silently.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    /// On every decision, send `PaxosMsg::Chosen` to the proposer whose id
+    /// won, so it can confirm to its client without counting accepts.
+    pub fn set_notify_proposers(&mut self, notify: bool) {
+        self.notify_proposers = notify;
+    }
+    fn notify_proposer(&self, slot: Slot, proposal_id: ProposalId) -> Option<Action<V>> {
+        self.notify_proposers.then(|| Action::Send {
+            to: proposal_id.1,
+            from: self.node_id,
+            msg: PaxosMsg::Chosen { slot, proposal_id },
+        })
+    }
+    /// Relay every decision to `learners` as a `PaxosMsg::Learn`.
+    pub fn relay_to(&mut self, learners: impl IntoIterator<Item = NodeId>) {
+        self.relay_to = learners.into_iter().collect();
+    }
+    /// Cancel every pending stall timer; the slots stay undecided, we just
+    /// stop watching them.
+    pub fn cancel_stall_timers(&mut self) -> Vec<Action<V>> {
+        let mut timers: Vec<_> = self.stall_timers.drain().collect();
+        timers.sort_by_key(|(slot, _)| *slot);
+        timers.into_iter().map(|(_, id)| Action::CancelTimer { id }).collect()
+    }
+    /// Forget every ack, relay and chosen value; relay and stall settings
+    /// stay. Stall timers still pending go stale, and `wait_for` futures
+    /// still pending never resolve.
+    pub fn reset(&mut self) {
+        #[cfg(feature = "wait-for")]
+        self.waiters.clear();
+        self.acks.clear();
+        self.reported.clear();
+        self.chosen.clear();
+        self.chosen_ids.clear();
+        self.relays.clear();
+        self.relayed.clear();
+        self.stall_timers.clear();
+        self.inflight.clear();
+        if let Some(retained) = &mut self.retained_acks {
+            retained.clear();
+        }
+    }
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+    pub fn get_chosen(&self, slot: Slot) -> Option<&V> {
+        self.chosen.get(slot)
+    }
+    /// `get_chosen`, with the proposal id whose accept quorum (or relayed
+    /// Learn) decided it: which proposal won. None for a slot we only have
+    /// from catch-up, which doesn't carry ids.
+    pub fn chosen_with_id(&self, slot: Slot) -> Option<(&V, ProposalId)> {
+        Some((self.chosen.get(slot)?, *self.chosen_ids.get(&slot)?))
+    }
+    /// Debugging aid, for fuzzing and simulation: remember every value we're
+    /// shown in an AcceptProposal (feed the learner a copy of each), and
+    /// report `PaxosError::SafetyViolation` alongside any decision for a
+    /// value never proposed in that slot. Off by default.
+    pub fn set_check_proposed(&mut self, on: bool) {
+        self.proposed = on.then(HashSet::new);
+    }
+    /// A future for the value chosen in `slot`, ready at once if we know
+    /// it already. Any number of them can wait on the same slot; dropping
+    /// one stops us holding on to it.
+    #[cfg(feature = "wait-for")]
+    pub fn wait_for(&mut self, slot: Slot) -> WaitFor<V> {
+        self.prune_waiters();
+        let value = self.chosen.get(slot).cloned();
+        let decided = value.is_some();
+        let shared = Arc::new(Mutex::new(Waiter { value, waker: None }));
+        if !decided {
+            self.waiters.entry(slot).or_default().push(shared.clone());
+        }
+        WaitFor { shared }
+    }
+
+    /// `wait_for` futures still waiting on an undecided slot.
+    #[cfg(feature = "wait-for")]
+    pub fn waiting(&self) -> usize {
+        self.waiters.values().flatten().filter(|w| Arc::strong_count(w) > 1).count()
+    }
+
+    // Drop the waiters whose future is gone, and any left on a decided slot
+    #[cfg(feature = "wait-for")]
+    fn prune_waiters(&mut self) {
+        let chosen = &self.chosen;
+        self.waiters.retain(|slot, waiters| {
+            waiters.retain(|w| Arc::strong_count(w) > 1);
+            !waiters.is_empty() && !chosen.contains(*slot)
+        });
+    }
+    /// Votes are coming in for `slot` but it isn't decided yet: someone is
+    /// running a round there.
+    pub fn is_in_flight(&self, slot: Slot) -> bool {
+        !self.chosen.contains(slot)
+            && (self.relayed.contains(&slot)
+                || self.acks.keys().any(|(s, _)| *s == slot)
+                || self.relays.keys().any(|(s, _)| *s == slot))
+    }
+    pub fn stats(&self) -> LearnerStats {
+        let chosen = self.chosen.range(0).count() as u64;
+        let end = self.chosen.last_slot().map_or(0, |s| s.saturating_add(1));
+        let mut inflight: HashSet<Slot> = self.acks.keys().chain(self.relays.keys()).map(|(s, _)| *s).collect();
+        inflight.extend(&self.relayed);
+        inflight.retain(|s| !self.chosen.contains(*s));
+        LearnerStats { chosen, inflight: inflight.len() as u64, gaps: end - chosen }
+    }
+    /// Lowest slot we haven't learned a value for.
+    pub fn first_gap(&self) -> Slot {
+        (0..).find(|s| !self.chosen.contains(*s)).unwrap_or(Slot::MAX)
+    }
+    /// What we've chosen, as a `LearnDigest` to gossip to other learners:
+    /// our first gap as its base, so the prefix below it costs nothing, and
+    /// a bitmap of at most `DIGEST_WINDOW` slots from there. Anything further
+    /// out goes in later digests, as the base moves up.
+    pub fn digest(&self) -> PaxosMsg<V> {
+        let base = self.first_gap();
+        let end = self.chosen.last_slot().map_or(0, |s| s.saturating_add(1));
+        let up_to = end.clamp(base, base.saturating_add(DIGEST_WINDOW));
+        let mut bitmap = vec![0u8; (up_to - base).div_ceil(8) as usize];
+        for (slot, _) in self.chosen.range(base).take_while(|(s, _)| *s < up_to) {
+            let bit = slot - base;
+            bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+        PaxosMsg::LearnDigest { base, up_to, bitmap }
+    }
+    /// Slots a peer's digest has that we don't, as `start..end` runs. Work
+    /// is bounded by our own log and the bitmap actually sent, whatever
+    /// `base` and `up_to` claim.
+    fn missing_ranges(&self, base: Slot, up_to: Slot, bitmap: &[u8]) -> Vec<(Slot, Slot)> {
+        let mut ranges: Vec<(Slot, Slot)> = Vec::new();
+        let mut push = |slot: Slot, end: Slot| match ranges.last_mut() {
+            Some((_, last)) if *last == slot => *last = end,
+            _ => ranges.push((slot, end)),
+        };
+        // The peer has all of 0..base: ask for the holes between our slots
+        let mut next = 0;
+        for (slot, _) in self.chosen.range(0).take_while(|(s, _)| *s < base) {
+            if slot > next {
+                push(next, slot);
+            }
+            next = slot + 1;
+        }
+        if next < base {
+            push(next, base);
+        }
+        // A short bitmap just means the missing bits are clear
+        let window = (bitmap.len() as u64).saturating_mul(8).min(DIGEST_WINDOW);
+        let up_to = up_to.min(base.saturating_add(window));
+        for slot in base..up_to {
+            let bit = slot - base;
+            if bitmap[(bit / 8) as usize] & (1 << (bit % 8)) != 0 && !self.chosen.contains(slot) {
+                push(slot, slot + 1);
+            }
+        }
+        ranges
+    }
+    /// Choose `v` in `slot` and drop what we were tracking for it; None if
+    /// it's already chosen. Every path to a decision (acks, relays,
+    /// catch-up) goes through here, so whichever gets there first emits the
+    /// slot's one ChoseValue and the rest are no-ops. Otherwise the slot's
+    /// stall timer, if it had one, to cancel.
+    fn settle(&mut self, slot: Slot, v: V, pid: Option<ProposalId>) -> Option<Option<TimerId>> {
+        if self.chosen.contains(slot) {
+            return None;
+        }
+        self.decide(slot, v);
+        if let Some(pid) = pid {
+            self.chosen_ids.insert(slot, pid);
+        }
+        self.relays.retain(|(seen_slot, _), _| *seen_slot != slot);
+        self.forget_votes(slot);
+        self.relayed.remove(&slot);
+        Some(self.stall_timers.remove(&slot))
+    }
+    /// Mark `slot` most recently heard from, evicting the least recent
+    /// undecided slot if that puts us over `max_inflight`.
+    fn touch_inflight(&mut self, slot: Slot) -> Vec<Action<V>> {
+        if self.max_inflight == 0 || self.chosen.contains(slot) {
+            return vec![];
+        }
+        self.inflight.retain(|s| *s != slot);
+        self.inflight.push_back(slot);
+        let mut actions = vec![];
+        while self.inflight.len() > self.max_inflight {
+            let Some(old) = self.inflight.pop_front() else { break };
+            self.relays.retain(|(seen_slot, _), _| *seen_slot != old);
+            self.relayed.remove(&old);
+            self.forget_votes(old);
+            actions.extend(self.stall_timers.remove(&old).map(|id| Action::CancelTimer { id }));
+            if self.strict {
+                actions.push(Action::Error { error: PaxosError::TooManyInflight { slot: old, max: self.max_inflight } });
+            }
         }
+        actions
     }
-    pub fn get_chosen(&self, pid: ProposalId) -> Option<&V> {
-        self.chosen.get(&pid)
+    /// `ChoseValue` for `v` in `slot`, preceded by a `SafetyViolation` if
+    /// `set_check_proposed` is on and never saw it proposed.
+    fn chose(&self, slot: Slot, v: V, request_id: Option<u64>) -> Vec<Action<V>> {
+        let mut actions = vec![];
+        if self.proposed.as_ref().is_some_and(|seen| !seen.contains(&(slot, v.clone()))) {
+            actions.push(Action::Error { error: PaxosError::SafetyViolation { slot } });
+        }
+        actions.push(Action::ChoseValue { slot, v, request_id });
+        actions
+    }
+    /// Record `v` as chosen in `slot` and resolve its `wait_for`s.
+    fn decide(&mut self, slot: Slot, v: V) {
+        #[cfg(feature = "wait-for")]
+        for waiter in self.waiters.remove(&slot).unwrap_or_default() {
+            let waker = {
+                let mut w = waiter.lock().unwrap_or_else(|e| e.into_inner());
+                w.value = Some(v.clone());
+                w.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+        self.chosen.put(slot, v);
+    }
+    fn forget_votes(&mut self, slot: Slot) {
+        self.acks.retain(|(seen_slot, _), _| *seen_slot != slot);
+        self.reported.retain(|(_, seen_slot, _), _| *seen_slot != slot);
+        self.inflight.retain(|s| *s != slot);
+    }
+    /// An acceptor that reports a different value for an id it already
+    /// reported is buggy or lying: from then on its acks for that id don't
+    /// count, for either value. Some means drop this ack and answer with the
+    /// actions given.
+    fn check_equivocation(&mut self, from: NodeId, slot: Slot, pid: ProposalId, v: &V) -> Option<Vec<Action<V>>> {
+        if self.chosen.contains(slot) || self.relayed.contains(&slot) {
+            return None;
+        }
+        match self.reported.get(&(from, slot, pid)) {
+            None => {
+                self.reported.insert((from, slot, pid), Some(v.clone()));
+                None
+            }
+            Some(Some(seen)) if seen == v => None,
+            Some(Some(_)) => {
+                self.reported.insert((from, slot, pid), None);
+                if let Some(acks) = self.acks.get_mut(&(slot, pid)) {
+                    acks.remove(from);
+                }
+                Some(vec![Action::Error { error: PaxosError::Equivocation { node: from, slot, pid } }])
+            }
+            Some(None) => Some(vec![]),
+        }
+    }
+    /// On the first ack for an undecided slot, arm its stall timer.
+    fn arm_stall_timer(&mut self, slot: Slot) -> Option<Action<V>> {
+        if self.stall_ms == 0 || self.chosen.contains(slot) || self.stall_timers.contains_key(&slot) {
+            return None;
+        }
+        let id = TimerId { seq: self.next_timer_seq, node: self.node_id, kind: TimerKind::LearnStalled(slot) };
+        self.next_timer_seq = self.next_timer_seq.saturating_add(1);
+        self.stall_timers.insert(slot, id);
+        Some(Action::SetTimer { id, ms: self.stall_ms })
     }
-    fn record_accepted(&mut self, from: NodeId, pid: ProposalId, v: V) -> Option<V> {
-        // If we already chose for this pid, ignore further acks.
-        if self.chosen.contains_key(&pid) {
+    /// The value if this ack completed a quorum, plus the slot's stall timer
+    /// if choosing it means that timer must go.
+    fn record_accepted(&mut self, from: NodeId, slot: Slot, pid: ProposalId, v: V) -> Option<(V, Option<TimerId>)> {
+        // If we already chose (or relayed) this slot, ignore further acks.
+        if self.chosen.contains(slot) || self.relayed.contains(&slot) {
             return None;
         }
-        let entry = self.acks.entry(pid).or_insert_with(HashSet::new);
+        let entry = self.acks.entry((slot, pid)).or_insert_with(|| self.context.node_set());
         if !entry.insert(from) {
             return None;
         }
-        if entry.len() >= self.quorum {
-            // We just learned (pid, v)
-            self.chosen.insert(pid, v.clone());
-            // Optionally GC: drop other values tracked for this pid.
-            self.acks.retain(|(seen_pid), _| *seen_pid != pid);
-            return Some(v);
+        if self.context.is_quorum(entry, self.quorum) {
+            if let Some(retained) = &mut self.retained_acks {
+                retained.insert(slot, entry.iter().collect());
+            }
+            // We just learned (slot, v)
+            if self.relay_threshold > 0 {
+                self.relayed.insert(slot);
+                // GC: drop every other proposal tracked for this slot.
+                self.forget_votes(slot);
+                return Some((v, None));
+            }
+            return self.settle(slot, v.clone(), Some(pid)).map(|stall_timer| (v, stall_timer));
         }
         None
     }
+    fn record_learn(&mut self, from: NodeId, slot: Slot, pid: ProposalId, v: V) -> Option<(V, Option<TimerId>)> {
+        if self.chosen.contains(slot) {
+            return None;
+        }
+        let entry = self.relays.entry((slot, pid)).or_insert_with(|| self.context.node_set());
+        if !entry.insert(from) || entry.len() < self.relay_threshold.max(1) {
+            return None;
+        }
+        self.settle(slot, v.clone(), Some(pid)).map(|stall_timer| (v, stall_timer))
+    }
 }
 impl<V> HandlesEvents<V> for Learner<V>
 where
@@ -52,16 +522,151 @@ where
     }
     fn on_message(&mut self, from: NodeId, msg: PaxosMsg<V>) -> Vec<Action<V>> {
         match msg {
-            PaxosMsg::Accepted { proposal } => {
-                if let Some(chosen_v) = self.record_accepted(from, proposal.id, proposal.value.clone()) {
-                    return vec![Action::ChoseValue { v: chosen_v }];
+            PaxosMsg::Accepted { slot, proposal, request_id } => {
+                let pid = proposal.id;
+                if self.acceptors.as_ref().is_some_and(|a| !a.contains(&from)) {
+                    if !self.strict {
+                        return vec![];
+                    }
+                    return vec![Action::Error { error: PaxosError::AckFromNonAcceptor { node: from, slot } }];
+                }
+                if let Some(actions) = self.check_equivocation(from, slot, pid, &proposal.value) {
+                    return actions;
+                }
+                let mut actions = self.touch_inflight(slot);
+                actions.extend(self.arm_stall_timer(slot));
+                if let Some((chosen_v, stall_timer)) = self.record_accepted(from, slot, pid, proposal.value) {
+                    actions.extend(self.relay_to.iter().map(|&to| Action::Send {
+                        to,
+                        from: self.node_id,
+                        msg: PaxosMsg::Learn { slot, proposal_id: pid, value: chosen_v.clone() },
+                    }));
+                    actions.extend(stall_timer.map(|id| Action::CancelTimer { id }));
+                    if self.relay_threshold == 0 {
+                        actions.extend(self.notify_proposer(slot, pid));
+                        actions.extend(self.chose(slot, chosen_v, request_id));
+                    }
+                }
+                actions
+            }
+            // Once a slot is decided, whichever way, a Learn for it can only
+            // confirm that (nothing to do) or contradict it. Before that, one
+            // meeting the relay threshold decides it, and any acks still
+            // coming in are ignored from then on.
+            PaxosMsg::Learn { slot, value, .. } if self.chosen.contains(slot) => {
+                if self.chosen.get(slot) == Some(&value) {
+                    return vec![];
+                }
+                vec![Action::Error { error: PaxosError::ConflictingLearn { node: from, slot } }]
+            }
+            PaxosMsg::Learn { slot, proposal_id, value } => {
+                let mut actions = if self.relay_threshold > 0 { self.touch_inflight(slot) } else { vec![] };
+                if let Some((v, stall_timer)) = self.record_learn(from, slot, proposal_id, value) {
+                    actions.extend(stall_timer.map(|id| Action::CancelTimer { id }));
+                    actions.extend(self.notify_proposer(slot, proposal_id));
+                    actions.extend(self.chose(slot, v, None));
+                }
+                actions
+            }
+            PaxosMsg::QueryChosen { slot } => vec![Action::Send {
+                to: from,
+                from: self.node_id,
+                msg: PaxosMsg::ChosenReply { slot, value: self.chosen.get(slot).cloned() },
+            }],
+            PaxosMsg::LearnDigest { base, up_to, bitmap } => self
+                .missing_ranges(base, up_to, &bitmap)
+                .into_iter()
+                .map(|(start, end)| Action::Send { to: from, from: self.node_id, msg: PaxosMsg::CatchUpRequest { start, end } })
+                .collect(),
+            PaxosMsg::CatchUpRequest { start, end } => {
+                let limit = if self.catch_up_max_entries > 0 { self.catch_up_max_entries } else { usize::MAX };
+                let mut entries = Vec::new();
+                let mut next = None;
+                for (slot, v) in self.chosen.range(start).take_while(|(s, _)| *s < end) {
+                    if entries.len() == limit {
+                        next = Some(slot);
+                        break;
+                    }
+                    entries.push((slot, v.clone()));
+                }
+                vec![Action::Send { to: from, from: self.node_id, msg: PaxosMsg::CatchUpResponse { entries, next, end } }]
+            }
+            PaxosMsg::CatchUpResponse { entries, next, end } => {
+                // Ask for the next page before taking this one in
+                let mut actions: Vec<Action<V>> = next
+                    .map(|start| Action::Send { to: from, from: self.node_id, msg: PaxosMsg::CatchUpRequest { start, end } })
+                    .into_iter()
+                    .collect();
+                for (slot, v) in entries {
+                    // A peer learner's word, as if its relay met our threshold
+                    if let Some(stall_timer) = self.settle(slot, v.clone(), None) {
+                        actions.extend(stall_timer.map(|id| Action::CancelTimer { id }));
+                        actions.extend(self.chose(slot, v, None));
+                    }
+                }
+                actions
+            }
+            PaxosMsg::AcceptProposal { slot, value, .. } => {
+                if let Some(seen) = &mut self.proposed {
+                    seen.insert((slot, value));
                 }
                 vec![]
             }
             _ => vec![],
         }
     }
-    fn on_timeout(&mut self, _id: TimerId) -> Vec<Action<V>> {
-        vec![]
+    fn on_timeout(&mut self, id: TimerId) -> Vec<Action<V>> {
+        let TimerKind::LearnStalled(slot) = id.kind else { return vec![] };
+        if self.stall_timers.get(&slot) != Some(&id) {
+            return vec![]; // stale
+        }
+        self.stall_timers.remove(&slot);
+        vec![Action::LearnStalled { slot }]
+    }
+}
+
+/// Watches `Learn` relays from a set of learners and reports a slot as
+/// `WidelyLearned` once a majority of them have decided it, for applications
+/// that want a decision to survive losing the acceptors' view of it.
+pub struct LearnerGroup {
+    learners: HashSet<NodeId>,
+    reported: HashMap<Slot, HashSet<NodeId>>,
+    widely_learned: HashSet<Slot>,
+}
+
+impl LearnerGroup {
+    pub fn new(learners: impl IntoIterator<Item = NodeId>) -> Self {
+        Self {
+            learners: learners.into_iter().collect(),
+            reported: HashMap::new(),
+            widely_learned: HashSet::new(),
+        }
+    }
+    pub fn is_widely_learned(&self, slot: Slot) -> bool {
+        self.widely_learned.contains(&slot)
+    }
+    fn record_learn(&mut self, from: NodeId, slot: Slot) -> bool {
+        if !self.learners.contains(&from) || self.widely_learned.contains(&slot) {
+            return false;
+        }
+        let entry = self.reported.entry(slot).or_default();
+        entry.insert(from);
+        if entry.len() > self.learners.len() / 2 {
+            self.reported.remove(&slot);
+            self.widely_learned.insert(slot);
+            return true;
+        }
+        false
+    }
+}
+
+impl<V: Clone> HandlesEvents<V> for LearnerGroup {
+    fn on_message(&mut self, from: NodeId, msg: PaxosMsg<V>) -> Vec<Action<V>> {
+        match msg {
+            PaxosMsg::Learn { slot, .. } if self.record_learn(from, slot) => {
+                vec![Action::WidelyLearned { slot }]
+            }
+            _ => vec![],
+        }
     }
 }
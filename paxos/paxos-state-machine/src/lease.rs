@@ -0,0 +1,28 @@
+// Leader lease over virtual time.
+//
+// Whoever grants the lease (election, an external coordinator, ...) promises
+// that no other node will act as leader before `expires_at_ms`. That only
+// holds with bounded clock drift, so callers should grant a duration that
+// leaves room for it.
+use crate::types::NodeId;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub holder: NodeId,
+    pub expires_at_ms: u64,
+}
+
+impl Lease {
+    pub fn new(holder: NodeId, now_ms: u64, duration_ms: u64) -> Self {
+        Self { holder, expires_at_ms: now_ms.saturating_add(duration_ms) }
+    }
+
+    pub fn is_valid(&self, now_ms: u64) -> bool {
+        now_ms < self.expires_at_ms
+    }
+
+    /// Push the expiry out, never back.
+    pub fn renew(&mut self, now_ms: u64, duration_ms: u64) {
+        self.expires_at_ms = self.expires_at_ms.max(now_ms.saturating_add(duration_ms));
+    }
+}
@@ -1,6 +1,25 @@
 // Library root for paxos-state-machine
-mod types;
-mod msg;
-mod proposer;
-mod acceptor;
-mod learner;
\ No newline at end of file
+pub mod types;
+pub mod error;
+pub mod config;
+pub mod node_set;
+pub mod msg;
+pub mod proposer;
+pub mod acceptor;
+pub mod learner;
+pub mod apply;
+pub mod log_store;
+pub mod wire;
+pub mod transport;
+pub mod failure_detector;
+pub mod election;
+pub mod lease;
+pub mod node;
+pub mod sim;
+pub mod cluster;
+pub mod topology;
+pub mod client;
+pub mod middleware;
+pub mod rng;
+pub mod conformance;
+pub mod dueling;
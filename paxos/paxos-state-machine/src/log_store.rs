@@ -0,0 +1,121 @@
+// Slot-keyed logs. The learner's chosen values and the acceptor's accepted
+// proposals are both one, so a storage backend implements LogStore once and
+// either role can run on it.
+use std::collections::BTreeMap;
+
+use crate::types::Slot;
+
+pub trait LogStore<V> {
+    fn get(&self, slot: Slot) -> Option<&V>;
+    /// Overwrites whatever `slot` held.
+    fn put(&mut self, slot: Slot, v: V);
+    /// Entries at `from` and above, in slot order.
+    fn range(&self, from: Slot) -> Box<dyn Iterator<Item = (Slot, &V)> + '_>;
+    fn clear(&mut self);
+
+    fn contains(&self, slot: Slot) -> bool {
+        self.get(slot).is_some()
+    }
+
+    /// Highest slot with an entry.
+    fn last_slot(&self) -> Option<Slot> {
+        self.range(0).last().map(|(slot, _)| slot)
+    }
+}
+
+/// The in-memory log both roles use unless given another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemLog<V> {
+    entries: BTreeMap<Slot, V>,
+}
+
+impl<V> Default for MemLog<V> {
+    fn default() -> Self {
+        Self { entries: BTreeMap::new() }
+    }
+}
+
+impl<V> MemLog<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<V> LogStore<V> for MemLog<V> {
+    fn get(&self, slot: Slot) -> Option<&V> {
+        self.entries.get(&slot)
+    }
+
+    fn put(&mut self, slot: Slot, v: V) {
+        self.entries.insert(slot, v);
+    }
+
+    fn range(&self, from: Slot) -> Box<dyn Iterator<Item = (Slot, &V)> + '_> {
+        Box::new(self.entries.range(from..).map(|(slot, v)| (*slot, v)))
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn last_slot(&self) -> Option<Slot> {
+        self.entries.keys().next_back().copied()
+    }
+}
+
+// What a role holds: a MemLog, unless a backend was plugged in
+pub(crate) struct RoleLog<V> {
+    mem: MemLog<V>,
+    backend: Option<Box<dyn LogStore<V> + Send>>,
+}
+
+impl<V> RoleLog<V> {
+    pub(crate) fn new() -> Self {
+        Self { mem: MemLog::new(), backend: None }
+    }
+
+    pub(crate) fn set_backend(&mut self, store: Box<dyn LogStore<V> + Send>) {
+        self.mem.clear();
+        self.backend = Some(store);
+    }
+
+    fn store(&self) -> &dyn LogStore<V> {
+        match &self.backend {
+            Some(store) => store.as_ref(),
+            None => &self.mem,
+        }
+    }
+
+    fn store_mut(&mut self) -> &mut dyn LogStore<V> {
+        match &mut self.backend {
+            Some(store) => store.as_mut(),
+            None => &mut self.mem,
+        }
+    }
+}
+
+impl<V> LogStore<V> for RoleLog<V> {
+    fn get(&self, slot: Slot) -> Option<&V> {
+        self.store().get(slot)
+    }
+
+    fn put(&mut self, slot: Slot, v: V) {
+        self.store_mut().put(slot, v)
+    }
+
+    fn range(&self, from: Slot) -> Box<dyn Iterator<Item = (Slot, &V)> + '_> {
+        self.store().range(from)
+    }
+
+    fn clear(&mut self) {
+        self.store_mut().clear()
+    }
+
+    fn contains(&self, slot: Slot) -> bool {
+        self.store().contains(slot)
+    }
+
+    fn last_slot(&self) -> Option<Slot> {
+        self.store().last_slot()
+    }
+}
@@ -0,0 +1,75 @@
+// Post-processing of a role's output without touching the role.
+//
+// Wrap any `HandlesEvents` in `WithMiddleware` and every batch of actions it
+// returns goes through the middleware first: count them, trace them, drop
+// or rewrite them.
+use crate::{msg::PaxosMsg, types::*};
+
+pub trait Middleware<V> {
+    fn intercept(&mut self, actions: Vec<Action<V>>) -> Vec<Action<V>>;
+}
+
+/// Plain closures work as middleware.
+impl<V, F> Middleware<V> for F
+where
+    F: FnMut(Vec<Action<V>>) -> Vec<Action<V>>,
+{
+    fn intercept(&mut self, actions: Vec<Action<V>>) -> Vec<Action<V>> {
+        self(actions)
+    }
+}
+
+pub struct WithMiddleware<R, M> {
+    inner: R,
+    middleware: M,
+}
+
+impl<R, M> WithMiddleware<R, M> {
+    pub fn new(inner: R, middleware: M) -> Self {
+        Self { inner, middleware }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn middleware(&self) -> &M {
+        &self.middleware
+    }
+
+    pub fn into_parts(self) -> (R, M) {
+        (self.inner, self.middleware)
+    }
+}
+
+impl<V, R, M> HandlesEvents<V> for WithMiddleware<R, M>
+where
+    V: Clone,
+    R: HandlesEvents<V>,
+    M: Middleware<V>,
+{
+    fn on_init(&mut self) -> Vec<Action<V>> {
+        let actions = self.inner.on_init();
+        self.middleware.intercept(actions)
+    }
+
+    fn on_message(&mut self, from: NodeId, msg: PaxosMsg<V>) -> Vec<Action<V>> {
+        let actions = self.inner.on_message(from, msg);
+        self.middleware.intercept(actions)
+    }
+
+    fn on_timeout(&mut self, id: TimerId) -> Vec<Action<V>> {
+        let actions = self.inner.on_timeout(id);
+        self.middleware.intercept(actions)
+    }
+
+    // Go through the inner dispatcher in case it has its own.
+    fn on_event(&mut self, e: Event<V>) -> Vec<Action<V>> {
+        let actions = self.inner.on_event(e);
+        self.middleware.intercept(actions)
+    }
+}
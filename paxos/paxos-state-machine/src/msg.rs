@@ -1,10 +1,53 @@
 use crate::types::*;
 use crate::proposer::*;
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PaxosMsg<V> {
-    Prepare { proposal_id: ProposalId, from: NodeId },
-    Promise { accepted_proposal: Option<Proposal<V>>, proposal_response: ProposalId},
-    AcceptProposal { proposal_id: ProposalId, value: V },
-    Accepted { proposal: Proposal<V> },
-    Learn { proposal_id: ProposalId, value: V}
+    Prepare { slot: Slot, proposal_id: ProposalId, from: NodeId },
+    Promise { slot: Slot, accepted_proposal: Option<Proposal<V>>, proposal_response: ProposalId},
+    /// `committed_up_to`: the proposer knows every slot up to and including
+    /// it is decided, piggybacked so no separate commit message is needed.
+    /// `request_id`: the client request behind the round, for tracing; the
+    /// acceptor echoes it on its Accepted.
+    AcceptProposal { slot: Slot, proposal_id: ProposalId, value: V, committed_up_to: Option<Slot>, request_id: Option<u64> },
+    /// Acceptor -> proposer and learners: `proposal` was accepted in `slot`.
+    Accepted { slot: Slot, proposal: Proposal<V>, request_id: Option<u64> },
+    /// Learner relay of a value it already knows is chosen.
+    Learn { slot: Slot, proposal_id: ProposalId, value: V},
+    /// Acceptor -> proposer: `proposal_id` was refused, `promised` outranks it.
+    Nack { slot: Slot, proposal_id: ProposalId, promised: ProposalId },
+    /// Client -> learner: has anything been chosen in `slot`?
+    QueryChosen { slot: Slot },
+    /// Learner -> client: what we know was chosen in `slot`, if anything.
+    ChosenReply { slot: Slot, value: Option<V> },
+    /// Promise from an acceptor in lazy mode: only the id of what it
+    /// accepted in `slot`, the value is fetched if the proposer needs it.
+    PromiseIdOnly { slot: Slot, accepted_id: Option<ProposalId>, proposal_response: ProposalId },
+    /// Proposer -> acceptor: send me the value you accepted as `proposal_id`.
+    FetchAccepted { slot: Slot, proposal_id: ProposalId },
+    /// Acceptor -> proposer: answer to `FetchAccepted`.
+    AcceptedValue { slot: Slot, proposal: Proposal<V> },
+    /// Learner gossip: every slot below `base` is chosen here, and so is
+    /// `base + i` for each bit `i` set in `bitmap` (LSB first), for every
+    /// `base + i < up_to`.
+    LearnDigest { base: Slot, up_to: Slot, bitmap: Vec<u8> },
+    /// Learner -> learner: send me what you chose in `start..end`.
+    CatchUpRequest { start: Slot, end: Slot },
+    /// Answer to `CatchUpRequest`, in slot order. If it had to stop short,
+    /// `next` is where the rest of the requested range (up to `end`) starts.
+    CatchUpResponse { entries: Vec<(Slot, V)>, next: Option<Slot>, end: Slot },
+    /// Learner -> proposer: your `proposal_id` was chosen in `slot`.
+    Chosen { slot: Slot, proposal_id: ProposalId },
+    /// Promise from an acceptor that has accepted nothing in `slot`, the
+    /// usual case on a fresh cluster; saves the empty option on the wire.
+    PromiseEmpty { slot: Slot, proposal_response: ProposalId },
+    /// Outgoing leader -> its chosen successor: the highest round it got
+    /// to, so the successor's first Prepare outbids it instead of dueling.
+    LeaderHandoff { to: NodeId, round: u64 },
+    /// Proposer -> acceptors: Phase 1 for every slot from `from_slot` on at
+    /// once, so a leader can go straight to AcceptProposal in each of them.
+    PrepareFrom { from_slot: Slot, proposal_id: ProposalId, from: NodeId },
+    /// Acceptor -> proposer: promise for a PrepareFrom, with everything we
+    /// accepted in `from_slot` and above, in slot order.
+    PromiseFrom { from_slot: Slot, proposal_response: ProposalId, accepted: Vec<(Slot, Proposal<V>)> },
 }
\ No newline at end of file
@@ -0,0 +1,362 @@
+// A node running all three roles side by side, the usual deployment.
+//
+// Inbound messages are routed to the role that handles them, and the learner's
+// decisions are folded into `applied`, the gap-free prefix of the log.
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::{
+    acceptor::{Acceptor, AcceptorMetrics}, config::PaxosConfig, error::PaxosError, learner::{Learner, LearnerStats}, lease::Lease, msg::PaxosMsg,
+    proposer::{BuildError, Proposer, ProposerMetrics}, types::*,
+};
+
+/// Answer to a read that skips the consensus round.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReadOutcome<V> {
+    /// Our lease holds; this is the applied log as of now.
+    Ok(Vec<V>),
+    /// Someone else holds the lease (if we know who); ask them.
+    NotLeader { leader: Option<NodeId> },
+    /// We were the leader but the lease ran out; retry once it's renewed.
+    LeaseExpired,
+    /// The read's token is ahead of what we've applied; retry once
+    /// `applied()` has caught up to `wanted`.
+    Behind { applied: Slot, wanted: Slot },
+}
+
+/// What a client presents on a read so it sees its own writes: the read
+/// isn't answered until the serving node has applied everything below the
+/// token. Tokens only go up; keep the highest one seen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CommitToken(pub Slot);
+
+impl CommitToken {
+    /// Covers a write chosen in `slot`, and everything before it.
+    pub fn for_slot(slot: Slot) -> Self {
+        CommitToken(slot.saturating_add(1))
+    }
+}
+
+/// Every role's counters in one snapshot; see `Node::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NodeStats {
+    pub proposer: ProposerMetrics,
+    pub acceptor: AcceptorMetrics,
+    pub learner: LearnerStats,
+    /// Length of the gap-free applied prefix.
+    pub applied: u64,
+}
+
+/// Which of a `Node`'s roles an inbound message is for; see `Node::route`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Route {
+    Proposer,
+    Acceptor,
+    Learner,
+    /// An accept ack both the proposer and the learner count.
+    ProposerAndLearner,
+    /// Only a client has any use for it.
+    Client,
+}
+
+pub struct Node<V> {
+    node_id: NodeId,
+    proposer: Proposer<V>,
+    acceptor: Acceptor<V>,
+    learner: Learner<V>,
+    applied: Vec<V>,
+    lease: Option<Lease>,
+    // Check our proposer's accepts against what our learner has chosen
+    tripwire: bool,
+}
+
+impl<V> Node<V>
+where
+    V: Clone + Eq + Hash,
+{
+    /// `peers` are the acceptors our proposer talks to, `learners` the nodes
+    /// our acceptor reports to. `quorum` is used for both phases and by our
+    /// learner.
+    pub fn new(node_id: NodeId, ctx: NodeContext, peers: Vec<NodeId>, learners: HashSet<NodeId>, candidate_value: V, timer_ms: u64, quorum: usize) -> Result<Self, BuildError> {
+        Ok(Self {
+            node_id,
+            proposer: Proposer::new(node_id, ctx.clone(), peers, candidate_value, timer_ms, quorum)?,
+            acceptor: Acceptor::new(node_id, ctx.clone(), learners),
+            learner: Learner::new(node_id, ctx, quorum),
+            applied: Vec::new(),
+            lease: None,
+            tripwire: false,
+        })
+    }
+
+    /// `new`, with every role set up from `config`.
+    pub fn with_config(node_id: NodeId, ctx: NodeContext, peers: Vec<NodeId>, learners: HashSet<NodeId>, candidate_value: V, config: &PaxosConfig) -> Result<Self, BuildError> {
+        Ok(Self {
+            node_id,
+            proposer: Proposer::with_config(node_id, ctx.clone(), peers, candidate_value, config)?,
+            acceptor: Acceptor::with_config(node_id, ctx.clone(), learners, config),
+            learner: Learner::with_config(node_id, ctx, config),
+            applied: Vec::new(),
+            lease: None,
+            tripwire: false,
+        })
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    pub fn proposer(&self) -> &Proposer<V> {
+        &self.proposer
+    }
+
+    pub fn proposer_mut(&mut self) -> &mut Proposer<V> {
+        &mut self.proposer
+    }
+
+    pub fn acceptor(&self) -> &Acceptor<V> {
+        &self.acceptor
+    }
+
+    pub fn learner(&self) -> &Learner<V> {
+        &self.learner
+    }
+
+    pub fn learner_mut(&mut self) -> &mut Learner<V> {
+        &mut self.learner
+    }
+
+    /// Chosen values for slots 0..n with no gaps.
+    pub fn applied(&self) -> &[V] {
+        &self.applied
+    }
+
+    /// All three roles' metrics at once, e.g. for a metrics endpoint.
+    pub fn stats(&self) -> NodeStats {
+        NodeStats {
+            proposer: self.proposer.metrics(),
+            acceptor: self.acceptor.metrics(),
+            learner: self.learner.stats(),
+            applied: self.applied.len() as u64,
+        }
+    }
+
+    pub fn set_lease(&mut self, lease: Lease) {
+        self.lease = Some(lease);
+    }
+
+    /// Safety tripwire: check every AcceptProposal our proposer sends
+    /// against what our learner knows to be chosen. One carrying another
+    /// value for a decided slot can only be a bug, so it's dropped before it
+    /// reaches any acceptor, ours included, and reported once per slot as
+    /// `PaxosError::ProposedOverChosen`. Off by default; it only knows what
+    /// our learner has learned.
+    pub fn set_safety_tripwire(&mut self, on: bool) {
+        self.tripwire = on;
+    }
+
+    pub fn lease(&self) -> Option<Lease> {
+        self.lease
+    }
+
+    /// Serve a read locally, without a round, if we hold a valid lease.
+    /// While it holds no other node can get a write chosen, so our applied
+    /// log is current provided we ack client writes only once applied.
+    pub fn linearizable_read(&self, now_ms: u64) -> ReadOutcome<V> {
+        match self.lease {
+            None => ReadOutcome::NotLeader { leader: None },
+            Some(l) if l.holder != self.node_id => ReadOutcome::NotLeader { leader: Some(l.holder) },
+            Some(l) if !l.is_valid(now_ms) => ReadOutcome::LeaseExpired,
+            Some(_) => ReadOutcome::Ok(self.applied.clone()),
+        }
+    }
+
+    /// `linearizable_read`, once our applied log covers `token`: until then
+    /// it's `Behind`, so a client never reads state older than its writes.
+    pub fn linearizable_read_after(&self, now_ms: u64, token: CommitToken) -> ReadOutcome<V> {
+        let applied = self.applied.len() as Slot;
+        match self.linearizable_read(now_ms) {
+            ReadOutcome::Ok(_) if applied < token.0 => ReadOutcome::Behind { applied, wanted: token.0 },
+            outcome => outcome,
+        }
+    }
+
+    /// Token for everything we've applied so far, to hand a client whose
+    /// write is among it.
+    pub fn commit_token(&self) -> CommitToken {
+        CommitToken(self.applied.len() as Slot)
+    }
+
+    /// Start proposing `v` in `slot`; `Proposer::propose` plus our own
+    /// acceptor's vote, see `self_vote`.
+    pub fn propose(&mut self, slot: Slot, v: V) -> Vec<Action<V>> {
+        let actions = self.proposer.propose(slot, v);
+        self.self_vote(actions)
+    }
+
+    /// `Proposer::lead_from`, with our own acceptor's promise among the
+    /// quorum; see `self_vote`.
+    pub fn lead_from(&mut self, from_slot: Slot) -> Vec<Action<V>> {
+        let actions = self.proposer.lead_from(from_slot);
+        self.self_vote(actions)
+    }
+
+    /// Teardown before dropping the node: cancels every timer its roles
+    /// still have live, so the host's timer wheel is left clean, and parks
+    /// the proposer. The acceptor has nothing pending; write out
+    /// `acceptor().persisted()` if it needs to survive.
+    pub fn shutdown(&mut self) -> Vec<Action<V>> {
+        let mut actions = self.proposer.step_down();
+        actions.extend(self.learner.cancel_stall_timers());
+        actions
+    }
+
+    /// `propose_slot` into the first slot that neither our learner nor our
+    /// proposer knows to be decided, and that our learner isn't seeing votes
+    /// for (another proposer got there first), and which slot that was.
+    /// Skipping those only saves a round that would likely lose; proposing
+    /// into one would be safe regardless. If that other proposer dies
+    /// mid-round, its slot stays a gap until a leader's `recover` fills it.
+    pub fn propose_next_gap(&mut self, v: V) -> (Slot, Vec<Action<V>>) {
+        let slot = (self.learner.first_gap()..)
+            .find(|s| self.learner.get_chosen(*s).is_none() && !self.learner.is_in_flight(*s) && !self.proposer.is_decided(*s))
+            .unwrap_or(Slot::MAX);
+        let actions = self.proposer.propose_slot(slot, v);
+        (slot, self.self_vote(actions))
+    }
+
+    /// If our own acceptor isn't among the proposer's peers, nothing on the
+    /// wire counts it, so hand it the proposer's Prepares and AcceptProposals
+    /// directly and take its answers back in-process: our own promise and
+    /// accept then count toward the quorums like remote ones.
+    fn self_vote(&mut self, actions: Vec<Action<V>>) -> Vec<Action<V>> {
+        let actions = self.trip(actions);
+        if self.proposer.peers().contains(&self.node_id) {
+            return actions;
+        }
+        let mut local: Vec<PaxosMsg<V>> = vec![];
+        for a in &actions {
+            if let Action::Send { msg: msg @ (PaxosMsg::Prepare { .. } | PaxosMsg::PrepareFrom { .. } | PaxosMsg::AcceptProposal { .. }), .. } = a {
+                if !local.contains(msg) {
+                    local.push(msg.clone());
+                }
+            }
+        }
+        let mut out = actions;
+        for msg in local {
+            for a in self.acceptor.on_message(self.node_id, msg) {
+                match a {
+                    Action::Send { to, msg, .. } if to == self.node_id => out.extend(self.on_message(self.node_id, msg)),
+                    a => out.push(a),
+                }
+            }
+        }
+        out
+    }
+
+    // See set_safety_tripwire
+    fn trip(&self, actions: Vec<Action<V>>) -> Vec<Action<V>> {
+        if !self.tripwire {
+            return actions;
+        }
+        let mut tripped: Vec<Slot> = vec![];
+        let mut out: Vec<Action<V>> = actions
+            .into_iter()
+            .filter(|a| match a {
+                Action::Send { msg: PaxosMsg::AcceptProposal { slot, value, .. }, .. }
+                    if self.learner.get_chosen(*slot).is_some_and(|chosen| chosen != value) =>
+                {
+                    if !tripped.contains(slot) {
+                        tripped.push(*slot);
+                    }
+                    false
+                }
+                _ => true,
+            })
+            .collect();
+        out.extend(tripped.into_iter().map(|slot| Action::Error { error: PaxosError::ProposedOverChosen { slot } }));
+        out
+    }
+
+    /// Where `on_message` sends `msg`. Exhaustive on purpose, so a new
+    /// message can't be added without deciding who gets it. Whatever our own
+    /// acceptor broadcast and hears back (Accepted, Learn) goes to the learner
+    /// side; the acceptor only ever sees requests from proposers.
+    pub fn route(msg: &PaxosMsg<V>) -> Route {
+        match msg {
+            // Proposer requests
+            PaxosMsg::Prepare { .. } | PaxosMsg::PrepareFrom { .. } | PaxosMsg::AcceptProposal { .. } | PaxosMsg::FetchAccepted { .. } => Route::Acceptor,
+            // Acceptor answers, plus a learner telling us we won
+            PaxosMsg::Promise { .. }
+            | PaxosMsg::PromiseIdOnly { .. }
+            | PaxosMsg::PromiseEmpty { .. }
+            | PaxosMsg::PromiseFrom { .. }
+            | PaxosMsg::AcceptedValue { .. }
+            | PaxosMsg::Nack { .. }
+            | PaxosMsg::Chosen { .. }
+            | PaxosMsg::LeaderHandoff { .. } => Route::Proposer,
+            PaxosMsg::Accepted { .. } => Route::ProposerAndLearner,
+            // Relays, gossip, catch-up and client queries
+            PaxosMsg::Learn { .. }
+            | PaxosMsg::CatchUpResponse { .. }
+            | PaxosMsg::QueryChosen { .. }
+            | PaxosMsg::LearnDigest { .. }
+            | PaxosMsg::CatchUpRequest { .. } => Route::Learner,
+            // The answer to a client's QueryChosen
+            PaxosMsg::ChosenReply { .. } => Route::Client,
+        }
+    }
+
+    fn apply_chosen(&mut self) {
+        while let Some(v) = self.learner.get_chosen(self.applied.len() as Slot) {
+            self.applied.push(v.clone());
+        }
+    }
+}
+
+impl<V> HandlesEvents<V> for Node<V>
+where
+    V: Clone + Eq + Hash,
+{
+    fn on_init(&mut self) -> Vec<Action<V>> {
+        let actions = self.proposer.on_init();
+        self.self_vote(actions)
+    }
+
+    fn on_message(&mut self, from: NodeId, msg: PaxosMsg<V>) -> Vec<Action<V>> {
+        match Self::route(&msg) {
+            Route::Acceptor => self.acceptor.on_message(from, msg),
+            Route::Proposer => {
+                let actions = self.proposer.on_message(from, msg);
+                self.self_vote(actions)
+            }
+            Route::ProposerAndLearner => {
+                // The proposer counts it as an accept ack, the learner as a
+                // vote. Whatever the proposer sends in reply reaches our own
+                // acceptor like any other proposer output.
+                let actions = self.proposer.on_message(from, msg.clone());
+                let mut actions = self.self_vote(actions);
+                actions.extend(self.learner.on_message(from, msg));
+                self.apply_chosen();
+                actions
+            }
+            Route::Learner => {
+                let actions = self.learner.on_message(from, msg);
+                self.apply_chosen();
+                actions
+            }
+            // Meant for a client's ChosenQuery, not for us
+            Route::Client => vec![],
+        }
+    }
+
+    fn on_timeout(&mut self, id: TimerId) -> Vec<Action<V>> {
+        match id.kind {
+            TimerKind::LearnStalled(_) => self.learner.on_timeout(id),
+            TimerKind::Prepare(_) | TimerKind::Accept(_) | TimerKind::Watchdog | TimerKind::Startup => {
+                let actions = self.proposer.on_timeout(id);
+                self.self_vote(actions)
+            }
+        }
+    }
+}
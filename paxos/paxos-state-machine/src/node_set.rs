@@ -0,0 +1,131 @@
+// Small set of NodeIds, for the per-round and per-slot vote tallies.
+//
+// The tallies are created and thrown away for every round of every slot, so
+// with small clusters a bitmask beats allocating a HashSet each time. The
+// bitmask covers ids 0..128; inserting anything larger quietly switches that
+// set over to a HashSet, so the backend never changes the answers.
+use std::collections::{hash_set, HashSet};
+
+use crate::types::NodeId;
+
+/// How `NodeSet`s are backed; see `NodeContext::set_backend`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SetBackend {
+    #[default]
+    Hash,
+    /// `u128` mask indexed by NodeId. Worth it when ids are small.
+    Bits,
+}
+
+#[derive(Clone, Debug)]
+pub enum NodeSet {
+    Hash(HashSet<NodeId>),
+    Bits(u128),
+}
+
+impl Default for NodeSet {
+    fn default() -> Self {
+        NodeSet::new(SetBackend::Hash)
+    }
+}
+
+impl NodeSet {
+    pub fn new(backend: SetBackend) -> Self {
+        match backend {
+            SetBackend::Hash => NodeSet::Hash(HashSet::new()),
+            SetBackend::Bits => NodeSet::Bits(0),
+        }
+    }
+
+    /// True if `node` wasn't in the set already.
+    pub fn insert(&mut self, node: NodeId) -> bool {
+        match self {
+            NodeSet::Bits(mask) if node < 128 => {
+                let bit = 1u128 << node;
+                let fresh = *mask & bit == 0;
+                *mask |= bit;
+                fresh
+            }
+            NodeSet::Bits(_) => {
+                let mut set: HashSet<NodeId> = self.iter().collect();
+                let fresh = set.insert(node);
+                *self = NodeSet::Hash(set);
+                fresh
+            }
+            NodeSet::Hash(set) => set.insert(node),
+        }
+    }
+
+    /// True if `node` was in the set.
+    pub fn remove(&mut self, node: NodeId) -> bool {
+        match self {
+            NodeSet::Bits(mask) => {
+                let present = node < 128 && *mask & (1u128 << node) != 0;
+                if present {
+                    *mask &= !(1u128 << node);
+                }
+                present
+            }
+            NodeSet::Hash(set) => set.remove(&node),
+        }
+    }
+
+    pub fn contains(&self, node: NodeId) -> bool {
+        match self {
+            NodeSet::Bits(mask) => node < 128 && mask & (1u128 << node) != 0,
+            NodeSet::Hash(set) => set.contains(&node),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            NodeSet::Bits(mask) => mask.count_ones() as usize,
+            NodeSet::Hash(set) => set.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// In no particular order.
+    pub fn iter(&self) -> Iter<'_> {
+        match self {
+            NodeSet::Bits(mask) => Iter::Bits(*mask),
+            NodeSet::Hash(set) => Iter::Hash(set.iter()),
+        }
+    }
+}
+
+impl From<HashSet<NodeId>> for NodeSet {
+    fn from(set: HashSet<NodeId>) -> Self {
+        NodeSet::Hash(set)
+    }
+}
+
+impl FromIterator<NodeId> for NodeSet {
+    fn from_iter<I: IntoIterator<Item = NodeId>>(iter: I) -> Self {
+        NodeSet::Hash(iter.into_iter().collect())
+    }
+}
+
+pub enum Iter<'a> {
+    Bits(u128),
+    Hash(hash_set::Iter<'a, NodeId>),
+}
+
+impl Iterator for Iter<'_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        match self {
+            Iter::Bits(0) => None,
+            Iter::Bits(mask) => {
+                let node = mask.trailing_zeros() as NodeId;
+                *mask &= *mask - 1;
+                Some(node)
+            }
+            Iter::Hash(it) => it.next().copied(),
+        }
+    }
+}
@@ -1,40 +1,302 @@
-use crate::{types::*, msg::PaxosMsg};
-use std::collections::HashSet;
+use crate::{types::*, msg::PaxosMsg, failure_detector::FailureDetector, node_set::NodeSet, error::PaxosError, rng::Rng, config::PaxosConfig};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
 
-#[derive(Clone)]
+/// A value tagged with the id it was proposed under.
+///
+/// Equality, ordering and hashing look at the id only: two proposals with
+/// the same id are the same proposal whatever their values. Compare
+/// `value()`s explicitly if that's what you mean.
+#[derive(Clone, Debug)]
 pub struct Proposal<V> {
     pub id: ProposalId,
     pub value: V,
 }
 
+impl<V> Proposal<V> {
+    pub fn new(id: ProposalId, value: V) -> Self {
+        Self { id, value }
+    }
+
+    pub fn id(&self) -> ProposalId {
+        self.id
+    }
+
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    pub fn into_value(self) -> V {
+        self.value
+    }
+
+    /// Same id, different value.
+    pub fn with_value<U>(&self, value: U) -> Proposal<U> {
+        Proposal { id: self.id, value }
+    }
+
+    /// Transform the value, keeping the id.
+    pub fn map<U>(self, f: impl FnOnce(V) -> U) -> Proposal<U> {
+        Proposal { id: self.id, value: f(self.value) }
+    }
+}
+
 // Eq/Ord/Hash by id
 impl<V> PartialEq for Proposal<V> { fn eq(&self, o: &Self) -> bool { self.id == o.id } }
 impl<V> Eq for Proposal<V> {}
 impl<V> std::hash::Hash for Proposal<V> { fn hash<H: std::hash::Hasher>(&self, s: &mut H) { self.id.hash(s); } }
-impl<V> PartialOrd for Proposal<V> { fn partial_cmp(&self, o: &Self) -> Option<std::cmp::Ordering> { Some(self.id.cmp(&o.id)) } }
+impl<V> PartialOrd for Proposal<V> { fn partial_cmp(&self, o: &Self) -> Option<std::cmp::Ordering> { Some(self.cmp(o)) } }
 impl<V> Ord for Proposal<V> { fn cmp(&self, o: &Self) -> std::cmp::Ordering { self.id.cmp(&o.id) } }
 
-/// Single, compact state for the current proposer round.
+/// Of two possibly-missing accepted proposals, the one with the higher id:
+/// Phase 1's recovery rule, which value a new round has to adopt. On equal
+/// ids `a` is kept; they must be the same proposal, which debug builds check.
+pub fn merge_highest<V: PartialEq + fmt::Debug>(a: Option<Proposal<V>>, b: Option<Proposal<V>>) -> Option<Proposal<V>> {
+    if let (Some(a), Some(b)) = (&a, &b) {
+        debug_assert!(a.id != b.id || a.value == b.value, "{a:?} and {b:?} share an id");
+    }
+    highest(a, b)
+}
+
+// merge_highest without the value check, for callers that can't compare Vs
+fn highest<V>(a: Option<Proposal<V>>, b: Option<Proposal<V>>) -> Option<Proposal<V>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.id > a.id { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// How the proposer's phase timer grows across retries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackoffConfig {
+    /// Timeout for an uncontended round; doubled on every timeout.
+    pub base_ms: u64,
+    /// Doubling stops here, so a long contended stretch can't push the
+    /// timer out to where it never fires.
+    pub max_ms: u64,
+    /// Drop back to `base_ms` once a promise quorum is reached, so the next
+    /// round after a contended stretch starts fast again.
+    pub reset_on_success: bool,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self { base_ms: 100, max_ms: 60_000, reset_on_success: true }
+    }
+}
+
+/// Who a slot's first Prepare goes to; see `Proposer::set_broadcast_strategy`.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum BroadcastStrategy {
+    /// Every peer.
+    #[default]
+    All,
+    /// The first `quorum + k` peers, in peer order: enough to reach a
+    /// quorum with `k` slow or dead ones to spare.
+    QuorumPlusBuffer(usize),
+    /// Whichever of `peers` this picks, given them and the quorum.
+    Custom(fn(&[NodeId], usize) -> Vec<NodeId>),
+}
+
+/// Where a slot's round is; see `Proposer::debug_state`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Prepares out, collecting promises.
+    Prepare,
+    /// Promise quorum reached, AcceptProposals out.
+    Accept,
+    /// An accept quorum acked it (or we learned it was chosen).
+    Decided,
+}
+
+/// Prior accepted values a compare-and-set round may adopt as its own.
+type Acceptable<V> = Arc<dyn Fn(&V) -> bool + Send + Sync>;
+
+/// What the caller asked for in a slot. Survives round restarts.
+#[derive(Clone)]
+struct Intent<V> {
+    // Value we'd like to see chosen if nothing else was accepted before us
+    value: V,
+    // Compare-and-set: give up instead of adopting a prior accepted value
+    if_empty: bool,
+    // ...unless it's one of these, which is as good as ours
+    acceptable: Option<Acceptable<V>>,
+    // Client request behind this value, carried on our accepts for tracing
+    request_id: Option<u64>,
+    // Our own earlier rounds whose Phase 2 carried this value: a retry whose
+    // promises report one of them is looking at our value, not a rival's
+    sent_as: BTreeSet<ProposalId>,
+}
+
+impl<V> Intent<V> {
+    fn new(value: V) -> Self {
+        Self { value, if_empty: false, acceptable: None, request_id: None, sent_as: BTreeSet::new() }
+    }
+
+    fn accepts(&self, p: &Proposal<V>) -> bool {
+        self.sent_as.contains(&p.id) || self.acceptable.as_ref().is_some_and(|f| f(&p.value))
+    }
+}
+
+/// Single, compact state for the current round of one slot.
 struct RoundState<V> {
     proposal_id: ProposalId,
+    phase: Phase,
+    intent: Intent<V>,
+    // Timer guarding the current phase
+    timer: Option<TimerId>,
     // Prepare step
-    promises_from: HashSet<NodeId>,
+    promises_from: NodeSet,
     highest_accepted: Option<Proposal<V>>,
+    // Best id-only report (lazy promise) and who holds its value
+    highest_accepted_id: Option<(ProposalId, NodeId)>,
+    // Asked that acceptor for the value; not in Phase 2 until it answers
+    fetching: bool,
     // Accept step
-    accept_acks: HashSet<NodeId>,
+    accept_acks: NodeSet,
+    // Value sent in Phase 2, once we got there
+    proposed: Option<V>,
+    // Accept timeouts answered by re-sending rather than a new Phase 1
+    accept_resends: u32,
+    // Prepare and AcceptProposal broadcasts so far, resends included
+    broadcasts: u32,
+    // Adopted a no-op over the caller's value; propose it again once decided
+    requeue: bool,
+    // Highest promise an acceptor nacked us with
+    nacked_by: Option<ProposalId>,
+    // Accepts seen for other proposers' ids carrying our intent's value
+    foreign_acks: BTreeMap<ProposalId, NodeSet>,
+    // Who this round's Prepares and AcceptProposals go to
+    targets: Vec<NodeId>,
 }
 
 impl<V> RoundState<V> {
-    fn new(proposal_id: ProposalId) -> Self {
+    fn new(proposal_id: ProposalId, intent: Intent<V>, ctx: &NodeContext) -> Self {
         Self {
             proposal_id,
-            promises_from: HashSet::new(),
+            phase: Phase::Prepare,
+            intent,
+            timer: None,
+            promises_from: ctx.node_set(),
             highest_accepted: None,
-            accept_acks: HashSet::new(),
+            highest_accepted_id: None,
+            fetching: false,
+            accept_acks: ctx.node_set(),
+            proposed: None,
+            accept_resends: 0,
+            broadcasts: 0,
+            requeue: false,
+            nacked_by: None,
+            foreign_acks: BTreeMap::new(),
+            targets: Vec::new(),
+        }
+    }
+}
+
+/// A standing Phase 1 over every slot from `from_slot` on; see
+/// `Proposer::lead_from`.
+struct Leadership<V> {
+    from_slot: Slot,
+    proposal_id: ProposalId,
+    promises_from: NodeSet,
+    // Highest accepted proposal the promises reported, per slot
+    accepted: BTreeMap<Slot, Proposal<V>>,
+    // Promise quorum reached: new rounds skip straight to Phase 2
+    established: bool,
+    // Slots already given a round under `proposal_id`, which can't be reused there
+    used: BTreeSet<Slot>,
+}
+
+/// Counters bumped as the proposer handles messages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProposerMetrics {
+    /// Promises for a proposal id we aren't running (stale, or a bug).
+    pub unknown_round_promises: u64,
+    /// Timeouts whose doubled timer was clamped to `BackoffConfig::max_ms`.
+    pub backoff_capped: u64,
+    /// Prepare rounds broadcast, retries included.
+    pub rounds_started: u64,
+}
+
+/// Why a round didn't get its value chosen; see `Proposer::last_failure`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundFailure {
+    /// An acceptor had promised `by`, which outranks us: someone else is
+    /// leading, back off.
+    Preempted { by: ProposalId },
+    /// The phase timer ran out with no one refusing us: the network (or a
+    /// quorum of acceptors) is slow or down.
+    TimedOut,
+}
+
+/// Owned snapshot of a proposer for debug dumps; see `Proposer::debug_state`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProposerDebug {
+    pub node_id: NodeId,
+    pub idle: bool,
+    /// Idle because `check_quorum` found no quorum reachable.
+    pub gave_up: bool,
+    pub timer_ms: u64,
+    pub last_failure: Option<RoundFailure>,
+    /// Slot order.
+    pub rounds: Vec<RoundDebug>,
+}
+
+/// One slot's round in a `ProposerDebug`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoundDebug {
+    pub slot: Slot,
+    pub proposal_id: ProposalId,
+    pub phase: Phase,
+    pub promises: usize,
+    pub accept_acks: usize,
+    /// Prepare and AcceptProposal broadcasts this round has sent.
+    pub broadcasts: u32,
+}
+
+/// Why a proposer couldn't be constructed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    /// `peers` plus ourselves is fewer than `number_of_nodes`, so the quorum
+    /// computed from the cluster size may never be reachable.
+    InsufficientPeers { peers: usize, number_of_nodes: u64 },
+    /// No peers at all, in a cluster that isn't just us.
+    NoPeers { number_of_nodes: u64 },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::InsufficientPeers { peers, number_of_nodes } => {
+                write!(f, "{peers} peers (plus self) can't cover a cluster of {number_of_nodes}")
+            }
+            BuildError::NoPeers { number_of_nodes } => {
+                write!(f, "no peers given for a cluster of {number_of_nodes}")
+            }
         }
     }
 }
 
+impl std::error::Error for BuildError {}
+
+/// Values with a "do nothing" filler, as left behind by a recovering
+/// leader plugging log holes; see `Proposer::set_repropose_after_noop`.
+pub trait IsNoop {
+    fn is_noop(&self) -> bool;
+}
+
+/// Computes the value for an empty slot; see `Proposer::set_value_fn`.
+pub type ValueFn<V> = Box<dyn Fn(Option<&V>) -> V + Send>;
+
+/// Durable home for the proposer's round counter. `persist` is called with
+/// a round before any Prepare for it goes out, and must not return until
+/// the round is on disk. After a restart, resume above the stored round
+/// with `Proposer::start_round_with` so we never reuse one.
+pub trait RoundStore {
+    fn persist(&mut self, round: u64);
+}
+
 pub struct Proposer<V> {
     node_id: NodeId,
     ctx: NodeContext,
@@ -42,104 +304,767 @@ pub struct Proposer<V> {
     next_pid: ProposalId,
     candidate_value: V,
     quorum: usize,
-    round: Option<RoundState<V>>,
-    timer_id: TimerId,
+    rounds: BTreeMap<Slot, RoundState<V>>,
+    next_timer_seq: u64,
     timer_ms: u64,
+    backoff: BackoffConfig,
+    accept_retries: u32,
+    // 0: no limit. Otherwise the most broadcasts one round may send
+    broadcast_budget: u32,
+    value_fn: Option<ValueFn<V>>,
+    round_store: Option<Box<dyn RoundStore + Send>>,
+    // Set by set_repropose_after_noop
+    noop_check: Option<fn(&V) -> bool>,
+    // Tell acceptors our decided prefix on every AcceptProposal
+    piggyback_commits: bool,
+    broadcast: BroadcastStrategy,
+    // Set by set_detect_adopted_value
+    value_eq: Option<fn(&V, &V) -> bool>,
+    // Told to step down (e.g. not the leader), until activate
+    stepped_down: bool,
+    // check_quorum found no quorum reachable, until it finds one again.
+    // Either flag parks rounds and ignores input; we resume once both clear.
+    unreachable: bool,
+    metrics: ProposerMetrics,
+    // Report anomalies as Action::Error instead of just counting them
+    strict: bool,
+    // 0: on_init prepares straight away. Otherwise it waits this long,
+    // on startup_timer.
+    initial_delay_ms: u64,
+    startup_timer: Option<TimerId>,
+    last_failure: Option<RoundFailure>,
+    // Set by lead_from
+    leadership: Option<Leadership<V>>,
 }
 
 impl<V: Clone> Proposer<V> {
-    pub fn new(node_id: NodeId, ctx: NodeContext, peers: Vec<NodeId>, candidate_value: V, timer_ms: u64, quorum: usize) -> Self {
-        Self {
+    /// Fails if `peers` can't account for every node in `ctx` (counting
+    /// ourselves). Empty `peers` is only allowed for a single-node cluster,
+    /// where every proposal is decided on the spot. Our own id is stripped
+    /// from `peers`; see `set_loopback`.
+    pub fn new(node_id: NodeId, ctx: NodeContext, mut peers: Vec<NodeId>, candidate_value: V, timer_ms: u64, quorum: usize) -> Result<Self, BuildError> {
+        peers.retain(|p| *p != node_id);
+        if peers.is_empty() && ctx.number_of_nodes > 1 {
+            return Err(BuildError::NoPeers { number_of_nodes: ctx.number_of_nodes });
+        }
+        if (peers.len() as u64).saturating_add(1) < ctx.number_of_nodes {
+            return Err(BuildError::InsufficientPeers { peers: peers.len(), number_of_nodes: ctx.number_of_nodes });
+        }
+        Ok(Self {
             node_id,
             ctx,
             peers,
             quorum,
             next_pid: (0, node_id),
             candidate_value,
-            round: None,
-            timer_id: (0, node_id),
+            rounds: BTreeMap::new(),
+            next_timer_seq: 0,
             timer_ms,
+            backoff: BackoffConfig { base_ms: timer_ms, ..BackoffConfig::default() },
+            stepped_down: false,
+            unreachable: false,
+            metrics: ProposerMetrics::default(),
+            strict: false,
+            accept_retries: 2,
+            broadcast_budget: 0,
+            value_fn: None,
+            round_store: None,
+            noop_check: None,
+            value_eq: None,
+            piggyback_commits: false,
+            broadcast: BroadcastStrategy::All,
+            initial_delay_ms: 0,
+            startup_timer: None,
+            last_failure: None,
+            leadership: None,
+        })
+    }
+
+    /// `new`, with the quorum, timer and the rest taken from `config`.
+    pub fn with_config(node_id: NodeId, ctx: NodeContext, peers: Vec<NodeId>, candidate_value: V, config: &PaxosConfig) -> Result<Self, BuildError> {
+        let quorum = config.quorum_for(&ctx);
+        let mut proposer = Self::new(node_id, ctx, peers, candidate_value, config.backoff.base_ms, quorum)?;
+        proposer.set_backoff(config.backoff);
+        proposer.set_accept_retries(config.accept_retries);
+        proposer.set_broadcast_budget(config.broadcast_budget);
+        proposer.set_strict(config.strict);
+        proposer.set_loopback(config.loopback);
+        Ok(proposer)
+    }
+
+    pub fn context(&self) -> &NodeContext {
+        &self.ctx
+    }
+
+    /// Also send our Prepares and AcceptProposals to ourselves, for a host
+    /// that loops them back to our own acceptor over its transport, which
+    /// then counts like any other. Off by default, with us stripped from
+    /// `peers`: then our acceptor's vote only counts if the host hands it
+    /// over in-process, as `Node` does.
+    pub fn set_loopback(&mut self, on: bool) {
+        self.peers.retain(|p| *p != self.node_id);
+        if on {
+            self.peers.push(self.node_id);
         }
     }
 
-    fn quorum(&self) -> usize {
-        (self.ctx.number_of_nodes / 2 + 1) as usize
+    /// The acceptors we send Prepare and AcceptProposal to.
+    pub fn peers(&self) -> &[NodeId] {
+        &self.peers
+    }
+
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// Send the slots we know are decided (from 0 up, without gaps) along
+    /// with each AcceptProposal as `committed_up_to`. Off by default; peers
+    /// on an older wire format can't decode it.
+    pub fn set_piggyback_commits(&mut self, on: bool) {
+        self.piggyback_commits = on;
+    }
+
+    /// Send a slot's first round to only some peers (and its
+    /// AcceptProposals to the same ones), to save traffic in a big cluster.
+    /// Every later round for the slot, after a timeout, goes to all of
+    /// them. `All` by default.
+    pub fn set_broadcast_strategy(&mut self, strategy: BroadcastStrategy) {
+        self.broadcast = strategy;
+    }
+
+    /// How many times an accept timeout re-sends the same AcceptProposal
+    /// before giving up on the round and re-running Phase 1. Default 2.
+    /// Worth it when acks were merely lost: the promises still hold.
+    pub fn set_accept_retries(&mut self, retries: u32) {
+        self.accept_retries = retries;
+    }
+
+    /// Cap how many broadcasts (its Prepare, its AcceptProposal, and each
+    /// accept resend) one round may send, the first ones included. Once
+    /// spent the round sends nothing more, and its next timeout moves to a
+    /// fresh round with a higher id, whatever `set_accept_retries` allows;
+    /// the new round gets its own budget. It takes 2 for a round that runs
+    /// Phase 1 to get anything chosen, 1 only suits rounds under
+    /// `lead_from`. 0 (the default) is no limit.
+    pub fn set_broadcast_budget(&mut self, budget: u32) {
+        self.broadcast_budget = budget;
+    }
+
+    pub fn metrics(&self) -> ProposerMetrics {
+        self.metrics
+    }
+
+    /// Why our most recent failed round failed, in any slot. Stays put
+    /// across later successes; None until something fails (or `reset`).
+    pub fn last_failure(&self) -> Option<RoundFailure> {
+        self.last_failure
+    }
+
+    /// Also surface what `metrics` counts as `Action::Error`, e.g. to catch
+    /// proposers and acceptors disagreeing about ids in tests.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Hold `on_init`'s first Prepare back by a delay drawn from `1..=max_ms`,
+    /// so nodes booted together don't all contend for slot 0 at once. `seed`
+    /// is the run's; each node draws its own delay from it. A `max_ms` of 0
+    /// turns the delay off.
+    pub fn set_initial_delay(&mut self, max_ms: u64, seed: u64) {
+        self.initial_delay_ms = match max_ms {
+            0 => 0,
+            max_ms => 1 + Rng::for_node(seed, self.node_id).below(max_ms),
+        };
+    }
+
+    pub fn set_backoff(&mut self, backoff: BackoffConfig) {
+        self.backoff = backoff;
+        self.timer_ms = backoff.base_ms;
+    }
+
+    /// Read-modify-write: when a promise quorum shows the slot empty, propose
+    /// `f(prev)` instead of the fixed value, where `prev` is the value we last
+    /// saw decided in an earlier slot. A slot with a prior accepted value still
+    /// adopts it unchanged, since it may already be chosen.
+    pub fn set_value_fn(&mut self, f: impl Fn(Option<&V>) -> V + Send + 'static) {
+        self.value_fn = Some(Box::new(f));
+    }
+
+    pub fn set_round_store(&mut self, store: impl RoundStore + Send + 'static) {
+        self.round_store = Some(Box::new(store));
+    }
+
+    /// Value we got decided in the highest slot below `slot`, if any.
+    fn last_decided_before(&self, slot: Slot) -> Option<&V> {
+        self.rounds
+            .range(..slot)
+            .rev()
+            .find(|(_, r)| r.phase == Phase::Decided)
+            .and_then(|(_, r)| r.proposed.as_ref())
+    }
+
+    /// Value for a slot the promise quorum reported empty.
+    fn fresh_value(&self, slot: Slot) -> Option<V> {
+        match &self.value_fn {
+            Some(f) => Some(f(self.last_decided_before(slot))),
+            None => self.rounds.get(&slot).map(|r| r.intent.value.clone()),
+        }
+    }
+
+    /// Timeout the next phase timer will be armed with.
+    pub fn timer_ms(&self) -> u64 {
+        self.timer_ms
+    }
+
+    /// Proposal id of the live round for `slot`, if any.
+    pub fn round_id(&self, slot: Slot) -> Option<ProposalId> {
+        self.rounds.get(&slot).map(|r| r.proposal_id)
+    }
+
+    /// Peers `slot`'s live round asked that haven't promised it yet, in
+    /// peer order: who to resend a Prepare to instead of broadcasting it
+    /// again. Empty if there's no round.
+    pub fn pending_promisers(&self, slot: Slot) -> Vec<NodeId> {
+        let Some(r) = self.rounds.get(&slot) else { return vec![] };
+        r.targets.iter().copied().filter(|p| !r.promises_from.contains(*p)).collect()
+    }
+
+    /// `pending_promisers` for Phase 2: peers yet to accept our proposal.
+    pub fn pending_accepters(&self, slot: Slot) -> Vec<NodeId> {
+        let Some(r) = self.rounds.get(&slot) else { return vec![] };
+        r.targets.iter().copied().filter(|p| !r.accept_acks.contains(*p)).collect()
+    }
+
+    /// True once an accept quorum acked our proposal for `slot`.
+    pub fn is_decided(&self, slot: Slot) -> bool {
+        self.rounds.get(&slot).is_some_and(|r| r.phase == Phase::Decided)
+    }
+
+    /// Stepped down, or parked by `check_quorum`: either way no round
+    /// makes progress.
+    pub fn is_idle(&self) -> bool {
+        self.stepped_down || self.unreachable
+    }
+
+    /// True once a quorum promised our `lead_from`, until it lapses.
+    pub fn is_leading(&self) -> bool {
+        self.leadership.as_ref().is_some_and(|l| l.established)
+    }
+
+    /// Everything a `/debug` dump wants to show, copied out.
+    pub fn debug_state(&self) -> ProposerDebug {
+        let rounds = self
+            .rounds
+            .iter()
+            .map(|(&slot, r)| RoundDebug {
+                slot,
+                proposal_id: r.proposal_id,
+                phase: r.phase,
+                promises: r.promises_from.len(),
+                accept_acks: r.accept_acks.len(),
+                broadcasts: r.broadcasts,
+            })
+            .collect();
+        ProposerDebug {
+            node_id: self.node_id,
+            idle: self.is_idle(),
+            gave_up: self.unreachable,
+            timer_ms: self.timer_ms,
+            last_failure: self.last_failure,
+            rounds,
+        }
+    }
+
+    /// Back to a freshly built proposer: every round, decided or not, is
+    /// dropped and metrics, backoff and idleness start over. Peers, quorum
+    /// and settings stay. The round counter keeps counting, since reusing a
+    /// proposal id is never safe; timers still pending go stale.
+    pub fn reset(&mut self) {
+        self.rounds.clear();
+        self.leadership = None;
+        self.startup_timer = None;
+        self.last_failure = None;
+        self.timer_ms = self.backoff.base_ms;
+        self.stepped_down = false;
+        self.unreachable = false;
+        self.metrics = ProposerMetrics::default();
+    }
+
+    /// Go idle: cancel every live phase timer and ignore input until
+    /// `activate`. Undecided rounds are parked, not forgotten, and so is a
+    /// slot 0 round still waiting out its startup delay. A no-op if we
+    /// already stepped down.
+    pub fn step_down(&mut self) -> Vec<Action<V>> {
+        if self.stepped_down {
+            return vec![];
+        }
+        self.stepped_down = true;
+        self.park()
+    }
+
+    // Cancel every phase timer, keeping the rounds they were for
+    fn park(&mut self) -> Vec<Action<V>> {
+        self.leadership = None;
+        let mut actions: Vec<Action<V>> = self
+            .rounds
+            .values_mut()
+            .filter_map(|r| r.timer.take())
+            .map(|id| Action::CancelTimer { id })
+            .collect();
+        if let Some(id) = self.startup_timer.take() {
+            actions.push(Action::CancelTimer { id });
+            let v = self.candidate_value.clone();
+            self.start_round(0, Intent::new(v));
+        }
+        actions
+    }
+
+    /// Hand leadership to `successor` for a planned restart: tell it the
+    /// highest round we got to with `LeaderHandoff`, so its rounds start
+    /// above ours, then `step_down`.
+    pub fn handoff(&mut self, successor: NodeId) -> Vec<Action<V>> {
+        let round = self.next_pid.0.saturating_sub(1);
+        let msg = PaxosMsg::LeaderHandoff { to: successor, round };
+        let mut actions = vec![Action::Send { to: successor, from: self.node_id, msg }];
+        actions.extend(self.step_down());
+        actions
+    }
+
+    /// Undo `step_down` and restart every undecided slot with a fresh
+    /// proposal id, including anything proposed while we were idle. Rounds
+    /// stay parked while `check_quorum` still finds no quorum; they restart
+    /// once it does.
+    pub fn activate(&mut self) -> Vec<Action<V>> {
+        if !self.stepped_down {
+            return vec![];
+        }
+        self.stepped_down = false;
+        self.resume()
+    }
+
+    // Restart the parked rounds, unless something still holds us idle
+    fn resume(&mut self) -> Vec<Action<V>> {
+        if self.is_idle() {
+            return vec![];
+        }
+        let pending: Vec<(Slot, Intent<V>)> = self
+            .rounds
+            .iter()
+            .filter(|(_, r)| r.phase != Phase::Decided)
+            .map(|(slot, r)| (*slot, r.intent.clone()))
+            .collect();
+        pending
+            .into_iter()
+            .flat_map(|(slot, intent)| self.start_round(slot, intent))
+            .collect()
+    }
+
+    /// Re-check, against `fd`, whether our live peers (plus ourselves) can
+    /// still make up a quorum, by the same rule our rounds are counted with
+    /// (weights or checker included). If not, report `QuorumUnreachable` once
+    /// and go idle instead of retrying into the void; once enough peers are
+    /// back, pick up where we left off (unless we've also stepped down).
+    /// Call it whenever liveness may have changed.
+    pub fn check_quorum(&mut self, fd: &FailureDetector, now_ms: u64) -> Vec<Action<V>> {
+        let others: Vec<NodeId> = self.peers.iter().copied().filter(|p| *p != self.node_id).collect();
+        let alive = fd.alive(&others, now_ms);
+        let live = alive.len();
+        let needed = self.quorum;
+        let mut voters = self.ctx.node_set();
+        for n in alive.into_iter().chain([self.node_id]) {
+            voters.insert(n);
+        }
+        let reachable = self.ctx.is_quorum(&voters, needed);
+        match (reachable, self.unreachable) {
+            (false, false) => {
+                self.unreachable = true;
+                let mut actions = self.park();
+                actions.push(Action::QuorumUnreachable { live, needed });
+                actions
+            }
+            (true, true) => {
+                self.unreachable = false;
+                self.resume()
+            }
+            _ => vec![],
+        }
     }
 
     fn next_proposal_id(&mut self) -> ProposalId {
+        // Acceptors keep one promise for every slot, so a fresh id outbids
+        // the one we lead under
+        self.leadership = None;
         let pid = self.next_pid;
         self.next_pid.0 = self.next_pid.0.saturating_add(1);
         pid
     }
 
-    fn next_timer_id(&mut self) -> TimerId {
-        let tid = self.timer_id;
-        self.timer_id.0 = self.timer_id.0.saturating_add(1);
-        tid
+    fn next_timer_id(&mut self, kind: TimerKind) -> TimerId {
+        let seq = self.next_timer_seq;
+        self.next_timer_seq = self.next_timer_seq.saturating_add(1);
+        TimerId { seq, node: self.node_id, kind }
     }
 
-    fn start_round(&mut self) -> Vec<Action<V>> {
+    /// We already know `value` was chosen in `slot` (handoff, learned out of
+    /// band): mark it decided without running a round. Any round in flight
+    /// there is cancelled.
+    pub fn seed_chosen(&mut self, slot: Slot, value: V) -> Vec<Action<V>> {
+        let mut actions = vec![];
+        let pid = match self.rounds.get_mut(&slot) {
+            Some(r) => {
+                if let Some(id) = r.timer.take() {
+                    actions.push(Action::CancelTimer { id });
+                }
+                r.proposal_id
+            }
+            None => (0, self.node_id),
+        };
+        let mut round = RoundState::new(pid, Intent::new(value.clone()), &self.ctx);
+        round.phase = Phase::Decided;
+        round.proposed = Some(value);
+        self.rounds.insert(slot, round);
+        actions
+    }
+
+    /// Start proposing `v` in `slot`. A round already running for that slot
+    /// is replaced by a fresh one with a higher proposal id. A slot we know
+    /// is decided stays decided: nothing happens.
+    pub fn propose(&mut self, slot: Slot, v: V) -> Vec<Action<V>> {
+        self.replace_round(slot, Intent::new(v))
+    }
+
+    /// `propose`, tagging the round with the client's `request_id`. It rides
+    /// on our AcceptProposals and their Accepteds, and comes back on the
+    /// `ChoseValue` for the slot, whichever value won.
+    pub fn propose_traced(&mut self, slot: Slot, v: V, request_id: u64) -> Vec<Action<V>> {
+        self.replace_round(slot, Intent { request_id: Some(request_id), ..Intent::new(v) })
+    }
+
+    /// Propose `v` in `slot` only if we have nothing there yet. A slot we
+    /// know is decided answers `Action::AlreadyChosen` with its value; a
+    /// round we're already running there is left alone.
+    pub fn propose_slot(&mut self, slot: Slot, v: V) -> Vec<Action<V>> {
+        match self.rounds.get(&slot) {
+            None => self.start_round(slot, Intent::new(v)),
+            Some(r) => match (&r.phase, &r.proposed) {
+                (Phase::Decided, Some(existing)) => vec![Action::AlreadyChosen { slot, existing: existing.clone() }],
+                _ => vec![],
+            },
+        }
+    }
+
+    /// Leader takeover: run Phase 1 on every slot in `from..to` we have no
+    /// round in, all under one proposal id so the slots' promises don't
+    /// outbid each other. Each one then gets whatever its promise quorum
+    /// reports as accepted, or `noop` if nothing was, so the log has no holes
+    /// below `to` before we propose anything new.
+    pub fn recover(&mut self, from: Slot, to: Slot, noop: V) -> Vec<Action<V>> {
         let pid = self.next_proposal_id();
-        self.round = Some(RoundState::new(pid));
-        let tid = self.next_timer_id();
+        let mut actions = vec![];
+        for slot in from..to {
+            if !self.rounds.contains_key(&slot) {
+                actions.extend(self.start_round_as(slot, Intent::new(noop.clone()), pid));
+            }
+        }
+        actions
+    }
+
+    /// Become the stable leader for every slot from `from_slot` on: one
+    /// `PrepareFrom` to every peer, and once a quorum promises it, each slot
+    /// we start a round in goes straight to Phase 2 under that id, adopting
+    /// whatever the promises reported accepted there. Rounds already running
+    /// keep their own ids, which acceptors will now refuse, so let them
+    /// settle first. Not retried: it lapses on a nack, or as soon as any slot
+    /// needs a fresh id (a round timing out, say), and slots go back to
+    /// running their own Phase 1. See `is_leading`.
+    pub fn lead_from(&mut self, from_slot: Slot) -> Vec<Action<V>> {
+        if self.is_idle() || self.peers.is_empty() {
+            return vec![];
+        }
+        let pid = self.next_proposal_id();
+        self.metrics.rounds_started += 1;
+        if let Some(store) = &mut self.round_store {
+            store.persist(pid.0);
+        }
+        self.leadership = Some(Leadership {
+            from_slot,
+            proposal_id: pid,
+            promises_from: self.ctx.node_set(),
+            accepted: BTreeMap::new(),
+            established: false,
+            used: BTreeSet::new(),
+        });
+        let msg = PaxosMsg::PrepareFrom { from_slot, proposal_id: pid, from: self.node_id };
+        self.peers.iter().map(|&to| Action::Send { to, from: self.node_id, msg: msg.clone() }).collect()
+    }
+
+    /// Compare-and-set: propose `v` in `slot` only if the slot is empty.
+    /// If the promise quorum reports any previously accepted value, the round
+    /// stops there with `Action::OtherValueAccepted` carrying that value
+    /// instead of entering Phase 2. (An accepted value may not be chosen yet,
+    /// but it might be, so for a CAS caller the slot is not empty.) A value reported
+    /// under one of our own earlier rounds for this call is ours, not a
+    /// rival's: a retry adopts it and carries on into Phase 2.
+    pub fn propose_if_empty(&mut self, slot: Slot, v: V) -> Vec<Action<V>> {
+        self.replace_round(slot, Intent { if_empty: true, ..Intent::new(v) })
+    }
+
+    /// `propose_if_empty`, except that a prior accepted value equal to `v`
+    /// or any of `also` doesn't lose the CAS: it's as good as ours, so the
+    /// round carries on and gets it chosen rather than reporting
+    /// `OtherValueAccepted`. For interchangeable values such as no-ops.
+    pub fn propose_any_of(&mut self, slot: Slot, v: V, also: impl IntoIterator<Item = V>) -> Vec<Action<V>>
+    where
+        V: PartialEq + Send + Sync + 'static,
+    {
+        let mut candidates: Vec<V> = also.into_iter().collect();
+        candidates.push(v.clone());
+        let acceptable: Acceptable<V> = Arc::new(move |prior| candidates.contains(prior));
+        self.replace_round(slot, Intent { if_empty: true, acceptable: Some(acceptable), ..Intent::new(v) })
+    }
+
+    /// Swap the value we're trying to get chosen in `slot` without starting
+    /// a new round. Only safe, and only done, while the round is still in
+    /// Phase 1 with no promise reporting a prior accepted value; returns
+    /// false (and changes nothing) otherwise. With a `value_fn` set the
+    /// function still decides what goes out.
+    pub fn try_replace_candidate(&mut self, slot: Slot, v: V) -> bool {
+        match self.rounds.get_mut(&slot) {
+            Some(r) if r.phase == Phase::Prepare && r.highest_accepted.is_none() && r.highest_accepted_id.is_none() => {
+                r.intent.value = v;
+                r.intent.sent_as.clear();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// `propose`, but with the round number dictated by the caller (an
+    /// epoch from an external lease, say) instead of our own counter. It has
+    /// to be above every round we've used; later rounds count up from it.
+    pub fn start_round_with(&mut self, slot: Slot, round: u64, v: V) -> Result<Vec<Action<V>>, PaxosError> {
+        if round < self.next_pid.0 {
+            return Err(PaxosError::RoundTooLow { round, next: self.next_pid.0 });
+        }
+        self.next_pid.0 = round;
+        Ok(self.replace_round(slot, Intent::new(v)))
+    }
+
+    fn replace_round(&mut self, slot: Slot, intent: Intent<V>) -> Vec<Action<V>> {
+        let mut actions = vec![];
+        if let Some(id) = self.rounds.get(&slot).and_then(|r| r.timer) {
+            actions.push(Action::CancelTimer { id });
+        }
+        actions.extend(self.start_round(slot, intent));
+        actions
+    }
+
+    fn start_round(&mut self, slot: Slot, intent: Intent<V>) -> Vec<Action<V>> {
+        if self.is_decided(slot) {
+            return vec![];
+        }
+        let led = self.leadership.as_ref().filter(|l| l.established && slot >= l.from_slot && !l.used.contains(&slot));
+        if let Some(pid) = led.map(|l| l.proposal_id) {
+            return self.start_led_round(slot, intent, pid);
+        }
+        let pid = self.next_proposal_id();
+        self.start_round_as(slot, intent, pid)
+    }
+
+    /// `start_round` under the promise quorum `lead_from` got us: Phase 1
+    /// is already done for `slot`, so go straight on from there.
+    fn start_led_round(&mut self, slot: Slot, intent: Intent<V>, pid: ProposalId) -> Vec<Action<V>> {
+        let Some(l) = self.leadership.as_mut() else { return vec![] };
+        l.used.insert(slot);
+        let mut round = RoundState::new(pid, intent, &self.ctx);
+        round.promises_from = l.promises_from.clone();
+        round.highest_accepted = l.accepted.get(&slot).cloned();
+        round.targets = self.peers.clone();
+        self.rounds.insert(slot, round);
+        self.promise_quorum(slot)
+    }
+
+    fn start_round_as(&mut self, slot: Slot, intent: Intent<V>, pid: ProposalId) -> Vec<Action<V>> {
+        let mut round = RoundState::new(pid, intent, &self.ctx);
+        // A round for a slot we already had one in is a retry: ask everyone
+        round.targets = match self.broadcast {
+            _ if self.rounds.contains_key(&slot) => self.peers.clone(),
+            BroadcastStrategy::All => self.peers.clone(),
+            BroadcastStrategy::QuorumPlusBuffer(k) => self.peers.iter().copied().take(self.quorum.saturating_add(k)).collect(),
+            BroadcastStrategy::Custom(pick) => pick(&self.peers, self.quorum),
+        };
+        if self.is_idle() {
+            // Parked until we resume; nothing goes out.
+            self.rounds.insert(slot, round);
+            return vec![];
+        }
+        if self.peers.is_empty() {
+            // Single-node cluster: nobody to ask, nothing can compete.
+            self.rounds.insert(slot, round);
+            let Some(v) = self.fresh_value(slot) else { return vec![] };
+            let r = self.rounds.get_mut(&slot).expect("just inserted");
+            r.phase = Phase::Decided;
+            r.proposed = Some(v.clone());
+            return vec![Action::ChoseValue { slot, v, request_id: r.intent.request_id }];
+        }
+        let tid = self.next_timer_id(TimerKind::Prepare(slot));
+        round.timer = Some(tid);
+        round.broadcasts = 1;
+        self.rounds.insert(slot, round);
+        self.metrics.rounds_started += 1;
+        if let Some(store) = &mut self.round_store {
+            store.persist(pid.0);
+        }
 
-        let mut actions: Vec<Action<V>> = self.broadcast_prepare(pid);
+        let mut actions: Vec<Action<V>> = self.broadcast_prepare(slot, pid);
         actions.push(Action::SetTimer { id: tid, ms: self.timer_ms });
         actions
     }
 
-    fn broadcast_prepare(&self, pid: ProposalId) -> Vec<Action<V>> {
-        self.peers.iter().copied().map(|to| Action::Send {
+    fn broadcast_prepare(&self, slot: Slot, pid: ProposalId) -> Vec<Action<V>> {
+        self.targets(slot).iter().copied().map(|to| Action::Send {
             to,
             from: self.node_id,
-            msg: PaxosMsg::Prepare { proposal_id: pid, from: self.node_id },
+            msg: PaxosMsg::Prepare { slot, proposal_id: pid, from: self.node_id },
         }).collect()
     }
 
-    fn broadcast_accept(&self, pid: ProposalId, v: V) -> Vec<Action<V>> {
-        self.peers.iter().copied().map(|to| Action::Send {
+    fn broadcast_accept(&self, slot: Slot, pid: ProposalId, v: V) -> Vec<Action<V>> {
+        let committed_up_to = if self.piggyback_commits { self.committed_prefix() } else { None };
+        let request_id = self.rounds.get(&slot).and_then(|r| r.intent.request_id);
+        self.targets(slot).iter().copied().map(|to| Action::Send {
             to,
             from: self.node_id,
-            msg: PaxosMsg::AcceptProposal { proposal_id: pid, value: v.clone() },
+            msg: PaxosMsg::AcceptProposal { slot, proposal_id: pid, value: v.clone(), committed_up_to, request_id },
         }).collect()
     }
 
+    fn targets(&self, slot: Slot) -> &[NodeId] {
+        self.rounds.get(&slot).map_or(&self.peers, |r| &r.targets)
+    }
+
+    /// Highest slot `s` with every slot in `0..=s` decided by us.
+    fn committed_prefix(&self) -> Option<Slot> {
+        let mut committed = None;
+        for (expected, (slot, r)) in (0..).zip(&self.rounds) {
+            if *slot != expected || r.phase != Phase::Decided {
+                break;
+            }
+            committed = Some(*slot);
+        }
+        committed
+    }
+
     pub fn on_init(&mut self) -> Vec<Action<V>> {
-        self.start_round()
+        if self.initial_delay_ms > 0 {
+            if self.startup_timer.is_some() {
+                return vec![];
+            }
+            let id = self.next_timer_id(TimerKind::Startup);
+            self.startup_timer = Some(id);
+            return vec![Action::SetTimer { id, ms: self.initial_delay_ms }];
+        }
+        // Single-decree use: the candidate goes into slot 0.
+        let v = self.candidate_value.clone();
+        self.start_round(0, Intent::new(v))
     }
 
     pub fn on_message(&mut self, from: NodeId, msg: PaxosMsg<V>) -> Vec<Action<V>> {
+        // Our predecessor is stepping down for us: start above it. A
+        // successor is usually still idle when this arrives, so it's taken
+        // before anything else; who activates us is up to the caller (or the
+        // election).
+        if let PaxosMsg::LeaderHandoff { to, round } = msg {
+            if to == self.node_id {
+                self.next_pid.0 = self.next_pid.0.max(round.saturating_add(1));
+            }
+            return vec![];
+        }
+        if self.is_idle() {
+            return vec![];
+        }
         match msg {
-            PaxosMsg::Promise { accepted_proposal, proposal_response } => {
-                let q = self.quorum; // take from &self BEFORE mutable borrow
-
-                // Do all mutations on the round in a short scope
-                let maybe_send: Option<(ProposalId, V)> = {
-                    let r = match self.round.as_mut() {
-                        Some(r) => r,
-                        None => return vec![],
-                    };
-                    if r.proposal_id != proposal_response { return vec![]; }
-                    if !r.promises_from.insert(from) { return vec![]; }
-
-                    if let Some(p) = accepted_proposal {
-                        if r.highest_accepted.as_ref().map_or(true, |best| p.id > best.id) {
-                            r.highest_accepted = Some(p);
-                        }
-                    }
-
-                    if r.promises_from.len() >= q {
-                        let v = r.highest_accepted
-                            .as_ref()
-                            .map(|p| p.value.clone())
-                            .unwrap_or_else(|| self.candidate_value.clone());
-                        Some((r.proposal_id, v))
-                    } else {
-                        None
+            PaxosMsg::Promise { slot, accepted_proposal, proposal_response } => {
+                self.record_promise(from, slot, proposal_response, accepted_proposal, None)
+            },
+            PaxosMsg::PromiseIdOnly { slot, accepted_id, proposal_response } => {
+                self.record_promise(from, slot, proposal_response, None, accepted_id)
+            },
+            PaxosMsg::PromiseEmpty { slot, proposal_response } => {
+                self.record_promise(from, slot, proposal_response, None, None)
+            },
+            PaxosMsg::PromiseFrom { from_slot, proposal_response, accepted } => {
+                let q = self.quorum;
+                let Some(l) = self.leadership.as_mut() else { return vec![] };
+                if l.established || (l.from_slot, l.proposal_id) != (from_slot, proposal_response) || !l.promises_from.insert(from) {
+                    return vec![];
+                }
+                for (slot, p) in accepted {
+                    if let Some(best) = highest(l.accepted.remove(&slot), Some(p)) {
+                        l.accepted.insert(slot, best);
                     }
-                };
-                if let Some((pid, v)) = maybe_send {
-                    return self.broadcast_accept(pid, v);
+                }
+                l.established = self.ctx.is_quorum(&l.promises_from, q);
+                vec![]
+            },
+            PaxosMsg::AcceptedValue { slot, proposal } => {
+                let Some(r) = self.rounds.get_mut(&slot) else { return vec![] };
+                let wanted = r.highest_accepted_id.map(|(id, _)| id);
+                if r.phase != Phase::Prepare || !r.fetching || wanted != Some(proposal.id) { return vec![]; }
+                r.fetching = false;
+                r.highest_accepted = Some(proposal);
+                self.promise_quorum(slot)
+            },
+            PaxosMsg::Accepted { slot, proposal, .. } => {
+                let q = self.quorum;
+                let Some(r) = self.rounds.get_mut(&slot) else { return vec![] };
+                if r.phase != Phase::Decided && r.proposal_id != proposal.id {
+                    // Someone else's round, maybe carrying our value
+                    let Some(eq) = self.value_eq else { return vec![] };
+                    if !eq(&r.intent.value, &proposal.value) { return vec![]; }
+                    let acks = r.foreign_acks.entry(proposal.id).or_insert_with(|| self.ctx.node_set());
+                    if !acks.insert(from) || !self.ctx.is_quorum(acks, q) { return vec![]; }
+                    return self.decide_adopted(slot);
+                }
+                if r.phase != Phase::Accept || r.proposal_id != proposal.id { return vec![]; }
+                if !r.accept_acks.insert(from) || !self.ctx.is_quorum(&r.accept_acks, q) { return vec![]; }
+                let Some(v) = r.proposed.clone() else { return vec![] };
+                r.phase = Phase::Decided;
+                let mut actions: Vec<Action<V>> = r.timer.take().map(|id| Action::CancelTimer { id }).into_iter().collect();
+                actions.push(Action::ChoseValue { slot, v, request_id: r.intent.request_id });
+                actions.extend(self.requeue(slot));
+                actions
+            },
+            PaxosMsg::Chosen { slot, proposal_id } => {
+                // A learner saw our accept quorum before we did (or instead).
+                let Some(r) = self.rounds.get_mut(&slot) else { return vec![] };
+                if r.phase != Phase::Decided && r.proposal_id != proposal_id && r.foreign_acks.contains_key(&proposal_id) {
+                    return self.decide_adopted(slot);
+                }
+                if r.phase != Phase::Accept || r.proposal_id != proposal_id { return vec![]; }
+                let Some(v) = r.proposed.clone() else { return vec![] };
+                r.phase = Phase::Decided;
+                let mut actions: Vec<Action<V>> = r.timer.take().map(|id| Action::CancelTimer { id }).into_iter().collect();
+                actions.push(Action::ChoseValue { slot, v, request_id: r.intent.request_id });
+                actions.extend(self.requeue(slot));
+                actions
+            },
+            PaxosMsg::Nack { slot, proposal_id, promised } => {
+                // Only nacks for our live round matter; make sure the retry
+                // (driven by the phase timer) outbids whoever beat us.
+                if self.leadership.as_ref().is_some_and(|l| l.proposal_id == proposal_id) {
+                    self.leadership = None;
+                }
+                let Some(r) = self.rounds.get_mut(&slot) else { return vec![] };
+                if r.proposal_id != proposal_id {
+                    return vec![];
+                }
+                if r.phase != Phase::Decided {
+                    r.nacked_by = r.nacked_by.max(Some(promised));
+                    self.last_failure = Some(RoundFailure::Preempted { by: promised });
+                }
+                if promised.0 >= self.next_pid.0 {
+                    self.next_pid.0 = promised.0.saturating_add(1);
                 }
                 vec![]
             },
@@ -147,14 +1072,198 @@ impl<V: Clone> Proposer<V> {
         }
     }
 
+    fn record_promise(
+        &mut self,
+        from: NodeId,
+        slot: Slot,
+        proposal_response: ProposalId,
+        accepted: Option<Proposal<V>>,
+        accepted_id: Option<ProposalId>,
+    ) -> Vec<Action<V>> {
+        let q = self.quorum;
+        let current = self.round_id(slot);
+        if current != Some(proposal_response) {
+            self.metrics.unknown_round_promises += 1;
+            if !self.strict {
+                return vec![];
+            }
+            let error = PaxosError::PromiseForUnknownRound { slot, got: proposal_response, current };
+            return vec![Action::Error { error }];
+        }
+        let Some(r) = self.rounds.get_mut(&slot) else { return vec![] };
+        if r.phase != Phase::Prepare { return vec![]; }
+        if !r.promises_from.insert(from) { return vec![]; }
+        r.highest_accepted = highest(r.highest_accepted.take(), accepted);
+        if let Some(id) = accepted_id {
+            if r.highest_accepted_id.is_none_or(|(best, _)| id > best) {
+                r.highest_accepted_id = Some((id, from));
+            }
+        }
+        if !self.ctx.is_quorum(&r.promises_from, q) {
+            return vec![];
+        }
+        self.promise_quorum(slot)
+    }
+
+    /// We hold a promise quorum for `slot`: fetch a lazily reported value if
+    /// that's the one we must adopt, otherwise stop (lost CAS) or go to
+    /// Phase 2.
+    fn promise_quorum(&mut self, slot: Slot) -> Vec<Action<V>> {
+        let Some(r) = self.rounds.get_mut(&slot) else { return vec![] };
+        if let Some((id, holder)) = r.highest_accepted_id {
+            if r.highest_accepted.as_ref().is_none_or(|p| p.id < id) {
+                if r.fetching {
+                    return vec![];
+                }
+                // Still guarded by the Prepare timer if the reply is lost.
+                r.fetching = true;
+                let msg = PaxosMsg::FetchAccepted { slot, proposal_id: id };
+                return vec![Action::Send { to: holder, from: self.node_id, msg }];
+            }
+        }
+        let lost = r.highest_accepted.as_ref().filter(|p| r.intent.if_empty && !r.intent.accepts(p));
+        if let Some(p) = lost {
+            // CAS lost: report what's there and drop the round. A value we
+            // were told is acceptable, or one an earlier round of ours sent,
+            // is adopted below like any other.
+            let existing = p.value.clone();
+            let mut actions = vec![];
+            if let Some(id) = r.timer.take() {
+                actions.push(Action::CancelTimer { id });
+            }
+            self.rounds.remove(&slot);
+            actions.push(Action::OtherValueAccepted { slot, existing });
+            return actions;
+        }
+        if self.broadcast_budget > 0 && r.broadcasts >= self.broadcast_budget {
+            // No broadcast left for Phase 2: the Prepare timer moves us on
+            return vec![];
+        }
+        let adopted = r.highest_accepted.as_ref().map(|p| p.value.clone());
+        r.requeue = adopted.as_ref().zip(self.noop_check).is_some_and(|(v, is_noop)| is_noop(v) && !is_noop(&r.intent.value));
+        r.phase = Phase::Accept;
+        let (pid, prepare_timer) = (r.proposal_id, r.timer.take());
+
+        let Some(v) = adopted.or_else(|| self.fresh_value(slot)) else { return vec![] };
+        if self.backoff.reset_on_success {
+            self.timer_ms = self.backoff.base_ms;
+        }
+        let mut actions = vec![];
+        if let Some(id) = prepare_timer {
+            actions.push(Action::CancelTimer { id });
+        }
+        let tid = self.next_timer_id(TimerKind::Accept(slot));
+        if let Some(r) = self.rounds.get_mut(&slot) {
+            r.timer = Some(tid);
+            r.proposed = Some(v.clone());
+            r.broadcasts += 1;
+            r.intent.sent_as.insert(pid);
+        }
+        actions.extend(self.broadcast_accept(slot, pid, v));
+        actions.push(Action::SetTimer { id: tid, ms: self.timer_ms });
+        actions
+    }
+
+    /// Another proposer's round got our intent's value chosen in `slot`:
+    /// as good as our own win, so stop retrying. Nothing to requeue, ours is
+    /// the value that made it.
+    fn decide_adopted(&mut self, slot: Slot) -> Vec<Action<V>> {
+        let Some(r) = self.rounds.get_mut(&slot) else { return vec![] };
+        let v = r.intent.value.clone();
+        r.phase = Phase::Decided;
+        r.proposed = Some(v.clone());
+        r.requeue = false;
+        let mut actions: Vec<Action<V>> = r.timer.take().map(|id| Action::CancelTimer { id }).into_iter().collect();
+        actions.push(Action::ChoseValue { slot, v, request_id: r.intent.request_id });
+        actions
+    }
+
+    /// `slot` was just decided. If that was for a no-op we had to adopt,
+    /// the caller's value goes into the next slot we have no round for.
+    fn requeue(&mut self, slot: Slot) -> Vec<Action<V>> {
+        let Some(r) = self.rounds.get_mut(&slot) else { return vec![] };
+        if !std::mem::take(&mut r.requeue) {
+            return vec![];
+        }
+        let intent = Intent { sent_as: BTreeSet::new(), ..r.intent.clone() };
+        let Some(next) = (slot + 1..).find(|s| !self.rounds.contains_key(s)) else { return vec![] };
+        self.start_round(next, intent)
+    }
+
+    /// Same id, same value, fresh timer; None once the retry budget (or
+    /// the round's broadcast budget) is spent.
+    fn resend_accept(&mut self, slot: Slot) -> Option<Vec<Action<V>>> {
+        let retries = self.accept_retries;
+        let r = self.rounds.get(&slot)?;
+        if r.accept_resends >= retries || (self.broadcast_budget > 0 && r.broadcasts >= self.broadcast_budget) {
+            return None;
+        }
+        let (pid, v) = (r.proposal_id, r.proposed.clone()?);
+        let tid = self.next_timer_id(TimerKind::Accept(slot));
+        let r = self.rounds.get_mut(&slot)?;
+        r.accept_resends += 1;
+        r.broadcasts += 1;
+        r.timer = Some(tid);
+        let mut actions = self.broadcast_accept(slot, pid, v);
+        actions.push(Action::SetTimer { id: tid, ms: self.timer_ms });
+        Some(actions)
+    }
+
     pub fn on_timeout(&mut self, id: TimerId) -> Vec<Action<V>> {
-        if self.timer_id != id { return vec![]; } // stale
-        self.timer_ms = self.timer_ms.saturating_mul(2);
-        // Restart round with a higher proposal id
-        self.start_round()
+        if self.is_idle() {
+            return vec![];
+        }
+        let slot = match id.kind {
+            TimerKind::Prepare(slot) | TimerKind::Accept(slot) => slot,
+            TimerKind::Startup if self.startup_timer == Some(id) => {
+                self.startup_timer = None;
+                let v = self.candidate_value.clone();
+                return self.start_round(0, Intent::new(v));
+            }
+            TimerKind::Startup | TimerKind::Watchdog | TimerKind::LearnStalled(_) => return vec![],
+        };
+        let intent = match self.rounds.get(&slot) {
+            Some(r) if r.timer == Some(id) => {
+                // A nack doesn't end the round, this timer does
+                self.last_failure = Some(r.nacked_by.map_or(RoundFailure::TimedOut, |by| RoundFailure::Preempted { by }));
+                r.intent.clone()
+            }
+            _ => return vec![], // stale
+        };
+        if let TimerKind::Accept(_) = id.kind {
+            if let Some(actions) = self.resend_accept(slot) {
+                return actions;
+            }
+        }
+        let doubled = self.timer_ms.saturating_mul(2);
+        if doubled > self.backoff.max_ms {
+            self.metrics.backoff_capped += 1;
+        }
+        self.timer_ms = doubled.min(self.backoff.max_ms);
+        // Restart this slot's round with a higher proposal id
+        self.start_round(slot, intent)
     }
 }
 
+impl<V: Clone + IsNoop> Proposer<V> {
+    /// When a promise quorum makes us adopt a no-op in place of a real
+    /// value we were proposing, get the no-op chosen (we must) and then
+    /// propose our value again in the next free slot.
+    pub fn set_repropose_after_noop(&mut self, on: bool) {
+        self.noop_check = on.then_some(V::is_noop as fn(&V) -> bool);
+    }
+}
+
+impl<V: Clone + PartialEq> Proposer<V> {
+    /// Count accept quorums for other proposers' ids too, when they carry
+    /// the value we're proposing in that slot (say a higher round adopted
+    /// it from us). Such a quorum decides our round with `ChoseValue`
+    /// instead of leaving us retrying, as does a learner's `Chosen` for an
+    /// id we've seen accept it.
+    pub fn set_detect_adopted_value(&mut self, on: bool) {
+        self.value_eq = on.then_some(V::eq as fn(&V, &V) -> bool);
+    }
+}
 
 /* If you use the trait abstraction */
 impl<V: Clone> HandlesEvents<V> for Proposer<V> {
@@ -0,0 +1,40 @@
+// Small deterministic RNG (SplitMix64) so simulations and jitter are
+// reproducible from a seed. Not for anything security related.
+use crate::types::NodeId;
+
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// `node`'s own stream for a run: the same `(run_seed, node)` always
+    /// gives the same numbers, whatever the other nodes draw.
+    pub fn for_node(run_seed: u64, node: NodeId) -> Self {
+        let mut mix = Rng::new(run_seed ^ node.wrapping_mul(0xd6e8_feb8_6659_fd93));
+        Rng::new(mix.next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`; `n == 0` yields 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        if n == 0 { 0 } else { self.next_u64() % n }
+    }
+
+    /// True with probability `p` (clamped to 0..=1).
+    pub fn chance(&mut self, p: f64) -> bool {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        unit < p
+    }
+}
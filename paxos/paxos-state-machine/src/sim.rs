@@ -0,0 +1,602 @@
+// Deterministic in-memory network for driving roles in tests and experiments.
+//
+// Everything runs on a virtual clock. Sends arrive `latency_ms` after they're
+// emitted, timers fire `ms` after they're set unless cancelled first, and ties
+// are broken by scheduling order so a run is reproducible. Faults (drops,
+// jitter, crashed nodes) are drawn from a seeded RNG, so they are too.
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    acceptor::{AcceptorPersisted, AcceptorStore},
+    rng::Rng,
+    types::*,
+};
+
+/// Message-level faults applied to every send.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Faults {
+    /// Probability that a message is lost.
+    pub drop_rate: f64,
+    /// Up to this much extra delay per message, which reorders them.
+    pub jitter_ms: u64,
+}
+
+enum Item<V> {
+    Deliver { to: NodeId, event: Event<V> },
+    // A node's output, held back by its processing delay
+    Emit { from: NodeId, actions: Vec<Action<V>> },
+}
+
+struct Scheduled<V> {
+    at: u64,
+    seq: u64,
+    item: Item<V>,
+}
+
+impl<V> PartialEq for Scheduled<V> { fn eq(&self, o: &Self) -> bool { (self.at, self.seq) == (o.at, o.seq) } }
+impl<V> Eq for Scheduled<V> {}
+impl<V> PartialOrd for Scheduled<V> { fn partial_cmp(&self, o: &Self) -> Option<std::cmp::Ordering> { Some(self.cmp(o)) } }
+impl<V> Ord for Scheduled<V> { fn cmp(&self, o: &Self) -> std::cmp::Ordering { (self.at, self.seq).cmp(&(o.at, o.seq)) } }
+
+/// `R` is the role type hosted at every node; use `Box<dyn HandlesEvents<V>>`
+/// to mix proposers, acceptors and learners in one network.
+pub struct Network<V, R> {
+    now_ms: u64,
+    next_seq: u64,
+    latency_ms: u64,
+    nodes: BTreeMap<NodeId, R>,
+    delays: HashMap<NodeId, u64>,
+    queue: BinaryHeap<Reverse<Scheduled<V>>>,
+    live_timers: HashSet<TimerId>,
+    faults: Faults,
+    // Faults on a node's sends come from its own stream, so they replay
+    // exactly from the seed even when the interleaving elsewhere changes
+    seed: u64,
+    rngs: HashMap<NodeId, Rng>,
+    down: HashSet<NodeId>,
+    dropped: u64,
+    // Everything that isn't a Send or timer bookkeeping, e.g. ChoseValue
+    outputs: Vec<(u64, NodeId, Action<V>)>,
+    timeouts_fired: Vec<(u64, NodeId, TimerId)>,
+    // Every event a node handled and what it returned, once set_tracing is on
+    trace: Option<Trace<V>>,
+    // A node's acceptor store, and how long each write to it takes
+    disks: HashMap<NodeId, (MemStore<V>, u64)>,
+}
+
+impl<V: Clone, R: HandlesEvents<V>> Default for Network<V, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: Clone, R: HandlesEvents<V>> Network<V, R> {
+    pub fn new() -> Self {
+        Self {
+            now_ms: 0,
+            next_seq: 0,
+            latency_ms: 1,
+            nodes: BTreeMap::new(),
+            delays: HashMap::new(),
+            queue: BinaryHeap::new(),
+            live_timers: HashSet::new(),
+            faults: Faults::default(),
+            seed: 0,
+            rngs: HashMap::new(),
+            down: HashSet::new(),
+            dropped: 0,
+            outputs: Vec::new(),
+            timeouts_fired: Vec::new(),
+            trace: None,
+            disks: HashMap::new(),
+        }
+    }
+
+    pub fn add_node(&mut self, id: NodeId, role: R) {
+        self.nodes.insert(id, role);
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&R> {
+        self.nodes.get(&id)
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut R> {
+        self.nodes.get_mut(&id)
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now_ms
+    }
+
+    /// One-way delay for every message. Defaults to 1ms.
+    pub fn set_latency(&mut self, ms: u64) {
+        self.latency_ms = ms;
+    }
+
+    /// Make `node` slow: whatever it emits in response to an event takes
+    /// effect `ms` later on the virtual clock.
+    pub fn set_node_delay(&mut self, node: NodeId, ms: u64) {
+        self.delays.insert(node, ms);
+    }
+
+    /// Model a slow disk under `node`'s acceptor: `disk` is the store it was
+    /// given with `Acceptor::set_store`, and whenever handling an event
+    /// writes to it, nothing that handling sent (its replies included) goes
+    /// out until `ms` later, as with an fsync before answering. Its timers
+    /// are set and cancelled straight away. Stacks on `set_node_delay`.
+    pub fn set_persist_delay(&mut self, node: NodeId, disk: MemStore<V>, ms: u64) {
+        self.disks.insert(node, (disk, ms));
+    }
+
+    /// Turn on message faults. `seed` is the run seed: each node draws the
+    /// faults on its sends from `Rng::for_node(seed, node)`.
+    pub fn set_faults(&mut self, faults: Faults, seed: u64) {
+        self.faults = faults;
+        self.seed = seed;
+        self.rngs.clear();
+    }
+
+    /// Messages lost to `Faults::drop_rate` or to a crashed node.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Stop `node`: it receives nothing and its pending timers are forgotten
+    /// until `recover`. Its state is kept, as if it were on stable storage.
+    pub fn crash(&mut self, node: NodeId) {
+        self.down.insert(node);
+        self.live_timers.retain(|t| t.node != node);
+    }
+
+    /// Bring `node` back. It has no timers, so follow up with `submit` to
+    /// restart whatever it was doing.
+    pub fn recover(&mut self, node: NodeId) {
+        self.down.remove(&node);
+    }
+
+    pub fn is_down(&self, node: NodeId) -> bool {
+        self.down.contains(&node)
+    }
+
+    /// Carry out actions produced outside the event loop, e.g. by calling a
+    /// role method directly.
+    pub fn submit(&mut self, from: NodeId, actions: Vec<Action<V>>) {
+        self.emit(from, actions);
+    }
+
+    /// Virtual time of the next scheduled item, if any.
+    pub fn next_event_at(&self) -> Option<u64> {
+        self.queue.peek().map(|Reverse(s)| s.at)
+    }
+
+    pub fn outputs(&self) -> &[(u64, NodeId, Action<V>)] {
+        &self.outputs
+    }
+
+    pub fn timeouts_fired(&self) -> &[(u64, NodeId, TimerId)] {
+        &self.timeouts_fired
+    }
+
+    /// Record every event a node handles from now on, with the actions it
+    /// returned, for `Trace::diff` against another run. Off drops the trace.
+    pub fn set_tracing(&mut self, on: bool) {
+        if !on {
+            self.trace = None;
+        } else if self.trace.is_none() {
+            self.trace = Some(Trace::default());
+        }
+    }
+
+    pub fn trace(&self) -> Option<&Trace<V>> {
+        self.trace.as_ref()
+    }
+
+    /// Call `on_init` on every node, in id order.
+    pub fn init(&mut self) {
+        let ids: Vec<NodeId> = self.nodes.keys().copied().collect();
+        for id in ids {
+            self.init_node(id);
+        }
+    }
+
+    pub fn init_node(&mut self, id: NodeId) {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            let actions = node.on_init();
+            self.record(id, None, &actions);
+            self.emit(id, actions);
+        }
+    }
+
+    /// Feed an event to `to` right now, as if it just arrived.
+    pub fn inject(&mut self, to: NodeId, event: Event<V>) {
+        self.deliver(to, event);
+    }
+
+    /// Run the next scheduled item. False once nothing is left.
+    pub fn step(&mut self) -> bool {
+        let Some(Reverse(next)) = self.queue.pop() else { return false };
+        self.now_ms = self.now_ms.max(next.at);
+        match next.item {
+            Item::Deliver { to, event } => self.deliver(to, event),
+            Item::Emit { from, actions } => self.apply(from, actions),
+        }
+        true
+    }
+
+    /// Run until the queue drains or the clock passes `until_ms`.
+    pub fn run_until(&mut self, until_ms: u64) {
+        while self.queue.peek().is_some_and(|Reverse(s)| s.at <= until_ms) {
+            self.step();
+        }
+        self.now_ms = self.now_ms.max(until_ms);
+    }
+
+    /// Run at most `max_steps` items; true if the queue drained.
+    pub fn run_to_quiescence(&mut self, max_steps: usize) -> bool {
+        for _ in 0..max_steps {
+            if !self.step() {
+                return true;
+            }
+        }
+        self.queue.is_empty()
+    }
+
+    fn deliver(&mut self, to: NodeId, event: Event<V>) {
+        if let Event::Timeout { id } = &event {
+            if !self.live_timers.remove(id) {
+                return; // cancelled or already fired
+            }
+            self.timeouts_fired.push((self.now_ms, to, *id));
+        } else if self.down.contains(&to) {
+            self.dropped += 1;
+            return;
+        }
+        let Some(node) = self.nodes.get_mut(&to) else { return };
+        let traced = self.trace.is_some().then(|| event.clone());
+        let writes = self.disks.get(&to).map(|(disk, _)| disk.writes());
+        let actions = node.on_event(event);
+        if traced.is_some() {
+            self.record(to, traced, &actions);
+        }
+        match self.disks.get(&to) {
+            Some((disk, ms)) if Some(disk.writes()) != writes => {
+                // Nothing goes out before the write lands; the rest is local
+                let (sends, local) = actions.into_iter().partition(|a| matches!(a, Action::Send { .. }));
+                let at = self.now_ms + ms + self.delays.get(&to).copied().unwrap_or(0);
+                self.schedule(at, Item::Emit { from: to, actions: sends });
+                self.emit(to, local);
+            }
+            _ => self.emit(to, actions),
+        }
+    }
+
+    fn record(&mut self, node: NodeId, event: Option<Event<V>>, actions: &[Action<V>]) {
+        let at = self.now_ms;
+        if let Some(trace) = &mut self.trace {
+            trace.entries.push(TraceEntry { at, node, event, actions: actions.to_vec() });
+        }
+    }
+
+    fn emit(&mut self, from: NodeId, actions: Vec<Action<V>>) {
+        match self.delays.get(&from).copied().unwrap_or(0) {
+            0 => self.apply(from, actions),
+            delay => self.schedule(self.now_ms + delay, Item::Emit { from, actions }),
+        }
+    }
+
+    fn apply(&mut self, from: NodeId, actions: Vec<Action<V>>) {
+        for action in actions {
+            match action {
+                Action::Send { to, from: sender, msg } => {
+                    let seed = self.seed;
+                    let rng = self.rngs.entry(from).or_insert_with(|| Rng::for_node(seed, from));
+                    if rng.chance(self.faults.drop_rate) {
+                        self.dropped += 1;
+                        continue;
+                    }
+                    let at = self.now_ms + self.latency_ms + rng.below(self.faults.jitter_ms + 1);
+                    self.schedule(at, Item::Deliver { to, event: Event::Message { from: sender, msg } });
+                }
+                Action::SetTimer { id, ms } => {
+                    self.live_timers.insert(id);
+                    self.schedule(self.now_ms + ms, Item::Deliver { to: from, event: Event::Timeout { id } });
+                }
+                Action::CancelTimer { id } => {
+                    self.live_timers.remove(&id);
+                }
+                other => self.outputs.push((self.now_ms, from, other)),
+            }
+        }
+    }
+
+    fn schedule(&mut self, at: u64, item: Item<V>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queue.push(Reverse(Scheduled { at, seq, item }));
+    }
+}
+
+/// In-memory `AcceptorStore`: keeps the last record persisted and counts
+/// the writes. Clones share one disk, so hand one to `Acceptor::set_store`
+/// and keep another to look at, or to give `Network::set_persist_delay`.
+pub struct MemStore<V> {
+    disk: Arc<Mutex<Disk<V>>>,
+}
+
+struct Disk<V> {
+    last: Option<AcceptorPersisted<V>>,
+    writes: u64,
+}
+
+impl<V> Clone for MemStore<V> {
+    fn clone(&self) -> Self {
+        Self { disk: self.disk.clone() }
+    }
+}
+
+impl<V> Default for MemStore<V> {
+    fn default() -> Self {
+        Self { disk: Arc::new(Mutex::new(Disk { last: None, writes: 0 })) }
+    }
+}
+
+impl<V: Clone> MemStore<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What the acceptor would come back with after a crash.
+    pub fn last(&self) -> Option<AcceptorPersisted<V>> {
+        self.lock().last.clone()
+    }
+
+    pub fn writes(&self) -> u64 {
+        self.lock().writes
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Disk<V>> {
+        self.disk.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<V: Clone> AcceptorStore<V> for MemStore<V> {
+    fn persist(&mut self, state: &AcceptorPersisted<V>) {
+        let mut disk = self.lock();
+        disk.last = Some(state.clone());
+        disk.writes += 1;
+    }
+}
+
+/// Virtual clock and pending timers for driving a single role by hand, with
+/// no network in between: hand it the role's actions, then move time forward
+/// and feed the fired timers back as `Event::Timeout`s.
+#[derive(Debug, Default)]
+pub struct Clock {
+    now_ms: u64,
+    next_seq: u64,
+    // (deadline, seq) keeps equal deadlines in the order they were set
+    pending: BTreeMap<(u64, u64), TimerId>,
+    deadlines: HashMap<TimerId, (u64, u64)>,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now_ms
+    }
+
+    /// Pick up the `SetTimer`s and `CancelTimer`s among `actions`.
+    pub fn apply<V>(&mut self, actions: &[Action<V>]) {
+        for action in actions {
+            match action {
+                Action::SetTimer { id, ms } => self.set(*id, *ms),
+                Action::CancelTimer { id } => self.cancel(*id),
+                _ => {}
+            }
+        }
+    }
+
+    /// Arm `id` to fire `ms` from now; re-arming moves it.
+    pub fn set(&mut self, id: TimerId, ms: u64) {
+        self.cancel(id);
+        let key = (self.now_ms.saturating_add(ms), self.next_seq);
+        self.next_seq += 1;
+        self.pending.insert(key, id);
+        self.deadlines.insert(id, key);
+    }
+
+    pub fn cancel(&mut self, id: TimerId) {
+        if let Some(key) = self.deadlines.remove(&id) {
+            self.pending.remove(&key);
+        }
+    }
+
+    /// When the earliest pending timer is due.
+    pub fn peek_next_deadline(&self) -> Option<u64> {
+        self.pending.keys().next().map(|(at, _)| *at)
+    }
+
+    /// Move forward `ms`, returning every timer due by then in deadline order.
+    pub fn advance(&mut self, ms: u64) -> Vec<TimerId> {
+        self.advance_to(self.now_ms.saturating_add(ms))
+    }
+
+    /// Like `advance`, to an absolute time. The clock never goes backwards.
+    pub fn advance_to(&mut self, until_ms: u64) -> Vec<TimerId> {
+        let mut fired = Vec::new();
+        while let Some(entry) = self.pending.first_entry() {
+            if entry.key().0 > until_ms {
+                break;
+            }
+            let ((at, _), id) = entry.remove_entry();
+            self.deadlines.remove(&id);
+            self.now_ms = self.now_ms.max(at);
+            fired.push(id);
+        }
+        self.now_ms = self.now_ms.max(until_ms);
+        fired
+    }
+}
+
+/// Live-timer bookkeeping for tests: feed it every action vector a role
+/// returns (and the timeouts you deliver), then assert on what's still
+/// armed. A timer set and never cancelled or fired shows up as live, which
+/// is how leaks get caught.
+#[derive(Debug, Default)]
+pub struct TimerTracker {
+    live: HashSet<TimerId>,
+    cancelled: HashSet<TimerId>,
+}
+
+impl TimerTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply<V>(&mut self, actions: &[Action<V>]) {
+        for action in actions {
+            match action {
+                Action::SetTimer { id, .. } => {
+                    self.live.insert(*id);
+                    self.cancelled.remove(id);
+                }
+                Action::CancelTimer { id } if self.live.remove(id) => {
+                    self.cancelled.insert(*id);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// `id` fired and was delivered, so it's no longer armed.
+    pub fn fired(&mut self, id: TimerId) {
+        self.live.remove(&id);
+    }
+
+    pub fn is_live(&self, id: TimerId) -> bool {
+        self.live.contains(&id)
+    }
+
+    /// Live timers, in no particular order.
+    pub fn live(&self) -> impl Iterator<Item = TimerId> + '_ {
+        self.live.iter().copied()
+    }
+
+    /// Panics unless exactly `ids` are live.
+    #[track_caller]
+    pub fn assert_live(&self, ids: &[TimerId]) {
+        let want: HashSet<TimerId> = ids.iter().copied().collect();
+        assert_eq!(self.live, want, "live timers");
+    }
+
+    /// Panics unless `id` was set and then cancelled (and not set again).
+    #[track_caller]
+    pub fn assert_cancelled(&self, id: TimerId) {
+        assert!(self.cancelled.contains(&id), "{id:?} was not cancelled; live: {}", self.live.contains(&id));
+    }
+}
+
+/// One event a node handled in a traced run, and what it returned.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry<V> {
+    pub at: u64,
+    pub node: NodeId,
+    /// None for `on_init`.
+    pub event: Option<Event<V>>,
+    pub actions: Vec<Action<V>>,
+}
+
+impl<V: fmt::Debug> fmt::Display for TraceEntry<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at {}ms node {} ", self.at, self.node)?;
+        match &self.event {
+            Some(event) => write!(f, "handled {event:?}"),
+            None => write!(f, "init"),
+        }
+    }
+}
+
+/// What `Network::set_tracing` recorded, in the order it happened.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trace<V> {
+    entries: Vec<TraceEntry<V>>,
+}
+
+impl<V> Default for Trace<V> {
+    fn default() -> Self {
+        Self { entries: Vec::new() }
+    }
+}
+
+/// Where two traces part ways: the first entry that isn't the same in both.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence<V> {
+    pub index: usize,
+    /// The entry at `index` on each side; None if that run had already ended.
+    pub ours: Option<TraceEntry<V>>,
+    pub theirs: Option<TraceEntry<V>>,
+    /// Set when it's the same node handling the same event, returning
+    /// different actions.
+    pub action: Option<ActionDiff<V>>,
+}
+
+/// The first action that differs between two entries for the same event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ActionDiff<V> {
+    pub index: usize,
+    /// None past the end of that side's actions.
+    pub ours: Option<Action<V>>,
+    pub theirs: Option<Action<V>>,
+}
+
+impl<V: fmt::Debug> fmt::Display for Divergence<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry {}: ", self.index)?;
+        match (&self.ours, &self.theirs, &self.action) {
+            (Some(ours), _, Some(a)) => write!(f, "{ours}, action {}: {:?} became {:?}", a.index, a.ours, a.theirs),
+            (Some(ours), Some(theirs), None) => write!(f, "{ours} became {theirs}"),
+            (Some(ours), None, None) => write!(f, "{ours}, then the other run ended"),
+            (None, Some(theirs), _) => write!(f, "ended here, the other run went on: {theirs}"),
+            (None, None, _) => write!(f, "no difference"),
+        }
+    }
+}
+
+impl<V> Trace<V> {
+    pub fn entries(&self) -> &[TraceEntry<V>] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<V: Clone + PartialEq> Trace<V> {
+    /// The first point `other` differs from this trace, None if they match
+    /// end to end. Run the baseline and the changed code on the same seed to
+    /// find the event where a change first shows.
+    pub fn diff(&self, other: &Trace<V>) -> Option<Divergence<V>> {
+        let n = self.entries.len().max(other.entries.len());
+        let index = (0..n).find(|&i| self.entries.get(i) != other.entries.get(i))?;
+        let (ours, theirs) = (self.entries.get(index), other.entries.get(index));
+        let action = match (ours, theirs) {
+            (Some(a), Some(b)) if (a.at, a.node, &a.event) == (b.at, b.node, &b.event) => {
+                let i = (0..).find(|&i| a.actions.get(i) != b.actions.get(i)).expect("entries differ");
+                Some(ActionDiff { index: i, ours: a.actions.get(i).cloned(), theirs: b.actions.get(i).cloned() })
+            }
+            _ => None,
+        };
+        Some(Divergence { index, ours: ours.cloned(), theirs: theirs.cloned(), action })
+    }
+}
@@ -0,0 +1,65 @@
+// Sanity checks on a cluster layout, to run before starting any role.
+//
+// Each catches a misconfiguration that would otherwise only show up at
+// runtime as rounds that never reach a quorum or decisions nobody hears of.
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::types::{NodeContext, NodeId};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TopologyError {
+    /// Nobody to accept anything.
+    NoAcceptors,
+    /// Acceptors report their accepts to no one, so no decision is ever
+    /// learned.
+    NoLearners,
+    /// `proposer` sends Prepares to `peer`, which isn't an acceptor.
+    PeerNotAcceptor { proposer: NodeId, peer: NodeId },
+    /// Quorums are sized from `number_of_nodes`, so it has to be the
+    /// number of acceptors.
+    NodeCountMismatch { number_of_nodes: u64, acceptors: usize },
+}
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopologyError::NoAcceptors => write!(f, "no acceptors"),
+            TopologyError::NoLearners => write!(f, "no learners"),
+            TopologyError::PeerNotAcceptor { proposer, peer } => {
+                write!(f, "proposer {proposer} has peer {peer}, which is not an acceptor")
+            }
+            TopologyError::NodeCountMismatch { number_of_nodes, acceptors } => {
+                write!(f, "number_of_nodes is {number_of_nodes} but there are {acceptors} acceptors")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TopologyError {}
+
+/// Check a layout: `proposers` pairs each proposer with the peers it's
+/// built with, `learners` is who the acceptors report to. The first
+/// problem found is returned.
+pub fn validate_topology(
+    ctx: &NodeContext,
+    acceptors: &HashSet<NodeId>,
+    learners: &HashSet<NodeId>,
+    proposers: &[(NodeId, Vec<NodeId>)],
+) -> Result<(), TopologyError> {
+    if acceptors.is_empty() {
+        return Err(TopologyError::NoAcceptors);
+    }
+    if learners.is_empty() {
+        return Err(TopologyError::NoLearners);
+    }
+    for (proposer, peers) in proposers {
+        if let Some(&peer) = peers.iter().find(|p| !acceptors.contains(p)) {
+            return Err(TopologyError::PeerNotAcceptor { proposer: *proposer, peer });
+        }
+    }
+    if ctx.number_of_nodes != acceptors.len() as u64 {
+        return Err(TopologyError::NodeCountMismatch { number_of_nodes: ctx.number_of_nodes, acceptors: acceptors.len() });
+    }
+    Ok(())
+}
@@ -0,0 +1,533 @@
+// Length-prefixed framing for PaxosMsg over a byte stream (TCP in practice).
+//
+// A frame is a u32 little-endian payload length followed by the payload:
+// the sender's NodeId, then the wire-encoded message. The transport owns the
+// framing and its limits; the role handlers above it stay synchronous.
+//
+// With a `FrameAuth` set, the payload is instead a u32 tag length, the tag,
+// then the sender and message as above; the tag covers just those.
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{
+    failure_detector::FailureDetector,
+    msg::PaxosMsg,
+    types::{Action, NodeId},
+    wire::{self, DecodeError, Wire},
+};
+
+/// What `Outbound::push` does when the queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Wait for the writer to make room.
+    Block,
+    /// Make room by discarding the oldest queued message. Paxos tolerates
+    /// loss, and the newest message is usually the one that matters.
+    DropOldest,
+    /// Refuse with `TransportError::Backpressure`.
+    Error,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TransportConfig {
+    /// Largest payload we'll accept from a peer. Checked against the length
+    /// prefix before the buffer is allocated.
+    pub max_frame_bytes: usize,
+    /// Messages an `Outbound` queue holds before `backpressure` kicks in.
+    pub outbound_capacity: usize,
+    pub backpressure: BackpressurePolicy,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self { max_frame_bytes: 1 << 20, outbound_capacity: 1024, backpressure: BackpressurePolicy::Block }
+    }
+}
+
+#[derive(Debug)]
+pub enum TransportError {
+    Io(io::Error),
+    /// Peer announced a frame bigger than `max_frame_bytes`; the connection
+    /// has been closed.
+    FrameTooLarge { len: u64, max: usize },
+    Decode(DecodeError),
+    /// The peer hung up, or we closed the connection earlier.
+    Closed,
+    /// Outbound queue is full and the policy is `BackpressurePolicy::Error`.
+    Backpressure { capacity: usize },
+    /// A frame's tag didn't verify (or it had none). Nothing of it was
+    /// decoded.
+    AuthFailed,
+    /// `PeerPool` has no address for `peer`, or gave up reconnecting to it.
+    Unreachable { peer: NodeId },
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Io(e) => write!(f, "io error: {e}"),
+            TransportError::FrameTooLarge { len, max } => {
+                write!(f, "frame of {len} bytes exceeds limit of {max}")
+            }
+            TransportError::Decode(e) => write!(f, "bad frame: {e}"),
+            TransportError::Closed => write!(f, "connection closed"),
+            TransportError::Backpressure { capacity } => {
+                write!(f, "outbound queue full ({capacity} messages)")
+            }
+            TransportError::AuthFailed => write!(f, "frame failed authentication"),
+            TransportError::Unreachable { peer } => write!(f, "peer {peer} is unreachable"),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+impl From<io::Error> for TransportError {
+    fn from(e: io::Error) -> Self {
+        TransportError::Io(e)
+    }
+}
+
+impl From<DecodeError> for TransportError {
+    fn from(e: DecodeError) -> Self {
+        TransportError::Decode(e)
+    }
+}
+
+/// Byte stream a connection runs over. `close` is best effort.
+pub trait FrameStream: Read + Write {
+    fn close(&mut self) {}
+}
+
+impl FrameStream for TcpStream {
+    fn close(&mut self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+/// Signs outgoing frames and checks incoming ones, e.g. an HMAC under a
+/// key shared by the cluster.
+pub trait FrameAuth {
+    fn sign(&self, bytes: &[u8]) -> Vec<u8>;
+    fn verify(&self, bytes: &[u8], tag: &[u8]) -> bool;
+}
+
+pub struct Connection<S> {
+    stream: S,
+    config: TransportConfig,
+    closed: bool,
+    auth: Option<Box<dyn FrameAuth + Send>>,
+}
+
+impl<S: FrameStream> Connection<S> {
+    pub fn new(stream: S, config: TransportConfig) -> Self {
+        Self { stream, config, closed: false, auth: None }
+    }
+
+    /// Sign every frame we write and reject any we read that doesn't verify.
+    /// Both ends need the same setting.
+    pub fn set_auth(&mut self, auth: impl FrameAuth + Send + 'static) {
+        self.auth = Some(Box::new(auth));
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn close(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            self.stream.close();
+        }
+    }
+
+    pub fn write_frame(&mut self, payload: &[u8]) -> Result<(), TransportError> {
+        if self.closed {
+            return Err(TransportError::Closed);
+        }
+        let signed;
+        let payload = match &self.auth {
+            None => payload,
+            Some(auth) => {
+                let tag = auth.sign(payload);
+                let mut out = Vec::with_capacity(4 + tag.len() + payload.len());
+                tag.encode(&mut out);
+                out.extend_from_slice(payload);
+                signed = out;
+                &signed
+            }
+        };
+        let len = u32::try_from(payload.len()).map_err(|_| TransportError::FrameTooLarge {
+            len: payload.len() as u64,
+            max: u32::MAX as usize,
+        })?;
+        self.stream.write_all(&len.to_le_bytes())?;
+        self.stream.write_all(payload)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    pub fn read_frame(&mut self) -> Result<Vec<u8>, TransportError> {
+        if self.closed {
+            return Err(TransportError::Closed);
+        }
+        let mut header = [0u8; 4];
+        match self.stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.closed = true;
+                return Err(TransportError::Closed);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let len = u32::from_le_bytes(header) as usize;
+        if len > self.config.max_frame_bytes {
+            // Don't read (or allocate) a byte of it, and don't trust the
+            // peer to resync either.
+            self.close();
+            return Err(TransportError::FrameTooLarge { len: len as u64, max: self.config.max_frame_bytes });
+        }
+        let mut payload = vec![0u8; len];
+        self.stream.read_exact(&mut payload)?;
+        let Some(auth) = &self.auth else { return Ok(payload) };
+        let mut input = payload.as_slice();
+        let tag = Vec::<u8>::decode(&mut input).map_err(|_| TransportError::AuthFailed)?;
+        if !auth.verify(input, &tag) {
+            return Err(TransportError::AuthFailed);
+        }
+        Ok(input.to_vec())
+    }
+
+    pub fn send<V: Wire>(&mut self, from: NodeId, msg: &PaxosMsg<V>) -> Result<(), TransportError> {
+        let mut payload = Vec::new();
+        from.encode(&mut payload);
+        msg.encode(&mut payload);
+        self.write_frame(&payload)
+    }
+
+    pub fn recv<V: Wire>(&mut self) -> Result<(NodeId, PaxosMsg<V>), TransportError> {
+        let payload = self.read_frame()?;
+        let mut input = payload.as_slice();
+        let from = NodeId::decode(&mut input)?;
+        Ok((from, wire::decode(input)?))
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl Connection<TcpStream> {
+    pub fn connect(addr: impl std::net::ToSocketAddrs, config: TransportConfig) -> Result<Self, TransportError> {
+        Ok(Self::new(TcpStream::connect(addr)?, config))
+    }
+}
+
+struct Queue<V> {
+    items: VecDeque<(NodeId, NodeId, PaxosMsg<V>)>,
+    closed: bool,
+}
+
+/// Bounded queue of outgoing messages between the (synchronous) role handlers
+/// and whatever writes to the connections. Clones share the same queue.
+pub struct Outbound<V> {
+    shared: Arc<(Mutex<Queue<V>>, Condvar)>,
+    capacity: usize,
+    policy: BackpressurePolicy,
+}
+
+impl<V> Clone for Outbound<V> {
+    fn clone(&self) -> Self {
+        Self { shared: Arc::clone(&self.shared), capacity: self.capacity, policy: self.policy }
+    }
+}
+
+impl<V> Outbound<V> {
+    pub fn new(config: &TransportConfig) -> Self {
+        let queue = Queue { items: VecDeque::new(), closed: false };
+        Self {
+            shared: Arc::new((Mutex::new(queue), Condvar::new())),
+            capacity: config.outbound_capacity.max(1),
+            policy: config.backpressure,
+        }
+    }
+
+    /// Queue `msg` from `from` for `to`, applying the policy if full.
+    pub fn push(&self, to: NodeId, from: NodeId, msg: PaxosMsg<V>) -> Result<(), TransportError> {
+        let (lock, cvar) = &*self.shared;
+        let mut q = lock.lock().unwrap();
+        while q.items.len() >= self.capacity {
+            if q.closed {
+                return Err(TransportError::Closed);
+            }
+            match self.policy {
+                BackpressurePolicy::Block => q = cvar.wait(q).unwrap(),
+                BackpressurePolicy::DropOldest => {
+                    q.items.pop_front();
+                }
+                BackpressurePolicy::Error => {
+                    return Err(TransportError::Backpressure { capacity: self.capacity });
+                }
+            }
+        }
+        if q.closed {
+            return Err(TransportError::Closed);
+        }
+        q.items.push_back((to, from, msg));
+        cvar.notify_all();
+        Ok(())
+    }
+
+    /// Queue every `Action::Send` in `actions`; other actions are returned
+    /// for the caller to handle (timers, decisions, ...).
+    pub fn push_actions(&self, actions: Vec<Action<V>>) -> Result<Vec<Action<V>>, TransportError> {
+        let mut rest = Vec::new();
+        for action in actions {
+            match action {
+                Action::Send { to, from, msg } => self.push(to, from, msg)?,
+                other => rest.push(other),
+            }
+        }
+        Ok(rest)
+    }
+
+    /// Next queued message without waiting, as `(to, from, msg)`.
+    pub fn try_pop(&self) -> Option<(NodeId, NodeId, PaxosMsg<V>)> {
+        let (lock, cvar) = &*self.shared;
+        let item = lock.lock().unwrap().items.pop_front();
+        if item.is_some() {
+            cvar.notify_all();
+        }
+        item
+    }
+
+    /// Wait for the next message; `None` once closed and drained.
+    pub fn pop(&self) -> Option<(NodeId, NodeId, PaxosMsg<V>)> {
+        let (lock, cvar) = &*self.shared;
+        let mut q = lock.lock().unwrap();
+        loop {
+            if let Some(item) = q.items.pop_front() {
+                cvar.notify_all();
+                return Some(item);
+            }
+            if q.closed {
+                return None;
+            }
+            q = cvar.wait(q).unwrap();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.0.lock().unwrap().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wake everyone up: pushes fail from now on, pops drain what's left.
+    pub fn close(&self) {
+        let (lock, cvar) = &*self.shared;
+        lock.lock().unwrap().closed = true;
+        cvar.notify_all();
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PoolConfig {
+    pub transport: TransportConfig,
+    /// Messages held per peer while it's disconnected; past that the oldest
+    /// go, as with `BackpressurePolicy::DropOldest`.
+    pub queue_capacity: usize,
+    /// Wait before the first reconnect; doubled per failed attempt.
+    pub backoff_base: Duration,
+    pub backoff_max: Duration,
+    /// Failed connects in a row before the peer is given up on. 0 never
+    /// gives up.
+    pub max_attempts: u32,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            transport: TransportConfig::default(),
+            queue_capacity: 1024,
+            backoff_base: Duration::from_millis(50),
+            backoff_max: Duration::from_secs(5),
+            max_attempts: 10,
+        }
+    }
+}
+
+struct Peer<V> {
+    addr: SocketAddr,
+    conn: Option<Connection<TcpStream>>,
+    queue: VecDeque<(NodeId, PaxosMsg<V>)>,
+    // Failed connects since the last good one
+    failures: u32,
+    retry_at: Option<Instant>,
+    gave_up: bool,
+}
+
+/// One persistent connection per peer, reconnected with backoff. Messages
+/// to a peer that's down are queued and go out, oldest first, as soon as a
+/// reconnect succeeds. Nothing runs in the background: reconnects happen
+/// on `send`, or on `flush` for peers with messages still queued, so call
+/// that now and then (say on every tick).
+pub struct PeerPool<V> {
+    config: PoolConfig,
+    peers: BTreeMap<NodeId, Peer<V>>,
+    dropped: u64,
+    // Makes the `FrameAuth` for each new connection
+    auth: Option<AuthFactory>,
+}
+
+type AuthFactory = Box<dyn Fn() -> Box<dyn FrameAuth + Send> + Send>;
+
+impl<V: Wire> PeerPool<V> {
+    pub fn new(addrs: impl IntoIterator<Item = (NodeId, SocketAddr)>, config: PoolConfig) -> Self {
+        let peers = addrs
+            .into_iter()
+            .map(|(id, addr)| (id, Peer { addr, conn: None, queue: VecDeque::new(), failures: 0, retry_at: None, gave_up: false }))
+            .collect();
+        Self { config, peers, dropped: 0, auth: None }
+    }
+
+    /// Sign and check frames on every connection the pool makes from now on,
+    /// each with its own `FrameAuth` from `auth`. As with
+    /// `Connection::set_auth`, peers need the same setting.
+    pub fn set_auth<A: FrameAuth + Send + 'static>(&mut self, auth: impl Fn() -> A + Send + 'static) {
+        self.auth = Some(Box::new(move || Box::new(auth())));
+    }
+
+    /// Queue `msg` for `to` and send whatever is queued for it, connecting
+    /// first if need be. Ok means sent or queued for later; a failure to
+    /// connect only surfaces once the peer is given up on.
+    pub fn send(&mut self, to: NodeId, from: NodeId, msg: PaxosMsg<V>) -> Result<(), TransportError> {
+        let capacity = self.config.queue_capacity.max(1);
+        let peer = self.peers.get_mut(&to).ok_or(TransportError::Unreachable { peer: to })?;
+        if peer.gave_up {
+            return Err(TransportError::Unreachable { peer: to });
+        }
+        if peer.queue.len() >= capacity {
+            peer.queue.pop_front();
+            self.dropped += 1;
+        }
+        peer.queue.push_back((from, msg));
+        self.flush_peer(to)
+    }
+
+    /// `send` every `Action::Send`; other actions are returned for the
+    /// caller to handle. Stops at the first unreachable peer.
+    pub fn send_actions(&mut self, actions: Vec<Action<V>>) -> Result<Vec<Action<V>>, TransportError> {
+        let mut rest = Vec::new();
+        for action in actions {
+            match action {
+                Action::Send { to, from, msg } => self.send(to, from, msg)?,
+                other => rest.push(other),
+            }
+        }
+        Ok(rest)
+    }
+
+    /// Retry every peer with messages queued whose backoff is up. The peers
+    /// given up on this time are returned.
+    pub fn flush(&mut self) -> Vec<NodeId> {
+        let pending: Vec<NodeId> = self.peers.iter().filter(|(_, p)| !p.gave_up && !p.queue.is_empty()).map(|(id, _)| *id).collect();
+        pending.into_iter().filter(|&id| self.flush_peer(id).is_err()).collect()
+    }
+
+    /// Suspect every peer we've given up on, so roles stop counting on them.
+    pub fn report_unreachable(&self, fd: &mut FailureDetector) {
+        for id in self.unreachable() {
+            fd.suspect(id);
+        }
+    }
+
+    pub fn unreachable(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.peers.iter().filter(|(_, p)| p.gave_up).map(|(id, _)| *id)
+    }
+
+    /// Start trying `peer` again, e.g. once it's been heard from.
+    pub fn revive(&mut self, peer: NodeId) {
+        if let Some(p) = self.peers.get_mut(&peer) {
+            p.gave_up = false;
+            p.failures = 0;
+            p.retry_at = None;
+        }
+    }
+
+    pub fn is_connected(&self, peer: NodeId) -> bool {
+        self.peers.get(&peer).is_some_and(|p| p.conn.is_some())
+    }
+
+    pub fn queued(&self, peer: NodeId) -> usize {
+        self.peers.get(&peer).map_or(0, |p| p.queue.len())
+    }
+
+    /// Messages thrown out of full queues.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn flush_peer(&mut self, id: NodeId) -> Result<(), TransportError> {
+        let config = self.config;
+        let Some(peer) = self.peers.get_mut(&id) else { return Err(TransportError::Unreachable { peer: id }) };
+        while !peer.queue.is_empty() {
+            if peer.conn.is_none() && !peer.connect(&config, self.auth.as_ref()) {
+                if peer.gave_up {
+                    peer.queue.clear();
+                    return Err(TransportError::Unreachable { peer: id });
+                }
+                return Ok(()); // still backing off
+            }
+            let (Some(conn), Some((from, msg))) = (peer.conn.as_mut(), peer.queue.front()) else { break };
+            if conn.send(*from, msg).is_err() {
+                // Broken connection: back off like a failed connect, then
+                // resend from this one on. It may have gone out already;
+                // Paxos messages are idempotent.
+                peer.conn = None;
+                peer.back_off(&config);
+                continue;
+            }
+            peer.queue.pop_front();
+        }
+        Ok(())
+    }
+}
+
+impl<V> Peer<V> {
+    /// Try to connect if the backoff allows; true if we now have a connection.
+    fn connect(&mut self, config: &PoolConfig, auth: Option<&AuthFactory>) -> bool {
+        if self.retry_at.is_some_and(|at| Instant::now() < at) {
+            return false;
+        }
+        match TcpStream::connect(self.addr) {
+            Ok(stream) => {
+                let mut conn = Connection::new(stream, config.transport);
+                conn.auth = auth.map(|f| f());
+                self.conn = Some(conn);
+                self.failures = 0;
+                self.retry_at = None;
+                true
+            }
+            Err(_) => {
+                self.back_off(config);
+                false
+            }
+        }
+    }
+
+    fn back_off(&mut self, config: &PoolConfig) {
+        self.failures += 1;
+        if config.max_attempts > 0 && self.failures >= config.max_attempts {
+            self.gave_up = true;
+        }
+        let backoff = config.backoff_base.saturating_mul(1 << (self.failures - 1).min(16)).min(config.backoff_max);
+        self.retry_at = Some(Instant::now() + backoff);
+    }
+}
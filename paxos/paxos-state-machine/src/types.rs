@@ -1,22 +1,138 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::PaxosError;
 use crate::msg::PaxosMsg;
+use crate::node_set::{NodeSet, SetBackend};
+use crate::wire::{self, DecodeError, Wire};
 pub type NodeId = u64;
 pub type ProposalId = (u64, NodeId);
-pub type TimerId = (u64, NodeId);
+/// Log position in Multi-Paxos. Single-decree Paxos just uses slot 0.
+pub type Slot = u64;
+
+/// What a timer is guarding, so `on_timeout` can route it to the right round.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TimerKind {
+    /// Phase 1 of a slot didn't reach a promise quorum in time.
+    Prepare(Slot),
+    /// Phase 2 of a slot didn't reach an accept quorum in time.
+    Accept(Slot),
+    /// Role-wide timer that isn't tied to any slot.
+    Watchdog,
+    /// A learner saw acks for a slot but no decision in time.
+    LearnStalled(Slot),
+    /// A proposer's startup delay ran out; time for its first Prepare.
+    Startup,
+}
+
+/// `seq` is per-node and monotonic, so a re-armed timer for the same kind
+/// never collides with the one it replaced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimerId {
+    pub seq: u64,
+    pub node: NodeId,
+    pub kind: TimerKind,
+}
 
-#[derive(Clone, Copy)]
+/// Decides whether a set of voters is a quorum, for quorum systems that
+/// aren't a count or a weight (grids, trees, ...). Any two quorums it
+/// accepts must intersect, or Paxos is unsafe; that's on the implementor.
+pub trait QuorumChecker {
+    fn is_quorum(&self, voters: &NodeSet) -> bool;
+}
+
+/// More than half of `of` nodes, the classic rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Majority {
+    pub of: u64,
+}
+
+impl QuorumChecker for Majority {
+    fn is_quorum(&self, voters: &NodeSet) -> bool {
+        voters.len() as u64 * 2 > self.of
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct NodeContext {
     pub number_of_nodes: u64,
+    /// Optional vote weight per node. `None` is plain majority-by-count.
+    pub weights: Option<HashMap<NodeId, u64>>,
+    /// What the proposer's and learner's vote tallies are made of. `Bits`
+    /// saves an allocation per round when node ids are small.
+    pub set_backend: SetBackend,
+    /// Overrides both the count and the weights when set; every role built
+    /// from this context then asks it instead.
+    pub quorum_checker: Option<Arc<dyn QuorumChecker + Send + Sync>>,
 }
-pub struct AcceptorState<V> {
-    highest_promise: Option<ProposalId>,
-    accepted_id: Option<ProposalId>,
-    accepted_value: Option<V>,
+
+impl fmt::Debug for NodeContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeContext")
+            .field("number_of_nodes", &self.number_of_nodes)
+            .field("weights", &self.weights)
+            .field("set_backend", &self.set_backend)
+            .field("quorum_checker", &self.quorum_checker.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl NodeContext {
+    pub fn new(number_of_nodes: u64) -> Self {
+        Self { number_of_nodes, weights: None, set_backend: SetBackend::Hash, quorum_checker: None }
+    }
+
+    /// One entry per node; nodes missing from the map carry no vote.
+    pub fn weighted(weights: HashMap<NodeId, u64>) -> Self {
+        Self { number_of_nodes: weights.len() as u64, weights: Some(weights), set_backend: SetBackend::Hash, quorum_checker: None }
+    }
+
+    /// An empty tally with the configured backend.
+    pub fn node_set(&self) -> NodeSet {
+        NodeSet::new(self.set_backend)
+    }
+
+    pub fn total_weight(&self) -> u64 {
+        match &self.weights {
+            Some(w) => w.values().sum(),
+            None => self.number_of_nodes,
+        }
+    }
+
+    pub fn weight_of(&self, node: NodeId) -> u64 {
+        match &self.weights {
+            Some(w) => w.get(&node).copied().unwrap_or(0),
+            None => 1,
+        }
+    }
+
+    pub fn set_quorum_checker(&mut self, checker: impl QuorumChecker + Send + Sync + 'static) {
+        self.quorum_checker = Some(Arc::new(checker));
+    }
+
+    /// The `quorum_checker` if there is one. Otherwise, with weights,
+    /// `voters` are a quorum once their summed weight is more than half the
+    /// total, and without weights it's a plain count against `quorum`.
+    pub fn is_quorum(&self, voters: &NodeSet, quorum: usize) -> bool {
+        if let Some(checker) = &self.quorum_checker {
+            return checker.is_quorum(voters);
+        }
+        match &self.weights {
+            Some(_) => {
+                let votes: u64 = voters.iter().map(|n| self.weight_of(n)).sum();
+                votes * 2 > self.total_weight()
+            }
+            None => voters.len() >= quorum,
+        }
+    }
 }
 /// Represents the different phases of the Paxos protocol, these events
 /// are fed to the state machine to trigger transitions.
 /// The events defined are:
 /// 1. Message: Represents an incoming Paxos message from another node.
 /// 2. Timeout: Represents a timeout event, 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Event<V> {
     Message { from: NodeId, msg: PaxosMsg<V> },
     Timeout { id: TimerId  },
@@ -27,6 +143,14 @@ pub trait HandlesEvents<V: Clone> {
     fn on_init(&mut self) -> Vec<Action<V>> { vec![] }
     /// Handle an inbound Paxos message.
     fn on_message(&mut self, from: NodeId, msg: PaxosMsg<V>) -> Vec<Action<V>>;
+    /// Handle one already framed, still encoded message: `wire::decode`
+    /// then `on_message`. Nothing is dispatched if it doesn't decode.
+    fn on_wire(&mut self, from: NodeId, bytes: &[u8]) -> Result<Vec<Action<V>>, DecodeError>
+    where
+        V: Wire,
+    {
+        Ok(self.on_message(from, wire::decode(bytes)?))
+    }
     /// Handle a timeout (default: ignore).
     fn on_timeout(&mut self, _id: TimerId) -> Vec<Action<V>> { vec![] }
     /// Unified dispatcher you can feed into your scheduler.
@@ -36,12 +160,59 @@ pub trait HandlesEvents<V: Clone> {
             Event::Timeout { id }        => self.on_timeout(id),
         }
     }
+    /// Handle a batch of events that are ready together, in order. With
+    /// `dedup`, a Send identical to one earlier in the batch's output is
+    /// dropped: Paxos messages are idempotent, so the second copy is just
+    /// traffic.
+    fn on_events(&mut self, events: Vec<Event<V>>, dedup: bool) -> Vec<Action<V>>
+    where
+        V: PartialEq,
+    {
+        let mut out: Vec<Action<V>> = Vec::new();
+        for e in events {
+            for a in self.on_event(e) {
+                if dedup && matches!(a, Action::Send { .. }) && out.contains(&a) {
+                    continue;
+                }
+                out.push(a);
+            }
+        }
+        out
+    }
+}
+/// Lets boxed roles (e.g. `Box<dyn HandlesEvents<V>>`) be driven like any other.
+impl<V: Clone, R: HandlesEvents<V> + ?Sized> HandlesEvents<V> for Box<R> {
+    fn on_init(&mut self) -> Vec<Action<V>> { (**self).on_init() }
+    fn on_message(&mut self, from: NodeId, msg: PaxosMsg<V>) -> Vec<Action<V>> { (**self).on_message(from, msg) }
+    fn on_timeout(&mut self, id: TimerId) -> Vec<Action<V>> { (**self).on_timeout(id) }
+    fn on_event(&mut self, e: Event<V>) -> Vec<Action<V>> { (**self).on_event(e) }
+    fn on_events(&mut self, events: Vec<Event<V>>, dedup: bool) -> Vec<Action<V>> where V: PartialEq { (**self).on_events(events, dedup) }
 }
 // ---------- Outputs from the core ----------
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Action<V> {
     Send { to: NodeId, from: NodeId, msg: PaxosMsg<V> },
     SetTimer { id: TimerId, ms: u64 },
     CancelTimer { id: TimerId },
     ProposeValue { v: V },
-    ChoseValue { v: V },
+    /// `v` was chosen in `slot` (always 0 for single-decree use);
+    /// `request_id` is the client request traced on the round that got it
+    /// chosen, if any (see `Proposer::propose_traced`).
+    ChoseValue { slot: Slot, v: V, request_id: Option<u64> },
+    /// `propose_slot` found `slot` already decided, on `existing`.
+    AlreadyChosen { slot: Slot, existing: V },
+    /// A compare-and-set propose found `existing` accepted in `slot` and
+    /// gave up. It may or may not be chosen yet; ask a learner.
+    OtherValueAccepted { slot: Slot, existing: V },
+    /// A majority of learners in a `LearnerGroup` have decided `slot`.
+    WidelyLearned { slot: Slot },
+    /// Too few live peers are left to ever form a quorum; the proposer has
+    /// gone idle until that changes.
+    QuorumUnreachable { live: usize, needed: usize },
+    /// A learner heard about `slot` but it hasn't been decided within its
+    /// stall timeout; someone may need to re-propose it.
+    LearnStalled { slot: Slot },
+    /// Something that shouldn't happen did; only reported by roles asked to
+    /// be strict about it.
+    Error { error: PaxosError },
 }
\ No newline at end of file
@@ -0,0 +1,495 @@
+// Binary wire format for PaxosMsg.
+//
+// Layout is little-endian, one tag byte per enum variant / Option, and u32
+// length prefixes for variable sized values. Decoding never trusts a length
+// prefix: it is checked against the remaining input before anything is
+// allocated, so a hostile frame can't make us reserve memory it doesn't carry.
+use std::fmt;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::{acceptor::AcceptorPersisted, msg::PaxosMsg, proposer::Proposal, types::*};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Input ended before the value was complete.
+    Truncated { needed: usize, remaining: usize },
+    /// A variant/option tag we don't know about.
+    UnknownTag { tag: u8 },
+    /// A length prefix larger than the bytes that follow it.
+    LengthTooLarge { len: u64, remaining: usize },
+    /// A string payload that isn't valid UTF-8.
+    InvalidUtf8,
+    /// The message decoded fine but bytes were left over.
+    TrailingBytes { extra: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { needed, remaining } => {
+                write!(f, "truncated input: needed {needed} bytes, {remaining} left")
+            }
+            DecodeError::UnknownTag { tag } => write!(f, "unknown tag {tag}"),
+            DecodeError::LengthTooLarge { len, remaining } => {
+                write!(f, "length prefix {len} exceeds remaining {remaining} bytes")
+            }
+            DecodeError::InvalidUtf8 => write!(f, "invalid utf-8 in string"),
+            DecodeError::TrailingBytes { extra } => write!(f, "{extra} trailing bytes"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Values that know how to put themselves on the wire.
+/// `decode` consumes from the front of `input`.
+pub trait Wire: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// Encode a message into a fresh buffer.
+pub fn encode<V: Wire>(msg: &PaxosMsg<V>) -> Vec<u8> {
+    let mut out = Vec::new();
+    msg.encode(&mut out);
+    out
+}
+
+/// Decode exactly one message; leftover bytes are an error.
+pub fn decode<V: Wire>(bytes: &[u8]) -> Result<PaxosMsg<V>, DecodeError> {
+    decode_exact(bytes)
+}
+
+/// Decode exactly one `T`; leftover bytes are an error.
+pub fn decode_exact<T: Wire>(bytes: &[u8]) -> Result<T, DecodeError> {
+    let mut input = bytes;
+    let value = T::decode(&mut input)?;
+    if !input.is_empty() {
+        return Err(DecodeError::TrailingBytes { extra: input.len() });
+    }
+    Ok(value)
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < n {
+        return Err(DecodeError::Truncated { needed: n, remaining: input.len() });
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Ok(head)
+}
+
+fn take_u8(input: &mut &[u8]) -> Result<u8, DecodeError> {
+    Ok(take(input, 1)?[0])
+}
+
+/// Read a u32 length prefix and make sure that many bytes are actually there.
+fn take_len(input: &mut &[u8]) -> Result<usize, DecodeError> {
+    let len = u32::decode(input)? as usize;
+    if len > input.len() {
+        return Err(DecodeError::LengthTooLarge { len: len as u64, remaining: input.len() });
+    }
+    Ok(len)
+}
+
+fn put_len(len: usize, out: &mut Vec<u8>) {
+    let len = u32::try_from(len).expect("wire values are limited to u32::MAX bytes");
+    len.encode(out);
+}
+
+impl Wire for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let b = take(input, 4)?;
+        Ok(u32::from_le_bytes(b.try_into().unwrap()))
+    }
+}
+
+impl Wire for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let b = take(input, 8)?;
+        Ok(u64::from_le_bytes(b.try_into().unwrap()))
+    }
+}
+
+impl Wire for Vec<u8> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_len(self.len(), out);
+        out.extend_from_slice(self);
+    }
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = take_len(input)?;
+        Ok(take(input, len)?.to_vec())
+    }
+}
+
+impl Wire for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_len(self.len(), out);
+        out.extend_from_slice(self.as_bytes());
+    }
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = take_len(input)?;
+        let bytes = take(input, len)?;
+        std::str::from_utf8(bytes)
+            .map(str::to_owned)
+            .map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+/// For values that are expensive to clone: run the roles with `Arc<T>` as
+/// the value type and every broadcast clones a pointer, not the payload. It
+/// goes on the wire exactly as `T` does.
+impl<T: Wire> Wire for Arc<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (**self).encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Arc::new(T::decode(input)?))
+    }
+}
+
+impl<A: Wire, B: Wire> Wire for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+// Count prefixed list. Every entry takes at least a byte, so a count larger
+// than the remaining input can be refused before reserving anything.
+impl<A: Wire, B: Wire> Wire for Vec<(A, B)> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        put_len(self.len(), out);
+        for entry in self {
+            entry.encode(out);
+        }
+    }
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = take_len(input)?;
+        let mut entries = Vec::with_capacity(len);
+        for _ in 0..len {
+            entries.push(<(A, B)>::decode(input)?);
+        }
+        Ok(entries)
+    }
+}
+
+impl<T: Wire> Wire for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(v) => {
+                out.push(1);
+                v.encode(out);
+            }
+        }
+    }
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match take_u8(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(DecodeError::UnknownTag { tag }),
+        }
+    }
+}
+
+impl<V: Wire> Wire for Proposal<V> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.id.encode(out);
+        self.value.encode(out);
+    }
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Proposal { id: ProposalId::decode(input)?, value: V::decode(input)? })
+    }
+}
+
+// Slot map as a count prefixed list of (slot, proposal), in slot order.
+impl<V: Wire> Wire for AcceptorPersisted<V> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.latest_promise.encode(out);
+        put_len(self.accepted.len(), out);
+        for (slot, proposal) in &self.accepted {
+            slot.encode(out);
+            proposal.encode(out);
+        }
+    }
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let latest_promise = Option::decode(input)?;
+        let len = take_len(input)?;
+        let mut accepted = BTreeMap::new();
+        for _ in 0..len {
+            accepted.insert(Slot::decode(input)?, Proposal::decode(input)?);
+        }
+        Ok(AcceptorPersisted { latest_promise, accepted })
+    }
+}
+
+// Variant tags. Append only, never renumber.
+const TAG_PREPARE: u8 = 0;
+const TAG_PROMISE: u8 = 1;
+const TAG_ACCEPT_PROPOSAL: u8 = 2;
+const TAG_ACCEPTED: u8 = 3;
+const TAG_LEARN: u8 = 4;
+const TAG_NACK: u8 = 5;
+const TAG_QUERY_CHOSEN: u8 = 6;
+const TAG_CHOSEN_REPLY: u8 = 7;
+const TAG_PROMISE_ID_ONLY: u8 = 8;
+const TAG_FETCH_ACCEPTED: u8 = 9;
+const TAG_ACCEPTED_VALUE: u8 = 10;
+const TAG_LEARN_DIGEST: u8 = 11;
+const TAG_CATCH_UP_REQUEST: u8 = 12;
+const TAG_CATCH_UP_RESPONSE: u8 = 13;
+const TAG_CHOSEN: u8 = 14;
+const TAG_PROMISE_EMPTY: u8 = 15;
+// AcceptProposal with a commit watermark; without one it stays TAG_ACCEPT_PROPOSAL
+const TAG_ACCEPT_PROPOSAL_COMMITTED: u8 = 16;
+// AcceptProposal and Accepted carrying a request id
+const TAG_ACCEPT_PROPOSAL_TRACED: u8 = 17;
+const TAG_ACCEPTED_TRACED: u8 = 18;
+const TAG_LEADER_HANDOFF: u8 = 19;
+const TAG_PREPARE_FROM: u8 = 20;
+const TAG_PROMISE_FROM: u8 = 21;
+
+impl<V: Wire> Wire for PaxosMsg<V> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            PaxosMsg::Prepare { slot, proposal_id, from } => {
+                out.push(TAG_PREPARE);
+                slot.encode(out);
+                proposal_id.encode(out);
+                from.encode(out);
+            }
+            PaxosMsg::Promise { slot, accepted_proposal, proposal_response } => {
+                out.push(TAG_PROMISE);
+                slot.encode(out);
+                accepted_proposal.encode(out);
+                proposal_response.encode(out);
+            }
+            PaxosMsg::AcceptProposal { slot, proposal_id, value, committed_up_to, request_id: Some(request_id) } => {
+                out.push(TAG_ACCEPT_PROPOSAL_TRACED);
+                slot.encode(out);
+                proposal_id.encode(out);
+                value.encode(out);
+                committed_up_to.encode(out);
+                request_id.encode(out);
+            }
+            PaxosMsg::AcceptProposal { slot, proposal_id, value, committed_up_to, request_id: None } => {
+                out.push(if committed_up_to.is_some() { TAG_ACCEPT_PROPOSAL_COMMITTED } else { TAG_ACCEPT_PROPOSAL });
+                slot.encode(out);
+                proposal_id.encode(out);
+                value.encode(out);
+                if let Some(committed) = committed_up_to {
+                    committed.encode(out);
+                }
+            }
+            PaxosMsg::Accepted { slot, proposal, request_id } => {
+                out.push(if request_id.is_some() { TAG_ACCEPTED_TRACED } else { TAG_ACCEPTED });
+                slot.encode(out);
+                proposal.encode(out);
+                if let Some(request_id) = request_id {
+                    request_id.encode(out);
+                }
+            }
+            PaxosMsg::Learn { slot, proposal_id, value } => {
+                out.push(TAG_LEARN);
+                slot.encode(out);
+                proposal_id.encode(out);
+                value.encode(out);
+            }
+            PaxosMsg::Nack { slot, proposal_id, promised } => {
+                out.push(TAG_NACK);
+                slot.encode(out);
+                proposal_id.encode(out);
+                promised.encode(out);
+            }
+            PaxosMsg::QueryChosen { slot } => {
+                out.push(TAG_QUERY_CHOSEN);
+                slot.encode(out);
+            }
+            PaxosMsg::ChosenReply { slot, value } => {
+                out.push(TAG_CHOSEN_REPLY);
+                slot.encode(out);
+                value.encode(out);
+            }
+            PaxosMsg::PromiseIdOnly { slot, accepted_id, proposal_response } => {
+                out.push(TAG_PROMISE_ID_ONLY);
+                slot.encode(out);
+                accepted_id.encode(out);
+                proposal_response.encode(out);
+            }
+            PaxosMsg::FetchAccepted { slot, proposal_id } => {
+                out.push(TAG_FETCH_ACCEPTED);
+                slot.encode(out);
+                proposal_id.encode(out);
+            }
+            PaxosMsg::AcceptedValue { slot, proposal } => {
+                out.push(TAG_ACCEPTED_VALUE);
+                slot.encode(out);
+                proposal.encode(out);
+            }
+            PaxosMsg::LearnDigest { base, up_to, bitmap } => {
+                out.push(TAG_LEARN_DIGEST);
+                base.encode(out);
+                up_to.encode(out);
+                bitmap.encode(out);
+            }
+            PaxosMsg::CatchUpRequest { start, end } => {
+                out.push(TAG_CATCH_UP_REQUEST);
+                start.encode(out);
+                end.encode(out);
+            }
+            PaxosMsg::CatchUpResponse { entries, next, end } => {
+                out.push(TAG_CATCH_UP_RESPONSE);
+                entries.encode(out);
+                next.encode(out);
+                end.encode(out);
+            }
+            PaxosMsg::Chosen { slot, proposal_id } => {
+                out.push(TAG_CHOSEN);
+                slot.encode(out);
+                proposal_id.encode(out);
+            }
+            PaxosMsg::PromiseEmpty { slot, proposal_response } => {
+                out.push(TAG_PROMISE_EMPTY);
+                slot.encode(out);
+                proposal_response.encode(out);
+            }
+            PaxosMsg::LeaderHandoff { to, round } => {
+                out.push(TAG_LEADER_HANDOFF);
+                to.encode(out);
+                round.encode(out);
+            }
+            PaxosMsg::PrepareFrom { from_slot, proposal_id, from } => {
+                out.push(TAG_PREPARE_FROM);
+                from_slot.encode(out);
+                proposal_id.encode(out);
+                from.encode(out);
+            }
+            PaxosMsg::PromiseFrom { from_slot, proposal_response, accepted } => {
+                out.push(TAG_PROMISE_FROM);
+                from_slot.encode(out);
+                proposal_response.encode(out);
+                accepted.encode(out);
+            }
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match take_u8(input)? {
+            TAG_PREPARE => Ok(PaxosMsg::Prepare {
+                slot: Slot::decode(input)?,
+                proposal_id: ProposalId::decode(input)?,
+                from: NodeId::decode(input)?,
+            }),
+            TAG_PROMISE => Ok(PaxosMsg::Promise {
+                slot: Slot::decode(input)?,
+                accepted_proposal: Option::decode(input)?,
+                proposal_response: ProposalId::decode(input)?,
+            }),
+            TAG_ACCEPT_PROPOSAL => Ok(PaxosMsg::AcceptProposal {
+                slot: Slot::decode(input)?,
+                proposal_id: ProposalId::decode(input)?,
+                value: V::decode(input)?,
+                committed_up_to: None,
+                request_id: None,
+            }),
+            TAG_ACCEPT_PROPOSAL_COMMITTED => Ok(PaxosMsg::AcceptProposal {
+                slot: Slot::decode(input)?,
+                proposal_id: ProposalId::decode(input)?,
+                value: V::decode(input)?,
+                committed_up_to: Some(Slot::decode(input)?),
+                request_id: None,
+            }),
+            TAG_ACCEPT_PROPOSAL_TRACED => Ok(PaxosMsg::AcceptProposal {
+                slot: Slot::decode(input)?,
+                proposal_id: ProposalId::decode(input)?,
+                value: V::decode(input)?,
+                committed_up_to: Option::decode(input)?,
+                request_id: Some(u64::decode(input)?),
+            }),
+            TAG_ACCEPTED => Ok(PaxosMsg::Accepted {
+                slot: Slot::decode(input)?,
+                proposal: Proposal::decode(input)?,
+                request_id: None,
+            }),
+            TAG_ACCEPTED_TRACED => Ok(PaxosMsg::Accepted {
+                slot: Slot::decode(input)?,
+                proposal: Proposal::decode(input)?,
+                request_id: Some(u64::decode(input)?),
+            }),
+            TAG_LEARN => Ok(PaxosMsg::Learn {
+                slot: Slot::decode(input)?,
+                proposal_id: ProposalId::decode(input)?,
+                value: V::decode(input)?,
+            }),
+            TAG_NACK => Ok(PaxosMsg::Nack {
+                slot: Slot::decode(input)?,
+                proposal_id: ProposalId::decode(input)?,
+                promised: ProposalId::decode(input)?,
+            }),
+            TAG_QUERY_CHOSEN => Ok(PaxosMsg::QueryChosen { slot: Slot::decode(input)? }),
+            TAG_CHOSEN_REPLY => Ok(PaxosMsg::ChosenReply {
+                slot: Slot::decode(input)?,
+                value: Option::decode(input)?,
+            }),
+            TAG_PROMISE_ID_ONLY => Ok(PaxosMsg::PromiseIdOnly {
+                slot: Slot::decode(input)?,
+                accepted_id: Option::decode(input)?,
+                proposal_response: ProposalId::decode(input)?,
+            }),
+            TAG_FETCH_ACCEPTED => Ok(PaxosMsg::FetchAccepted {
+                slot: Slot::decode(input)?,
+                proposal_id: ProposalId::decode(input)?,
+            }),
+            TAG_ACCEPTED_VALUE => Ok(PaxosMsg::AcceptedValue {
+                slot: Slot::decode(input)?,
+                proposal: Proposal::decode(input)?,
+            }),
+            TAG_LEARN_DIGEST => Ok(PaxosMsg::LearnDigest {
+                base: Slot::decode(input)?,
+                up_to: Slot::decode(input)?,
+                bitmap: Vec::decode(input)?,
+            }),
+            TAG_CATCH_UP_REQUEST => Ok(PaxosMsg::CatchUpRequest {
+                start: Slot::decode(input)?,
+                end: Slot::decode(input)?,
+            }),
+            TAG_CATCH_UP_RESPONSE => Ok(PaxosMsg::CatchUpResponse {
+                entries: Vec::decode(input)?,
+                next: Option::decode(input)?,
+                end: Slot::decode(input)?,
+            }),
+            TAG_CHOSEN => Ok(PaxosMsg::Chosen {
+                slot: Slot::decode(input)?,
+                proposal_id: ProposalId::decode(input)?,
+            }),
+            TAG_PROMISE_EMPTY => Ok(PaxosMsg::PromiseEmpty {
+                slot: Slot::decode(input)?,
+                proposal_response: ProposalId::decode(input)?,
+            }),
+            TAG_LEADER_HANDOFF => Ok(PaxosMsg::LeaderHandoff { to: NodeId::decode(input)?, round: u64::decode(input)? }),
+            TAG_PREPARE_FROM => Ok(PaxosMsg::PrepareFrom {
+                from_slot: Slot::decode(input)?,
+                proposal_id: ProposalId::decode(input)?,
+                from: NodeId::decode(input)?,
+            }),
+            TAG_PROMISE_FROM => Ok(PaxosMsg::PromiseFrom {
+                from_slot: Slot::decode(input)?,
+                proposal_response: ProposalId::decode(input)?,
+                accepted: Vec::decode(input)?,
+            }),
+            tag => Err(DecodeError::UnknownTag { tag }),
+        }
+    }
+}
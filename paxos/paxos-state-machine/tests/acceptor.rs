@@ -0,0 +1,247 @@
+// Acceptors answering Prepares and AcceptProposals, and what they keep.
+mod common;
+
+use std::collections::HashSet;
+
+use common::*;
+use paxos_state_machine::{acceptor::{Acceptor, AcceptorMetrics, AcceptorPersisted, NackMode, Rejection}, config::PaxosConfig, learner::Learner, msg::PaxosMsg, proposer::Proposal, sim::MemStore, types::*, wire};
+
+fn acceptor() -> Acceptor<u64> {
+    Acceptor::new(1, NodeContext::new(3), HashSet::from([LEARNER]))
+}
+
+#[test]
+fn metrics_count_what_was_granted() {
+    let mut a = acceptor();
+    a.set_nack_mode(NackMode::Enabled);
+    a.on_message(2, prepare(0, (5, 2)));
+    a.on_message(3, prepare(0, (3, 3)));
+    a.on_message(2, accept(0, (5, 2), 1));
+    assert_eq!(a.metrics(), AcceptorMetrics { prepares_seen: 2, promises_granted: 1, accepts_granted: 1, nacks_sent: 1 });
+}
+
+#[test]
+fn rejection_log_keeps_the_latest() {
+    let mut a = acceptor();
+    a.set_rejection_log(2);
+    a.on_message(3, prepare(0, (5, 3)));
+    a.on_message(1, prepare(0, (1, 1)));
+    a.on_message(2, prepare(0, (2, 2)));
+    assert_eq!(a.recent_rejections().copied().collect::<Vec<_>>(), [
+        Rejection { proposer: 1, attempted: (1, 1), promised: (5, 3) },
+        Rejection { proposer: 2, attempted: (2, 2), promised: (5, 3) },
+    ]);
+    a.on_message(1, prepare(0, (3, 1)));
+    assert_eq!(a.recent_rejections().next().unwrap().proposer, 2);
+    assert_eq!(a.recent_rejections().count(), 2);
+}
+
+#[test]
+fn joining_acceptor_stays_quiet_until_bootstrapped() {
+    let mut old = acceptor();
+    old.on_message(2, prepare(0, (4, 2)));
+    old.on_message(2, accept(0, (4, 2), 9));
+    let mut new: Acceptor<u64> = Acceptor::joining(4, NodeContext::new(3), HashSet::new());
+    new.set_nack_mode(NackMode::Enabled);
+    assert!(new.on_message(3, prepare(0, (1, 3))).is_empty());
+    new.bootstrap(old.watermark());
+    let out = new.on_message(3, prepare(0, (1, 3)));
+    assert!(matches!(&out[..], [Action::Send { msg: PaxosMsg::Nack { promised: (4, 2), .. }, .. }]), "{out:?}");
+    let out = new.on_message(3, prepare(0, (5, 3)));
+    assert!(matches!(&out[..], [Action::Send { msg: PaxosMsg::PromiseEmpty { .. }, .. }]), "{out:?}");
+}
+
+#[test]
+fn persisted_state_round_trips_and_restores() {
+    let ctx = NodeContext::new(3);
+    let accept = |slot, value: &str| PaxosMsg::AcceptProposal { slot, proposal_id: (3, 2), value: value.to_string(), committed_up_to: None, request_id: None };
+    let mut a: Acceptor<String> = Acceptor::new(1, ctx.clone(), HashSet::from([LEARNER]));
+    a.on_message(2, PaxosMsg::Prepare { slot: 0, proposal_id: (3, 2), from: 2 });
+    a.on_message(2, accept(0, "a"));
+    a.on_message(2, accept(4, "b"));
+    a.on_message(3, PaxosMsg::Prepare { slot: 1, proposal_id: (5, 3), from: 3 });
+    let bytes = a.persisted().to_bytes();
+    let state = AcceptorPersisted::<String>::from_bytes(&bytes).unwrap();
+    assert_eq!(state, a.persisted());
+
+    let mut b: Acceptor<String> = Acceptor::new(1, ctx, HashSet::from([LEARNER]));
+    b.restore(state);
+    for id in [(1, 1), (3, 2), (5, 2), (5, 3), (5, 4), (6, 1)] {
+        assert_eq!(a.can_promise(id), b.can_promise(id));
+        assert_eq!(a.can_accept(id), b.can_accept(id));
+    }
+    let p = PaxosMsg::Prepare { slot: 4, proposal_id: (7, 2), from: 2 };
+    assert_eq!(a.on_message(2, p.clone()), b.on_message(2, p));
+
+    assert!(AcceptorPersisted::<String>::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    let mut trailing = bytes;
+    trailing.push(0);
+    assert!(AcceptorPersisted::<String>::from_bytes(&trailing).is_err());
+}
+
+#[test]
+fn reset_keeps_promises_unless_durable_state_goes_too() {
+    let mut a = acceptor();
+    a.on_message(2, prepare(0, (5, 2)));
+    a.reset();
+    assert_eq!(a.metrics().prepares_seen, 0);
+    assert!(!a.can_promise((4, 2)));
+    a.reset_durable();
+    assert!(a.can_promise((4, 2)));
+}
+
+#[test]
+fn wire_bytes_dispatch_like_messages() {
+    let (mut a, b) = (acceptor(), acceptor());
+    let mut b: Box<dyn HandlesEvents<u64>> = Box::new(b);
+    assert_eq!(a.on_wire(2, &wire::encode(&prepare(0, (3, 2)))).unwrap(), b.on_message(2, prepare(0, (3, 2))));
+    assert!(a.on_wire(2, &[0xff, 1, 2]).is_err());
+    assert!(b.on_wire(2, &[]).is_err());
+}
+
+// How many acks `a` fans out to learners for a fresh accept
+fn fanout(a: &mut Acceptor<u64>) -> usize {
+    sends(&a.on_message(2, accept(0, (1, 2), 5))).iter().filter(|(_, to, m)| *to != 2 && matches!(m, PaxosMsg::Accepted { .. })).count()
+}
+
+#[test]
+fn distinguished_learner_gets_the_only_ack() {
+    let learners = HashSet::from([7, 8, 9]);
+    let mut a: Acceptor<u64> = Acceptor::new(1, NodeContext::new(3), learners.clone());
+    assert_eq!(fanout(&mut a), 3);
+    let mut a: Acceptor<u64> = Acceptor::new(1, NodeContext::new(3), learners);
+    a.set_distinguished_learner(Some(7));
+    assert_eq!(fanout(&mut a), 1);
+
+    // It relays; the others take one relay as decisive.
+    let mut d: Learner<u64> = Learner::new(7, NodeContext::new(3), 2);
+    d.relay_to([8]);
+    let mut other: Learner<u64> = Learner::new(8, NodeContext::new(3), 2);
+    other.set_relay_threshold(1);
+    d.on_message(2, accepted(0, (1, 1), 5));
+    for (from, _, m) in sends(&d.on_message(3, accepted(0, (1, 1), 5))) {
+        other.on_message(from, m);
+    }
+    assert_eq!(other.get_chosen(0), Some(&5));
+}
+
+#[test]
+fn exposes_its_promise_and_accepted_values() {
+    let mut a = acceptor();
+    assert_eq!(a.promised(), None);
+    a.on_message(2, prepare(3, (2, 2)));
+    assert_eq!(a.promised(), Some((2, 2)));
+    assert!(a.accepted(3).is_none());
+    a.on_message(2, accept(3, (2, 2), 9));
+    let p = a.accepted(3).unwrap();
+    assert_eq!((p.id(), *p.value()), ((2, 2), 9));
+    assert!(a.accepted(4).is_none());
+}
+
+#[test]
+fn fresh_slot_gets_an_empty_promise() {
+    let mut a = acceptor();
+    let (_, _, promise) = sends(&a.on_message(2, prepare(0, (0, 2)))).remove(0);
+    assert_eq!(promise, PaxosMsg::PromiseEmpty { slot: 0, proposal_response: (0, 2) });
+    assert_eq!(wire::decode::<u64>(&wire::encode(&promise)).unwrap(), promise);
+}
+
+// The bytes of everything a fresh acceptor with many learners sends for one accept
+fn fanout_bytes() -> Vec<u8> {
+    let mut a: Acceptor<u64> = Acceptor::new(1, NodeContext::new(3), (1..=40).collect());
+    let mut bytes = vec![];
+    for (_, to, m) in sends(&a.on_message(2, accept(0, (1, 2), 9))) {
+        bytes.extend(to.to_le_bytes());
+        bytes.extend(wire::encode(&m));
+    }
+    bytes
+}
+
+#[test]
+fn learner_fanout_order_is_stable() {
+    assert_eq!(fanout_bytes(), fanout_bytes());
+}
+
+#[test]
+fn commit_watermark_only_moves_forward() {
+    let mut a = acceptor();
+    let accept_with = |slot, committed_up_to| PaxosMsg::AcceptProposal { slot, proposal_id: (1, 2), value: 9, committed_up_to, request_id: None };
+    assert_eq!(a.committed_up_to(), None);
+    a.on_message(2, accept_with(5, Some(4)));
+    assert_eq!(a.committed_up_to(), Some(4));
+    a.on_message(2, accept_with(6, Some(2)));
+    assert_eq!(a.committed_up_to(), Some(4));
+    for c in [None, Some(4)] {
+        let m = accept_with(5, c);
+        assert_eq!(wire::decode::<u64>(&wire::encode(&m)).unwrap(), m);
+    }
+}
+
+#[test]
+fn a_batch_persists_once() {
+    let disk = MemStore::new();
+    let mut a = acceptor();
+    a.set_store(disk.clone());
+    let prepares = (0..3).map(|slot| Event::Message { from: 2, msg: prepare(slot, (1, 2)) }).collect();
+    let out = a.on_events(prepares, false);
+    assert_eq!(out.len(), 3);
+    assert!(out.iter().all(|a| matches!(a, Action::Send { msg: PaxosMsg::PromiseEmpty { .. }, .. })));
+    assert_eq!(disk.writes(), 1);
+    a.on_message(2, accept(0, (1, 2), 5));
+    assert_eq!(disk.writes(), 2);
+    // A repeated Prepare changes nothing, so there's nothing to write.
+    a.on_message(2, prepare(0, (1, 2)));
+    assert_eq!(disk.writes(), 2);
+    let mut b: Box<dyn HandlesEvents<u64>> = Box::new(a);
+    b.on_events(vec![Event::Message { from: 3, msg: prepare(1, (2, 3)) }, Event::Message { from: 3, msg: accept(1, (2, 3), 1) }], false);
+    assert_eq!(disk.writes(), 3);
+}
+
+#[test]
+fn starts_from_saved_state() {
+    let mut a: Acceptor<u64> = Acceptor::with_state(1, NodeContext::new(3), HashSet::new(), Some((7, 2)), [(0, Proposal::new((5, 3), 9))]);
+    a.set_nack_mode(NackMode::Enabled);
+    let out = a.on_message(4, prepare(0, (6, 4)));
+    assert!(matches!(&out[..], [Action::Send { msg: PaxosMsg::Nack { promised: (7, 2), .. }, .. }]), "{out:?}");
+    let out = a.on_message(4, prepare(0, (8, 4)));
+    assert!(matches!(&out[..], [Action::Send { msg: PaxosMsg::Promise { .. }, .. }]), "{out:?}");
+    // The promise is raised to cover what was already accepted.
+    let a: Acceptor<u64> = Acceptor::with_state(1, NodeContext::new(3), HashSet::new(), Some((1, 1)), [(2, Proposal::new((5, 3), 9))]);
+    assert_eq!(a.promised(), Some((5, 3)));
+    assert_eq!(a.accepted(2).map(|p| p.value), Some(9));
+}
+
+#[test]
+fn nacks_are_opt_in() {
+    let mut a = acceptor();
+    assert_eq!((NackMode::default(), PaxosConfig::default().nack_mode), (NackMode::Silent, NackMode::Silent));
+    a.set_rejection_log(4);
+    a.on_message(2, prepare(0, (5, 2)));
+    assert!(a.on_message(3, prepare(0, (1, 3))).is_empty());
+    assert_eq!(a.metrics().nacks_sent, 0);
+    a.set_nack_mode(NackMode::Enabled);
+    assert_eq!(a.on_message(3, prepare(0, (1, 3))).len(), 1);
+    assert_eq!(a.metrics().nacks_sent, 1);
+    // Silent rejections are still logged.
+    assert_eq!(a.recent_rejections().count(), 2);
+}
+
+#[test]
+fn prepare_from_promises_every_slot_from_there_on() {
+    let mut a = Acceptor::with_state(1, NodeContext::new(3), HashSet::from([LEARNER]), None, [(3, Proposal::new((1, 2), 30u64)), (6, Proposal::new((1, 2), 60))]);
+    a.set_nack_mode(NackMode::Enabled);
+    let out = a.on_message(4, PaxosMsg::PrepareFrom { from_slot: 5, proposal_id: (4, 4), from: 4 });
+    let want = PaxosMsg::PromiseFrom { from_slot: 5, proposal_response: (4, 4), accepted: vec![(6, Proposal::new((1, 2), 60))] };
+    assert_eq!(out, [Action::Send { to: 4, from: 1, msg: want.clone() }]);
+    assert_eq!(wire::decode::<u64>(&wire::encode(&want)).unwrap(), want);
+
+    for slot in [5, 6, 7] {
+        let out = a.on_message(4, accept(slot, (4, 4), slot * 100));
+        assert!(matches!(&out[0], Action::Send { to: 4, msg: PaxosMsg::Accepted { .. }, .. }), "{out:?}");
+    }
+    assert_eq!(a.accepted(7).map(|p| p.value), Some(700));
+    assert_eq!(a.metrics().prepares_seen, 1);
+
+    let out = a.on_message(2, PaxosMsg::PrepareFrom { from_slot: 0, proposal_id: (3, 2), from: 2 });
+    assert!(matches!(&out[..], [Action::Send { msg: PaxosMsg::Nack { slot: 0, promised: (4, 4), .. }, .. }]), "{out:?}");
+}
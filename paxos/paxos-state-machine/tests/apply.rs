@@ -0,0 +1,29 @@
+// Applying learned commands to a state machine in slot order.
+mod common;
+
+use paxos_state_machine::{apply::*, learner::Learner, msg::PaxosMsg, proposer::Proposal, types::*};
+
+struct Counter;
+
+impl Apply<i64, i64> for Counter {
+    fn apply(&mut self, state: &mut i64, _slot: Slot, cmd: &i64) {
+        *state += cmd;
+    }
+}
+
+#[test]
+fn commands_apply_in_slot_order_as_gaps_fill() {
+    let mut l: Learner<i64> = Learner::new(common::LEARNER, NodeContext::new(1), 1);
+    let mut counter = Applier::new(0i64, Counter);
+    let mut slots = Applier::new(vec![], |s: &mut Vec<Slot>, slot, _: &i64| s.push(slot));
+    let mut applied = vec![];
+    for (slot, v) in [(2, 5), (0, 1), (3, 10), (1, 2)] {
+        l.on_message(1, PaxosMsg::Accepted { slot, proposal: Proposal::new((1, 1), v), request_id: None });
+        applied.push(counter.catch_up(&l));
+        slots.catch_up(&l);
+    }
+    assert_eq!(applied, [0, 1, 0, 3]);
+    assert_eq!(*counter.state(), 18);
+    assert_eq!(counter.applied_index(), 4);
+    assert_eq!(slots.into_state(), [0, 1, 2, 3]);
+}
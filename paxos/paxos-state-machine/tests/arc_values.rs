@@ -0,0 +1,44 @@
+// Values behind an Arc fan out without being cloned. Kept in its own crate:
+// the clone counter is global.
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use paxos_state_machine::{acceptor::Acceptor, msg::PaxosMsg, proposer::Proposer, types::*, wire};
+
+static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Big(u64);
+
+impl Clone for Big {
+    fn clone(&self) -> Self {
+        CLONES.fetch_add(1, Ordering::SeqCst);
+        Big(self.0)
+    }
+}
+
+impl wire::Wire for Big {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out)
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, wire::DecodeError> {
+        Ok(Big(u64::decode(input)?))
+    }
+}
+
+#[test]
+fn fanout_shares_the_value() {
+    let mut p = Proposer::new(1, NodeContext::new(3), vec![1, 2, 3], Arc::new(Big(0)), 100, 2).unwrap();
+    p.propose(0, Arc::new(Big(7)));
+    let proposal_response = p.round_id(0).unwrap();
+    CLONES.store(0, Ordering::SeqCst);
+    let out: Vec<_> = [2, 3].into_iter().flat_map(|f| p.on_message(f, PaxosMsg::PromiseEmpty { slot: 0, proposal_response })).collect();
+    let accepts: Vec<_> = out.into_iter().filter_map(|a| match a { Action::Send { msg: m @ PaxosMsg::AcceptProposal { .. }, .. } => Some(m), _ => None }).collect();
+    assert_eq!(accepts.len(), 2);
+    let mut a: Acceptor<Arc<Big>> = Acceptor::new(2, NodeContext::new(3), HashSet::from([7, 8, 9]));
+    assert_eq!(a.on_message(1, accepts[0].clone()).len(), 4);
+    assert_eq!(CLONES.load(Ordering::SeqCst), 0);
+    assert_eq!(wire::decode::<Arc<Big>>(&wire::encode(&accepts[0])).unwrap(), accepts[0]);
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use common::*;
+use paxos_state_machine::{client::ChosenQuery, learner::Learner, msg::PaxosMsg, types::*, wire};
+
+/// Ask each learner about `slot` and feed its answer to a fresh query.
+fn ask(ls: &mut [Learner<u64>], slot: Slot) -> ChosenQuery<u64> {
+    let mut q = ChosenQuery::new(slot, 2);
+    for (_, to, msg) in sends(&q.broadcast(100, [1, 2, 3])) {
+        for (from, _, reply) in sends(&ls[to as usize - 1].on_message(100, msg)) {
+            q.on_reply(from, reply);
+        }
+    }
+    q
+}
+
+#[test]
+fn query_needs_a_quorum_of_matching_answers() {
+    let mut ls: Vec<Learner<u64>> = (1..=3).map(|i| Learner::new(i, NodeContext::new(3), 2)).collect();
+    let q = ask(&mut ls, 0);
+    assert_eq!(q.replies(), 3);
+    assert_eq!(q.value(), None);
+
+    for l in &mut ls[..2] {
+        for a in [1, 2] {
+            l.on_message(a, accepted(0, (1, 1), 5));
+        }
+    }
+    let mut q = ChosenQuery::new(0, 2);
+    for (i, l) in ls.iter_mut().enumerate() {
+        for (_, _, reply) in sends(&l.on_message(100, PaxosMsg::QueryChosen { slot: 0 })) {
+            q.on_reply(i as NodeId + 1, reply);
+        }
+        if i == 0 {
+            assert_eq!(q.value(), None);
+        }
+    }
+    assert_eq!(q.value(), Some(&5));
+    assert_eq!(ask(&mut ls, 0).value(), Some(&5));
+}
+
+#[test]
+fn query_messages_round_trip() {
+    for m in [PaxosMsg::<u64>::QueryChosen { slot: 3 }, PaxosMsg::ChosenReply { slot: 3, value: Some(1) }, PaxosMsg::ChosenReply { slot: 3, value: None }] {
+        assert_eq!(wire::decode::<u64>(&wire::encode(&m)).unwrap(), m);
+    }
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use common::*;
+use paxos_state_machine::cluster::{Cluster, ProposeError};
+
+fn block_on<F: Future>(f: F) -> F::Output {
+    let mut f = pin!(f);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+            return v;
+        }
+    }
+}
+
+#[test]
+fn proposals_land_in_consecutive_slots() {
+    let mut c = Cluster::new(nodes(3), 1);
+    assert_eq!(block_on(c.propose(7)), Ok(7));
+    assert_eq!(c.next_slot(), 1);
+    assert_eq!(block_on(c.propose(8)), Ok(8));
+}
+
+#[test]
+fn losing_a_slot_reports_the_winner() {
+    let mut c = Cluster::new(nodes(3), 1);
+    let out = c.network_mut().node_mut(2).unwrap().proposer_mut().propose(0, 99);
+    c.network_mut().submit(2, out);
+    c.network_mut().run_until(3);
+    assert_eq!(block_on(c.propose(7)), Err(ProposeError::OtherValueChosen(99)));
+}
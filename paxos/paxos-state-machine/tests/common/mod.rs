@@ -0,0 +1,137 @@
+// Fixtures shared by the integration tests. Each test crate pulls in the
+// whole module and uses a few of them.
+#![allow(dead_code)]
+
+use std::collections::{HashSet, VecDeque};
+
+use paxos_state_machine::{acceptor::Acceptor, learner::Learner, msg::PaxosMsg, node::{Node, Route}, proposer::{IsNoop, Proposal, Proposer}, sim::Network, types::*};
+
+/// Where `roles` puts its learner.
+pub const LEARNER: NodeId = 9;
+/// Where `roles` puts the proposer it's given.
+pub const PROPOSER: NodeId = 10;
+
+pub type Roles = Network<u64, Box<dyn HandlesEvents<u64>>>;
+
+/// The first timer `actions` arms.
+#[track_caller]
+pub fn timer<V>(actions: &[Action<V>]) -> TimerId {
+    actions.iter().find_map(|a| match a { Action::SetTimer { id, .. } => Some(*id), _ => None }).expect("no timer set")
+}
+
+/// Every message `actions` sends, as (from, to, msg).
+pub fn sends<V: Clone>(actions: &[Action<V>]) -> Vec<(NodeId, NodeId, PaxosMsg<V>)> {
+    actions.iter().filter_map(|a| match a { Action::Send { to, from, msg } => Some((*from, *to, msg.clone())), _ => None }).collect()
+}
+
+/// Every value `actions` reports chosen.
+pub fn chosen<V: Clone>(actions: &[Action<V>]) -> Vec<V> {
+    actions.iter().filter_map(|a| match a { Action::ChoseValue { v, .. } => Some(v.clone()), _ => None }).collect()
+}
+
+/// Empty promises from each of `from` for the round `p` runs in `slot`,
+/// and everything `p` did about them.
+pub fn promise_quorum<V: Clone>(p: &mut Proposer<V>, slot: Slot, from: &[NodeId]) -> Vec<Action<V>> {
+    let proposal_response = p.round_id(slot).expect("no round");
+    from.iter().flat_map(|&n| p.on_message(n, PaxosMsg::PromiseEmpty { slot, proposal_response })).collect()
+}
+
+/// Acceptors 1..=`n` reporting to a majority learner at `LEARNER`, with
+/// `proposer` at `PROPOSER`.
+pub fn roles(n: u64, proposer: Proposer<u64>) -> Roles {
+    let ctx = NodeContext::new(n);
+    let mut net: Roles = Network::new();
+    for id in 1..=n {
+        net.add_node(id, Box::new(Acceptor::new(id, ctx.clone(), HashSet::from([LEARNER]))));
+    }
+    net.add_node(LEARNER, Box::new(Learner::<u64>::new(LEARNER, ctx, (n / 2 + 1) as usize)));
+    net.add_node(PROPOSER, Box::new(proposer));
+    net
+}
+
+/// `n` full nodes that all learn, each proposing ten times its id.
+pub fn nodes(n: u64) -> Network<u64, Node<u64>> {
+    let ids: Vec<NodeId> = (1..=n).collect();
+    let mut net = Network::new();
+    for &id in &ids {
+        net.add_node(id, Node::new(id, NodeContext::new(n), ids.clone(), ids.iter().copied().collect(), id * 10, 100, (n / 2 + 1) as usize).unwrap());
+    }
+    net
+}
+
+/// Deliver `out`, and everything it leads to, between the loopback proposer
+/// `p` at `me`, acceptors 1..=`accs.len()` and, if given, a learner at
+/// `LEARNER`. `tap` sees each message first. Returns everything but the sends.
+pub fn exchange(
+    me: NodeId,
+    p: &mut Proposer<u64>,
+    accs: &mut [Acceptor<u64>],
+    mut learner: Option<&mut Learner<u64>>,
+    out: Vec<Action<u64>>,
+    mut tap: impl FnMut(&PaxosMsg<u64>),
+) -> Vec<Action<u64>> {
+    let mut queue = VecDeque::from(out);
+    let mut rest = vec![];
+    while let Some(a) = queue.pop_front() {
+        let Action::Send { to, from, msg } = a else {
+            rest.push(a);
+            continue;
+        };
+        tap(&msg);
+        let out = match learner.as_deref_mut() {
+            Some(l) if to == LEARNER => l.on_message(from, msg),
+            _ if to == me && Node::route(&msg) != Route::Acceptor => p.on_message(from, msg),
+            _ => accs[to as usize - 1].on_message(from, msg),
+        };
+        queue.extend(out);
+    }
+    rest
+}
+
+/// Values `node` reported chosen on `net`, in order.
+pub fn chosen_at<R: HandlesEvents<u64>>(net: &Network<u64, R>, node: NodeId) -> Vec<u64> {
+    net.outputs().iter().filter_map(|(_, n, a)| match a { Action::ChoseValue { v, .. } if *n == node => Some(*v), _ => None }).collect()
+}
+
+/// A Prepare for `slot` under `pid`, from the proposer that owns it.
+pub fn prepare(slot: Slot, pid: ProposalId) -> PaxosMsg<u64> {
+    PaxosMsg::Prepare { slot, proposal_id: pid, from: pid.1 }
+}
+
+/// An AcceptProposal for `v` in `slot` under `pid`.
+pub fn accept(slot: Slot, pid: ProposalId, v: u64) -> PaxosMsg<u64> {
+    PaxosMsg::AcceptProposal { slot, proposal_id: pid, value: v, committed_up_to: None, request_id: None }
+}
+
+/// An acceptor's ack for `v` in `slot` under `pid`.
+pub fn accepted(slot: Slot, pid: ProposalId, v: u64) -> PaxosMsg<u64> {
+    PaxosMsg::Accepted { slot, proposal: Proposal { id: pid, value: v }, request_id: None }
+}
+
+/// How many Prepares `actions` sends.
+pub fn prepares<V>(actions: &[Action<V>]) -> usize {
+    actions.iter().filter(|a| matches!(a, Action::Send { msg: PaxosMsg::Prepare { .. }, .. })).count()
+}
+
+/// How many AcceptProposals `actions` sends.
+pub fn accepts<V>(actions: &[Action<V>]) -> usize {
+    actions.iter().filter(|a| matches!(a, Action::Send { msg: PaxosMsg::AcceptProposal { .. }, .. })).count()
+}
+
+/// A learner's relay of `v` in `slot` under `pid`.
+pub fn learn(slot: Slot, pid: ProposalId, v: u64) -> PaxosMsg<u64> {
+    PaxosMsg::Learn { slot, proposal_id: pid, value: v }
+}
+
+/// A command type with a no-op, for the requeue paths.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Cmd {
+    Noop,
+    Put(u64),
+}
+
+impl IsNoop for Cmd {
+    fn is_noop(&self) -> bool {
+        *self == Cmd::Noop
+    }
+}
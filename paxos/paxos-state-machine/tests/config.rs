@@ -0,0 +1,29 @@
+// One PaxosConfig building every role.
+mod common;
+
+use std::collections::HashSet;
+
+use common::*;
+use paxos_state_machine::{config::PaxosConfig, learner::Learner, node::Node, proposer::{BackoffConfig, Proposer}, types::*};
+
+#[test]
+fn defaults_pick_a_majority() {
+    let c = PaxosConfig::default();
+    assert_eq!(c.quorum_for(&NodeContext::new(5)), 3);
+    assert_eq!(c.backoff, BackoffConfig::default());
+    let p: Proposer<u64> = Proposer::with_config(1, NodeContext::new(5), (1..=5).collect(), 0, &c).unwrap();
+    assert_eq!(p.quorum(), 3);
+    let n: Node<u64> = Node::with_config(1, NodeContext::new(3), vec![1, 2, 3], HashSet::from([1, 2, 3]), 5, &c).unwrap();
+    assert_eq!(n.proposer().quorum(), 2);
+}
+
+#[test]
+fn explicit_quorum_overrides_the_majority() {
+    let c = PaxosConfig { quorum: Some(2), ..PaxosConfig::default() };
+    assert_eq!(PaxosConfig { quorum: None, ..c }, PaxosConfig::default());
+    let mut l: Learner<u64> = Learner::with_config(LEARNER, NodeContext::new(5), &c);
+    for n in [1, 2] {
+        l.on_message(n, accepted(0, (1, 1), 4));
+    }
+    assert_eq!(l.get_chosen(0), Some(&4));
+}
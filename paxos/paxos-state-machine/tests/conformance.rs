@@ -0,0 +1,23 @@
+// The randomized safety suite, at a size that keeps `cargo test` quick.
+// `paxos conformance <iterations>` runs it for longer.
+use paxos_state_machine::conformance::{run, run_seed, Config};
+
+#[test]
+fn regression_learns_every_slot_everywhere() {
+    let config = Config::learn_accepted_regression();
+    let report = run_seed(&config, config.first_seed).unwrap();
+    assert_eq!(report.learned, config.nodes * config.slots);
+}
+
+#[test]
+fn random_runs_stay_safe() {
+    let report = run(&Config { iterations: 50, ..Config::default() }).unwrap_or_else(|v| panic!("{v}"));
+    assert_eq!(report.runs, 51);
+    assert!(report.learned > 0 && report.dropped > 0);
+}
+
+#[test]
+fn fault_free_runs_decide_every_slot() {
+    let config = Config { iterations: 20, drop_rate: 0.0, jitter_ms: 0, restarts: 0, expect_progress: true, ..Config::default() };
+    run(&config).unwrap_or_else(|v| panic!("{v}"));
+}
@@ -0,0 +1,14 @@
+use paxos_state_machine::dueling::*;
+
+#[test]
+fn competing_proposers_settle_quickly() {
+    for proposers in [2, 3] {
+        let report = run(&Config { proposers, ..Config::default() }).unwrap();
+        assert!(report.max_rounds() <= 9);
+    }
+}
+
+#[test]
+fn round_limit_reports_livelock() {
+    assert!(matches!(run_seed(&Config { max_rounds: 1, ..Config::default() }, 0), Err(Livelock::TooManyRounds { .. })));
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use common::*;
+use paxos_state_machine::{election::Election, failure_detector::FailureDetector, proposer::Proposer, types::*};
+
+const IDS: [NodeId; 3] = [1, 2, 3];
+
+#[test]
+fn only_the_elected_node_proposes_and_a_successor_takes_over() {
+    let mut ps: Vec<Proposer<u64>> = IDS.iter().map(|&i| Proposer::new(i, NodeContext::new(3), IDS.to_vec(), i * 10, 100, 2).unwrap()).collect();
+    let mut es: Vec<Election> = IDS.iter().map(|&i| Election::new(i, IDS)).collect();
+    let mut fds: Vec<FailureDetector> = IDS.iter().map(|_| FailureDetector::new(50)).collect();
+    for i in 0..3 {
+        for &j in &IDS {
+            fds[i].heartbeat(j, 0);
+        }
+        es[i].drive(&fds[i], 0, &mut ps[i]);
+    }
+    let outs: Vec<_> = ps.iter_mut().map(|p| p.on_init()).collect();
+    assert_eq!(outs.iter().map(|o| prepares(o)).collect::<Vec<_>>(), [2, 0, 0]);
+    assert_eq!(es[1].leader(), Some(1));
+
+    // 1 goes quiet; 2 and 3 keep hearing each other.
+    for fd in &mut fds[1..] {
+        for j in [2, 3] {
+            fd.heartbeat(j, 100);
+        }
+    }
+    let from_2 = es[1].drive(&fds[1], 100, &mut ps[1]);
+    let from_3 = es[2].drive(&fds[2], 100, &mut ps[2]);
+    assert_eq!(es[1].leader(), Some(2));
+    assert!(es[1].is_leader());
+    assert_eq!(prepares(&from_2), 2);
+    assert_eq!(prepares(&from_3), 0);
+    assert!(ps[2].is_idle() && !ps[1].is_idle());
+}
+
+#[test]
+fn elections_and_quorum_checks_park_the_proposer_independently() {
+    let mut p = Proposer::new(2, NodeContext::new(3), IDS.to_vec(), 20u64, 100, 2).unwrap();
+    let mut e = Election::new(2, IDS);
+    let mut fd = FailureDetector::new(50);
+    for j in IDS {
+        fd.heartbeat(j, 0);
+    }
+    e.drive(&fd, 0, &mut p);
+    assert!(p.propose(0, 5).is_empty());
+
+    // Elected while no quorum is reachable: still parked until there is one.
+    let out = p.check_quorum(&fd, 100);
+    assert!(out.iter().any(|a| matches!(a, Action::QuorumUnreachable { .. })));
+    assert_eq!(prepares(&e.drive(&fd, 100, &mut p)), 0);
+    assert!(e.is_leader() && p.is_idle());
+    fd.heartbeat(3, 120);
+    assert_eq!(prepares(&p.check_quorum(&fd, 120)), 2);
+    assert!(!p.is_idle());
+
+    // Demoted, then the quorum flaps: we stay down.
+    fd.heartbeat(1, 130);
+    e.drive(&fd, 130, &mut p);
+    assert!(!e.is_leader() && p.is_idle());
+    assert!(p.check_quorum(&fd, 200).iter().any(|a| matches!(a, Action::QuorumUnreachable { .. })));
+    for j in [1, 3] {
+        fd.heartbeat(j, 210);
+    }
+    assert!(p.check_quorum(&fd, 210).is_empty());
+    assert!(p.is_idle());
+}
@@ -0,0 +1,380 @@
+// Learners counting acks, relays and catch-up into decisions.
+mod common;
+
+use std::collections::{HashMap, HashSet};
+
+use common::*;
+use paxos_state_machine::{error::PaxosError, learner::{Learner, LearnerGroup, DIGEST_WINDOW}, msg::PaxosMsg, node_set::SetBackend, sim::Network, types::*, wire};
+
+#[test]
+fn weighted_votes_decide() {
+    let ctx = NodeContext::weighted(HashMap::from([(1, 2), (2, 1), (3, 1)]));
+    assert!(ctx.is_quorum(&HashSet::from([1, 2]).into(), 2));
+    assert!(!ctx.is_quorum(&HashSet::from([2, 3]).into(), 2));
+    assert!(NodeContext::new(3).is_quorum(&HashSet::from([2, 3]).into(), 2));
+    let mut l: Learner<u64> = Learner::new(LEARNER, ctx, 2);
+    assert!(l.on_message(2, accepted(0, (1, 1), 5)).is_empty());
+    assert!(l.on_message(3, accepted(0, (1, 1), 5)).is_empty());
+    assert_eq!(l.on_message(1, accepted(0, (1, 1), 5)), vec![Action::ChoseValue { slot: 0, v: 5, request_id: None }]);
+}
+
+#[test]
+fn group_reports_once_every_member_learned() {
+    let mut g = LearnerGroup::new([7, 8, 9]);
+    assert!(g.on_message(7, learn(4, (1, 1), 3)).is_empty());
+    assert!(g.on_message(7, learn(4, (1, 1), 3)).is_empty());
+    assert!(g.on_message(5, learn(4, (1, 1), 3)).is_empty());
+    assert_eq!(g.on_message(8, learn(4, (1, 1), 3)), vec![Action::WidelyLearned { slot: 4 }]);
+    assert!(g.on_message(9, learn(4, (1, 1), 3)).is_empty());
+
+    let mut l: Learner<u64> = Learner::new(7, NodeContext::new(3), 2);
+    l.relay_to([7, 8, 9]);
+    l.on_message(1, accepted(4, (1, 1), 3));
+    let out = l.on_message(2, accepted(4, (1, 1), 3));
+    assert_eq!(chosen(&out), [3]);
+    assert_eq!(sends(&out).iter().map(|(_, to, _)| *to).collect::<Vec<_>>(), [7, 8, 9]);
+}
+
+#[test]
+fn relay_threshold_defers_the_decision_to_the_relays() {
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.set_relay_threshold(2);
+    l.relay_to([7, 8]);
+    assert!(l.on_message(1, accepted(0, (1, 1), 5)).is_empty());
+    let out = l.on_message(2, accepted(0, (1, 1), 5));
+    assert_eq!(sends(&out), [(LEARNER, 7, learn(0, (1, 1), 5)), (LEARNER, 8, learn(0, (1, 1), 5))]);
+    assert_eq!(out.len(), 2);
+    assert_eq!(l.get_chosen(0), None);
+    assert!(l.on_message(7, learn(0, (1, 1), 5)).is_empty());
+    assert!(l.on_message(7, learn(0, (1, 1), 5)).is_empty());
+    assert_eq!(l.on_message(8, learn(0, (1, 1), 5)), vec![Action::ChoseValue { slot: 0, v: 5, request_id: None }]);
+    assert_eq!(l.get_chosen(0), Some(&5));
+    assert!(l.on_message(3, accepted(0, (1, 1), 5)).is_empty());
+
+    // Without a threshold a lone Learn is enough.
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    assert_eq!(chosen(&l.on_message(7, learn(0, (1, 1), 5))), [5]);
+}
+
+fn stalling_learner() -> Learner<u64> {
+    let mut l = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.set_stall_timeout(50);
+    l
+}
+
+#[test]
+fn stall_timer_runs_only_while_a_slot_is_undecided() {
+    let mut l = stalling_learner();
+    let out = l.on_message(1, accepted(0, (1, 1), 5));
+    let Action::SetTimer { id, ms: 50 } = out[0] else { panic!("{out:?}") };
+    let out = l.on_message(2, accepted(0, (1, 1), 5));
+    assert_eq!(out, vec![Action::CancelTimer { id }, Action::ChoseValue { slot: 0, v: 5, request_id: None }]);
+    assert!(l.on_timeout(id).is_empty());
+
+    let mut net = Network::new();
+    net.add_node(LEARNER, stalling_learner());
+    net.inject(LEARNER, Event::Message { from: 1, msg: accepted(0, (1, 1), 5) });
+    net.inject(LEARNER, Event::Message { from: 2, msg: accepted(0, (1, 1), 5) });
+    net.run_to_quiescence(100);
+    assert!(net.timeouts_fired().is_empty());
+    assert!(!net.outputs().iter().any(|(_, _, a)| matches!(a, Action::LearnStalled { .. })));
+
+    let mut net = Network::new();
+    net.add_node(LEARNER, stalling_learner());
+    net.inject(LEARNER, Event::Message { from: 1, msg: accepted(3, (1, 1), 5) });
+    net.run_to_quiescence(100);
+    assert!(net.outputs().iter().any(|(t, _, a)| *t == 50 && matches!(a, Action::LearnStalled { slot: 3 })));
+}
+
+// The (start, end) of every catch-up request in `out`
+fn catch_up_requests(out: &[Action<u64>]) -> Vec<(Slot, Slot)> {
+    sends(out).into_iter().filter_map(|(_, _, m)| match m { PaxosMsg::CatchUpRequest { start, end } => Some((start, end)), _ => None }).collect()
+}
+
+#[test]
+fn digest_requests_only_the_gaps() {
+    let ctx = NodeContext::new(1);
+    let mut peer: Learner<u64> = Learner::new(2, ctx.clone(), 1);
+    let mut me: Learner<u64> = Learner::new(1, ctx, 1);
+    for s in 0..5 {
+        peer.on_message(9, accepted(s, (1, 9), s * 10));
+        if s != 1 && s != 3 {
+            me.on_message(9, accepted(s, (1, 9), s * 10));
+        }
+    }
+    let d = peer.digest();
+    assert_eq!(wire::decode::<u64>(&wire::encode(&d)).unwrap(), d);
+    let out = me.on_message(2, d);
+    assert_eq!(catch_up_requests(&out), [(1, 2), (3, 4)]);
+    assert_eq!(out.len(), 2);
+    for (_, _, request) in sends(&out) {
+        for (_, _, reply) in sends(&peer.on_message(1, request)) {
+            assert_eq!(wire::decode::<u64>(&wire::encode(&reply)).unwrap(), reply);
+            assert_eq!(me.on_message(2, reply).len(), 1);
+        }
+    }
+    assert_eq!(me.get_chosen(1), Some(&10));
+    assert_eq!(me.get_chosen(3), Some(&30));
+    assert!(me.on_message(2, peer.digest()).is_empty());
+}
+
+#[test]
+fn digest_work_is_bounded() {
+    let mut l: Learner<u64> = Learner::new(1, NodeContext::new(1), 1);
+    for s in [0, 1, 2, 5, u64::MAX - 1] {
+        l.on_message(9, accepted(s, (1, 9), 1));
+    }
+    let PaxosMsg::LearnDigest { base, up_to, bitmap } = l.digest() else { panic!() };
+    assert_eq!((base, up_to), (3, 3 + DIGEST_WINDOW));
+    assert_eq!(bitmap.len(), 1024);
+    assert_eq!(bitmap[0], 0b100);
+
+    // Everything below a peer's base is implied.
+    let mut me: Learner<u64> = Learner::new(2, NodeContext::new(1), 1);
+    me.on_message(9, accepted(1, (1, 9), 1));
+    let out = me.on_message(1, PaxosMsg::LearnDigest { base, up_to, bitmap });
+    assert_eq!(catch_up_requests(&out), [(0, 1), (2, 3), (5, 6)]);
+    let out = me.on_message(1, PaxosMsg::LearnDigest { base: u64::MAX - 3, up_to: u64::MAX, bitmap: vec![0xff] });
+    assert_eq!(out.len(), 2);
+}
+
+#[test]
+fn equivocating_acceptor_is_reported_and_not_counted() {
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    assert!(l.on_message(1, accepted(0, (1, 1), 5)).is_empty());
+    assert_eq!(l.on_message(1, accepted(0, (1, 1), 6)), vec![Action::Error { error: PaxosError::Equivocation { node: 1, slot: 0, pid: (1, 1) } }]);
+    // An honest ack for 6 doesn't make a quorum with the liar's.
+    assert!(l.on_message(2, accepted(0, (1, 1), 6)).is_empty());
+    assert_eq!(l.get_chosen(0), None);
+    assert!(l.on_message(3, accepted(0, (1, 1), 6)).contains(&Action::ChoseValue { slot: 0, v: 6, request_id: None }));
+}
+
+#[test]
+fn equivocation_check_holds_on_the_bits_backend() {
+    let mut ctx = NodeContext::new(3);
+    ctx.set_backend = SetBackend::Bits;
+    let mut l: Learner<u64> = Learner::new(LEARNER, ctx, 2);
+    l.on_message(1, accepted(0, (1, 1), 6));
+    assert!(!l.on_message(1, accepted(0, (1, 1), 5)).is_empty());
+    assert!(l.on_message(1, accepted(0, (1, 1), 6)).is_empty());
+    assert!(l.on_message(2, accepted(0, (1, 1), 6)).is_empty());
+    assert_eq!(l.get_chosen(0), None);
+}
+
+#[test]
+fn reset_forgets_decisions() {
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(1), 1);
+    l.on_message(1, accepted(0, (1, 1), 3));
+    assert_eq!(l.get_chosen(0), Some(&3));
+    l.reset();
+    assert_eq!(l.get_chosen(0), None);
+    assert_eq!(l.node_id(), LEARNER);
+    l.on_message(1, accepted(0, (2, 1), 4));
+    assert_eq!(l.get_chosen(0), Some(&4));
+}
+
+#[test]
+fn batched_queries_can_be_coalesced() {
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    let query = || Event::Message { from: 4, msg: PaxosMsg::QueryChosen { slot: 0 } };
+    assert_eq!(l.on_events(vec![query(), query()], false).len(), 2);
+    assert_eq!(l.on_events(vec![query(), query()], true).len(), 1);
+}
+
+#[test]
+fn acks_from_outside_the_acceptor_set_are_ignored() {
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.set_acceptors([1, 2, 3]);
+    assert!(l.on_message(7, accepted(0, (1, 1), 5)).is_empty());
+    assert!(l.on_message(1, accepted(0, (1, 1), 5)).is_empty());
+    assert_eq!(l.get_chosen(0), None);
+    l.set_strict(true);
+    assert_eq!(l.on_message(8, accepted(0, (1, 1), 5)), vec![Action::Error { error: PaxosError::AckFromNonAcceptor { node: 8, slot: 0 } }]);
+    assert_eq!(l.get_chosen(0), None);
+    assert!(l.on_message(2, accepted(0, (1, 1), 5)).contains(&Action::ChoseValue { slot: 0, v: 5, request_id: None }));
+}
+
+#[test]
+fn catch_up_pages_through_a_long_gap() {
+    let ctx = NodeContext::new(1);
+    let mut peer: Learner<u64> = Learner::new(2, ctx.clone(), 1);
+    peer.set_catch_up_limit(4);
+    for s in 0..10 {
+        peer.on_message(9, accepted(s, (1, 9), s));
+    }
+    let mut me: Learner<u64> = Learner::new(1, ctx, 1);
+    let mut request = Some(PaxosMsg::CatchUpRequest { start: 0, end: 10 });
+    let (mut pages, mut learned) = (0, vec![]);
+    while let Some(r) = request.take() {
+        pages += 1;
+        let (_, _, page) = sends(&peer.on_message(1, r)).remove(0);
+        assert_eq!(wire::decode::<u64>(&wire::encode(&page)).unwrap(), page);
+        let out = me.on_message(2, page);
+        learned.extend(chosen(&out));
+        request = sends(&out).into_iter().find(|(_, to, _)| *to == 2).map(|(_, _, m)| m);
+    }
+    assert_eq!(pages, 3);
+    assert_eq!(learned, (0..10).collect::<Vec<u64>>());
+}
+
+#[test]
+fn learner_quorum_can_be_smaller_than_a_majority() {
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(5), 2);
+    l.on_message(1, accepted(0, (1, 1), 4));
+    assert_eq!(l.get_chosen(0), None);
+    l.on_message(2, accepted(0, (1, 1), 4));
+    assert_eq!(l.get_chosen(0), Some(&4));
+}
+
+#[test]
+fn inflight_cap_evicts_the_oldest_undecided_votes() {
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.set_max_inflight(2);
+    l.set_strict(true);
+    let ack = |slot| accepted(slot, (1, 1), 10 + slot);
+    l.on_message(1, ack(0));
+    l.on_message(2, ack(0));
+    assert_eq!(l.get_chosen(0), Some(&10));
+    l.on_message(1, ack(1));
+    l.on_message(1, ack(2));
+    let out = l.on_message(1, ack(3));
+    assert!(out.contains(&Action::Error { error: PaxosError::TooManyInflight { slot: 1, max: 2 } }), "{out:?}");
+    // Slot 1 lost its vote; taking it back in pushes out 2. 3 keeps its vote.
+    l.on_message(2, ack(1));
+    assert_eq!(l.get_chosen(1), None);
+    l.on_message(2, ack(3));
+    assert_eq!(l.get_chosen(3), Some(&13));
+    l.on_message(2, ack(2));
+    assert_eq!(l.get_chosen(2), None);
+    assert_eq!(l.get_chosen(0), Some(&10));
+}
+
+#[test]
+fn decision_on_a_value_never_proposed_is_flagged() {
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.set_check_proposed(true);
+    l.on_message(1, PaxosMsg::AcceptProposal { slot: 0, proposal_id: (1, 1), value: 5, committed_up_to: None, request_id: None });
+    l.on_message(1, accepted(0, (1, 1), 5));
+    assert_eq!(l.on_message(2, accepted(0, (1, 1), 5)), vec![Action::ChoseValue { slot: 0, v: 5, request_id: None }]);
+    l.on_message(1, accepted(1, (1, 1), 6));
+    assert_eq!(l.on_message(2, accepted(1, (1, 1), 6)), vec![
+        Action::Error { error: PaxosError::SafetyViolation { slot: 1 } },
+        Action::ChoseValue { slot: 1, v: 6, request_id: None },
+    ]);
+}
+
+#[test]
+fn acks_and_relays_decide_once_in_any_order() {
+    let ack = |v| accepted(0, (1, 1), v);
+    let relay = |v| learn(0, (1, 1), v);
+
+    // Acks, then the same Learn.
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    let mut decided = chosen(&l.on_message(1, ack(5))).len() + chosen(&l.on_message(2, ack(5))).len();
+    assert!(l.on_message(4, relay(5)).is_empty());
+    decided += chosen(&l.on_message(3, ack(5))).len();
+    assert_eq!(decided, 1);
+    // Then a Learn that disagrees.
+    let out = l.on_message(4, relay(6));
+    assert!(matches!(&out[..], [Action::Error { error: PaxosError::ConflictingLearn { node: 4, slot: 0 } }]));
+    assert_eq!(l.get_chosen(0), Some(&5));
+
+    // A Learn, then acks: by default the relay alone decides.
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    assert_eq!(chosen(&l.on_message(4, relay(5))), [5]);
+    for from in 1..=3 {
+        assert!(l.on_message(from, ack(5)).is_empty());
+    }
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.set_relay_threshold(1);
+    assert_eq!(chosen(&l.on_message(4, relay(5))), [5]);
+    for from in 1..=3 {
+        assert!(l.on_message(from, ack(5)).is_empty());
+    }
+
+    // Some acks, a Learn, then a conflicting Learn.
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.set_relay_threshold(1);
+    l.on_message(1, ack(5));
+    assert_eq!(chosen(&l.on_message(4, relay(5))), [5]);
+    assert!(l.on_message(2, ack(5)).is_empty());
+    assert!(matches!(&l.on_message(4, relay(7))[..], [Action::Error { .. }]));
+}
+
+#[test]
+fn retained_acks_name_the_deciding_quorum() {
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(5), 3);
+    for f in [1, 2, 4] {
+        l.on_message(f, accepted(0, (1, 1), 5));
+    }
+    assert_eq!(l.acked_by(0), None);
+
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(5), 3);
+    l.set_retain_acks(true);
+    for f in [1, 2, 4, 5] {
+        l.on_message(f, accepted(0, (1, 1), 5));
+    }
+    assert_eq!(l.get_chosen(0), Some(&5));
+    assert_eq!(l.acked_by(0), Some(&HashSet::from([1, 2, 4])));
+    assert_eq!(l.acked_by(1), None);
+}
+
+#[test]
+fn a_slot_is_reported_chosen_exactly_once() {
+    for threshold in [0, 1] {
+        let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(5), 3);
+        l.set_relay_threshold(threshold);
+        let mut out = vec![];
+        for _ in 0..3 {
+            for f in 1..=5 {
+                out.extend(l.on_message(f, accepted(0, (1, 1), 5)));
+            }
+        }
+        for f in 1..=5 {
+            out.extend(l.on_message(f, accepted(0, (2, 2), 5)));
+        }
+        for f in 6..=8 {
+            out.extend(l.on_message(f, learn(0, (1, 1), 5)));
+        }
+        out.extend(l.on_message(7, PaxosMsg::CatchUpResponse { entries: vec![(0, 5)], next: None, end: 1 }));
+        for f in 1..=5 {
+            out.extend(l.on_message(f, accepted(0, (3, 3), 5)));
+        }
+        assert_eq!(chosen(&out), [5], "threshold {threshold}");
+    }
+}
+
+#[test]
+fn provenance_is_known_only_for_counted_decisions() {
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.on_message(1, accepted(0, (1, 1), 5));
+    l.on_message(2, accepted(0, (2, 2), 6));
+    assert_eq!(l.chosen_with_id(0), None);
+    l.on_message(3, accepted(0, (2, 2), 6));
+    assert_eq!(l.chosen_with_id(0), Some((&6, (2, 2))));
+    // Caught-up slots come without the round that chose them.
+    l.on_message(3, PaxosMsg::CatchUpResponse { entries: vec![(1, 4)], next: None, end: 2 });
+    assert_eq!(l.get_chosen(1), Some(&4));
+    assert_eq!(l.chosen_with_id(1), None);
+}
+
+#[test]
+fn starts_from_saved_decisions() {
+    let l: Learner<u64> = Learner::with_state(LEARNER, NodeContext::new(3), 2, [(0, 4), (1, 5)]);
+    assert_eq!(l.get_chosen(1), Some(&5));
+    assert_eq!(l.first_gap(), 2);
+}
+
+#[test]
+fn stats_count_gaps_below_the_highest_decision() {
+    let l = Learner::with_state(1, NodeContext::new(3), 2, [(0, 1u64), (3, 4)]);
+    assert_eq!((l.stats().chosen, l.stats().gaps), (2, 2));
+}
+
+#[test]
+fn chosen_value_names_its_slot() {
+    let mut l = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.on_message(1, accepted(2, (1, 1), 5));
+    let out = l.on_message(2, accepted(2, (1, 1), 5));
+    assert_eq!(out, [Action::ChoseValue { slot: 2, v: 5, request_id: None }]);
+}
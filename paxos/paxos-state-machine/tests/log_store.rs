@@ -0,0 +1,76 @@
+// The log store contract, and roles writing through it.
+mod common;
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use common::*;
+use paxos_state_machine::{acceptor::Acceptor, learner::Learner, log_store::{LogStore, MemLog}, types::*};
+
+#[test]
+fn mem_log_keeps_the_latest_entry_per_slot() {
+    let mut l = MemLog::new();
+    assert_eq!(l.last_slot(), None);
+    l.put(5, "e");
+    l.put(1, "a");
+    l.put(3, "c");
+    assert_eq!(l.get(3), Some(&"c"));
+    assert!(!l.contains(2));
+    assert_eq!(l.range(2).collect::<Vec<_>>(), [(3, &"c"), (5, &"e")]);
+    l.put(3, "C");
+    assert_eq!(l.get(3), Some(&"C"));
+    assert_eq!(l.last_slot(), Some(5));
+    l.clear();
+    assert_eq!(l.range(0).count(), 0);
+}
+
+/// A `MemLog` that records which slots were written.
+struct Spy<V> {
+    log: MemLog<V>,
+    puts: Arc<Mutex<Vec<Slot>>>,
+}
+
+impl<V> LogStore<V> for Spy<V> {
+    fn get(&self, slot: Slot) -> Option<&V> {
+        self.log.get(slot)
+    }
+
+    fn put(&mut self, slot: Slot, v: V) {
+        self.puts.lock().unwrap().push(slot);
+        self.log.put(slot, v)
+    }
+
+    fn range(&self, from: Slot) -> Box<dyn Iterator<Item = (Slot, &V)> + '_> {
+        self.log.range(from)
+    }
+
+    fn clear(&mut self) {
+        self.log.clear()
+    }
+}
+
+fn spy<V>() -> (Spy<V>, Arc<Mutex<Vec<Slot>>>) {
+    let puts = Arc::new(Mutex::new(vec![]));
+    (Spy { log: MemLog::new(), puts: puts.clone() }, puts)
+}
+
+#[test]
+fn roles_write_through_their_store() {
+    let (store, puts) = spy();
+    let mut a: Acceptor<u64> = Acceptor::new(1, NodeContext::new(3), HashSet::new());
+    a.set_log_store(store);
+    a.on_message(2, accept(4, (1, 2), 9));
+    assert_eq!(*puts.lock().unwrap(), [4]);
+    assert_eq!(a.accepted(4).map(|p| p.value), Some(9));
+    assert_eq!(a.persisted().accepted.len(), 1);
+
+    let (store, puts) = spy();
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.set_log_store(store);
+    for n in [1, 2] {
+        l.on_message(n, accepted(0, (1, 1), 3));
+    }
+    assert_eq!(*puts.lock().unwrap(), [0]);
+    assert_eq!(l.get_chosen(0), Some(&3));
+    assert_eq!(l.first_gap(), 1);
+}
@@ -0,0 +1,16 @@
+mod common;
+
+use common::*;
+use paxos_state_machine::{middleware::WithMiddleware, proposer::Proposer, sim::Network, types::*};
+
+#[test]
+fn filter_can_drop_timers() {
+    let p = Proposer::new(1, NodeContext::new(3), vec![1, 2, 3], 7u64, 100, 2).unwrap();
+    let mut w = WithMiddleware::new(p, |a: Vec<Action<u64>>| a.into_iter().filter(|a| !matches!(a, Action::SetTimer { .. })).collect());
+    assert_eq!(prepares(&w.on_init()), 2);
+    let mut net: Roles = Network::new();
+    net.add_node(1, Box::new(w));
+    net.init();
+    net.run_to_quiescence(100);
+    assert!(net.timeouts_fired().is_empty());
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use std::collections::HashSet;
+
+use common::*;
+use paxos_state_machine::{error::PaxosError, lease::Lease, msg::PaxosMsg, node::*, proposer::Proposal, sim::Network, types::*};
+
+#[test]
+fn reads_need_the_lease_and_leadership() {
+    let mut net = nodes(3);
+    net.init_node(1);
+    net.run_until(99);
+    assert_eq!((1..=3).flat_map(|n| chosen_at(&net, n)).collect::<Vec<_>>(), [10; 4]);
+
+    let n1 = net.node_mut(1).unwrap();
+    assert_eq!(n1.linearizable_read(0), ReadOutcome::NotLeader { leader: None });
+    n1.set_lease(Lease::new(1, 0, 100));
+    assert_eq!(n1.linearizable_read(50), ReadOutcome::Ok(vec![10]));
+    assert_eq!(n1.linearizable_read(100), ReadOutcome::LeaseExpired);
+    let n2 = net.node_mut(2).unwrap();
+    n2.set_lease(Lease::new(1, 0, 100));
+    assert_eq!(n2.linearizable_read(50), ReadOutcome::NotLeader { leader: Some(1) });
+}
+
+#[test]
+fn own_acceptor_counts_toward_the_quorum() {
+    let mut net = Network::new();
+    for id in 1..=3 {
+        let peers: Vec<NodeId> = (1..=3).filter(|p| *p != id).collect();
+        net.add_node(id, Node::new(id, NodeContext::new(3), peers, HashSet::from([1, 2, 3]), id * 100, 50, 2).unwrap());
+    }
+    net.crash(3);
+    net.init_node(1);
+    net.run_until(40);
+    let n1: &Node<u64> = net.node(1).unwrap();
+    assert!(n1.proposer().is_decided(0));
+    assert_eq!(n1.learner().get_chosen(0), Some(&100));
+}
+
+#[test]
+fn proposes_into_the_first_gap() {
+    let mut n = Node::new(1, NodeContext::new(3), vec![1, 2, 3], HashSet::from([1, 2, 3]), 0u64, 100, 2).unwrap();
+    n.proposer_mut().seed_chosen(0, 42);
+    let (slot, out) = n.propose_next_gap(7);
+    assert_eq!(slot, 1);
+    assert!(sends(&out).iter().any(|(_, _, m)| matches!(m, PaxosMsg::Prepare { slot: 1, .. })));
+    assert_eq!(n.proposer_mut().propose_slot(0, 9), vec![Action::AlreadyChosen { slot: 0, existing: 42 }]);
+    let round = n.proposer().round_id(1);
+    assert!(n.proposer_mut().propose_slot(1, 9).is_empty());
+    assert_eq!(n.proposer().round_id(1), round);
+}
+
+#[test]
+fn requeued_value_reaches_the_own_acceptor() {
+    let mut n: Node<Cmd> = Node::new(1, NodeContext::new(3), vec![2, 3], HashSet::from([1]), Cmd::Noop, 100, 2).unwrap();
+    n.proposer_mut().set_repropose_after_noop(true);
+    n.propose(0, Cmd::Put(5));
+    let pid = n.proposer().round_id(0).unwrap();
+    n.on_message(2, PaxosMsg::Promise { slot: 0, accepted_proposal: Some(Proposal::new((0, 3), Cmd::Noop)), proposal_response: pid });
+    assert_eq!(n.acceptor().accepted(0).map(|p| p.value.clone()), Some(Cmd::Noop));
+    let out = n.on_message(2, PaxosMsg::Accepted { slot: 0, proposal: Proposal::new(pid, Cmd::Noop), request_id: None });
+    assert!(n.proposer().is_decided(0));
+    let next = n.proposer().round_id(1).unwrap();
+    assert_eq!(n.acceptor().promised(), Some(next));
+    assert!(sends(&out).iter().any(|(_, to, m)| *to == 2 && matches!(m, PaxosMsg::Prepare { slot: 1, .. })));
+    // The local promise already counted: one remote makes the quorum.
+    let out = n.on_message(3, PaxosMsg::PromiseEmpty { slot: 1, proposal_response: next });
+    assert!(sends(&out).iter().any(|(_, _, m)| matches!(m, PaxosMsg::AcceptProposal { slot: 1, value: Cmd::Put(5), .. })), "{out:?}");
+}
+
+#[test]
+fn shutdown_cancels_every_live_timer_once() {
+    let mut n: Node<u64> = Node::new(1, NodeContext::new(3), vec![1, 2, 3], HashSet::from([1, 2, 3]), 7, 50, 2).unwrap();
+    n.learner_mut().set_stall_timeout(100);
+    let armed = [timer(&n.on_init()), timer(&n.on_message(2, accepted(5, (1, 2), 9)))];
+    let cancelled: Vec<TimerId> = n.shutdown().into_iter().filter_map(|a| match a { Action::CancelTimer { id } => Some(id), _ => None }).collect();
+    assert!(armed.iter().all(|id| cancelled.contains(id)), "{armed:?} {cancelled:?}");
+    assert!(n.shutdown().is_empty());
+}
+
+#[test]
+fn every_message_has_a_route() {
+    let p = || Proposal::new((1, 1), 3u64);
+    let cases: Vec<(PaxosMsg<u64>, Route)> = vec![
+        (prepare(0, (1, 1)), Route::Acceptor),
+        (accept(0, (1, 1), 3), Route::Acceptor),
+        (PaxosMsg::FetchAccepted { slot: 0, proposal_id: (1, 1) }, Route::Acceptor),
+        (PaxosMsg::PrepareFrom { from_slot: 0, proposal_id: (1, 1), from: 1 }, Route::Acceptor),
+        (PaxosMsg::Promise { slot: 0, accepted_proposal: None, proposal_response: (1, 1) }, Route::Proposer),
+        (PaxosMsg::PromiseIdOnly { slot: 0, accepted_id: None, proposal_response: (1, 1) }, Route::Proposer),
+        (PaxosMsg::AcceptedValue { slot: 0, proposal: p() }, Route::Proposer),
+        (PaxosMsg::PromiseEmpty { slot: 0, proposal_response: (1, 1) }, Route::Proposer),
+        (PaxosMsg::Nack { slot: 0, proposal_id: (1, 1), promised: (2, 1) }, Route::Proposer),
+        (PaxosMsg::Chosen { slot: 0, proposal_id: (1, 1) }, Route::Proposer),
+        (PaxosMsg::LeaderHandoff { to: 2, round: 3 }, Route::Proposer),
+        (PaxosMsg::PromiseFrom { from_slot: 0, proposal_response: (1, 1), accepted: vec![] }, Route::Proposer),
+        (accepted(0, (1, 1), 3), Route::ProposerAndLearner),
+        (learn(0, (1, 1), 3), Route::Learner),
+        (PaxosMsg::QueryChosen { slot: 0 }, Route::Learner),
+        (PaxosMsg::LearnDigest { base: 0, up_to: 0, bitmap: vec![] }, Route::Learner),
+        (PaxosMsg::CatchUpRequest { start: 0, end: 1 }, Route::Learner),
+        (PaxosMsg::CatchUpResponse { entries: vec![], next: None, end: 1 }, Route::Learner),
+        (PaxosMsg::ChosenReply { slot: 0, value: None }, Route::Client),
+    ];
+    for (m, r) in cases {
+        assert_eq!(Node::route(&m), r, "{m:?}");
+    }
+}
+
+#[test]
+fn reads_after_a_token_wait_for_its_slot() {
+    let mut n: Node<u64> = Node::new(1, NodeContext::new(3), vec![1, 2, 3], HashSet::from([1]), 0, 100, 2).unwrap();
+    n.set_lease(Lease::new(1, 0, 1000));
+    let token = CommitToken::for_slot(0);
+    assert_eq!(n.linearizable_read_after(10, token), ReadOutcome::Behind { applied: 0, wanted: 1 });
+    assert_eq!(n.linearizable_read_after(10, CommitToken::default()), ReadOutcome::Ok(vec![]));
+    for from in [2, 3] {
+        n.on_message(from, accepted(0, (1, 9), 42));
+    }
+    assert_eq!(n.linearizable_read_after(10, token), ReadOutcome::Ok(vec![42]));
+    assert_eq!(n.commit_token(), token);
+    assert_eq!(n.linearizable_read_after(2000, token), ReadOutcome::LeaseExpired);
+}
+
+#[test]
+fn next_gap_skips_slots_in_flight() {
+    let mut n: Node<u64> = Node::new(1, NodeContext::new(3), vec![1, 2, 3], HashSet::from([1]), 0, 100, 2).unwrap();
+    for f in [2, 3] {
+        n.on_message(f, accepted(0, (1, 2), 4));
+    }
+    n.on_message(2, accepted(1, (1, 2), 5));
+    assert!(n.learner().is_in_flight(1));
+    assert!(!n.learner().is_in_flight(0));
+    let (slot, out) = n.propose_next_gap(9);
+    assert_eq!(slot, 2);
+    assert!(sends(&out).iter().any(|(_, _, m)| matches!(m, PaxosMsg::Prepare { slot: 2, .. })));
+}
+
+fn node_with_chosen_slot_0(tripwire: bool) -> Node<u64> {
+    let mut n = Node::new(1, NodeContext::new(3), vec![1, 2, 3], HashSet::from([1, 2, 3]), 0, 100, 2).unwrap();
+    n.set_safety_tripwire(tripwire);
+    n.learner_mut().on_message(2, PaxosMsg::CatchUpResponse { entries: vec![(0, 5)], next: None, end: 1 });
+    assert_eq!(n.learner().get_chosen(0), Some(&5));
+    n
+}
+
+#[test]
+fn tripwire_blocks_proposing_over_a_chosen_slot() {
+    for tripwire in [false, true] {
+        let mut n = node_with_chosen_slot_0(tripwire);
+        n.propose(0, 7);
+        let mut out = n.on_message(2, PaxosMsg::PromiseEmpty { slot: 0, proposal_response: (0, 1) });
+        out.extend(n.on_message(3, PaxosMsg::PromiseEmpty { slot: 0, proposal_response: (0, 1) }));
+        let errors: Vec<_> = out.iter().filter_map(|a| match a { Action::Error { error } => Some(error.clone()), _ => None }).collect();
+        if tripwire {
+            assert_eq!(accepts(&out), 0, "{out:?}");
+            assert_eq!(errors, [PaxosError::ProposedOverChosen { slot: 0 }]);
+            assert_eq!(n.acceptor().accepted(0), None);
+        } else {
+            assert_eq!(accepts(&out), 2, "{out:?}");
+            assert!(errors.is_empty());
+        }
+    }
+
+    // Undecided slots are unaffected.
+    let mut n = node_with_chosen_slot_0(true);
+    n.propose(1, 7);
+    let out = n.on_message(2, PaxosMsg::PromiseEmpty { slot: 1, proposal_response: (0, 1) });
+    assert_eq!(accepts(&out), 2, "{out:?}");
+}
+
+#[test]
+fn stats_cover_every_role() {
+    let mut net = nodes(3);
+    net.init_node(1);
+    net.run_until(50);
+    let stats = net.node(1).unwrap().stats();
+    assert_eq!((stats.learner.chosen, stats.learner.gaps, stats.learner.inflight), (1, 0, 0));
+    assert_eq!(stats.proposer.rounds_started, 1);
+    assert!(stats.acceptor.promises_granted > 0);
+    assert_eq!(stats.applied, 1);
+    assert!(net.node(2).unwrap().stats().acceptor.promises_granted > 0);
+}
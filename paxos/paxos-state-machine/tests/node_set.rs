@@ -0,0 +1,47 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::*;
+use paxos_state_machine::{learner::Learner, node_set::{NodeSet, SetBackend}, proposer::Proposer, rng::Rng, types::*};
+
+#[test]
+fn backends_give_the_same_answers() {
+    let mut rng = Rng::new(7);
+    for trial in 0..2000 {
+        let n = 1 + rng.below(9);
+        let ctx = if trial % 3 == 0 {
+            NodeContext::weighted((1..=n).map(|i| (i, 1 + rng.below(4))).collect::<HashMap<_, _>>())
+        } else {
+            NodeContext::new(n)
+        };
+        let quorum = (n / 2 + 1) as usize;
+        let mut h = NodeSet::new(SetBackend::Hash);
+        let mut b = NodeSet::new(SetBackend::Bits);
+        for _ in 0..rng.below(12) {
+            // Now and then an id past the bitset's range.
+            let node = if rng.chance(0.05) { 200 + rng.below(5) } else { rng.below(n + 1) };
+            assert_eq!(h.insert(node), b.insert(node));
+            assert_eq!(h.len(), b.len());
+            assert_eq!(h.contains(node), b.contains(node));
+            assert_eq!(ctx.is_quorum(&h, quorum), ctx.is_quorum(&b, quorum));
+        }
+        let mut hs: Vec<NodeId> = h.iter().collect();
+        let mut bs: Vec<NodeId> = b.iter().collect();
+        hs.sort();
+        bs.sort();
+        assert_eq!(hs, bs);
+    }
+}
+
+#[test]
+fn roles_run_on_the_bits_backend() {
+    let mut ctx = NodeContext::new(3);
+    ctx.set_backend = SetBackend::Bits;
+    let mut l: Learner<u64> = Learner::new(LEARNER, ctx.clone(), 2);
+    l.on_message(1, accepted(0, (1, 1), 5));
+    l.on_message(2, accepted(0, (1, 1), 5));
+    assert_eq!(l.get_chosen(0), Some(&5));
+    let mut p = Proposer::new(1, ctx, vec![1, 2, 3], 7u64, 100, 2).unwrap();
+    assert_eq!(prepares(&p.on_init()), 2);
+}
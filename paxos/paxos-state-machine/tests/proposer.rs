@@ -0,0 +1,844 @@
+// Proposer rounds driven by hand: feed it promises and accepts, look at
+// what it sends.
+mod common;
+
+use std::collections::{hash_map::DefaultHasher, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use common::*;
+use paxos_state_machine::{acceptor::Acceptor, config::PaxosConfig, error::PaxosError, failure_detector::FailureDetector, learner::Learner, msg::PaxosMsg, proposer::{merge_highest, BackoffConfig, BroadcastStrategy, BuildError, Phase, Proposal, Proposer, RoundDebug, RoundFailure, RoundStore}, types::*, wire};
+
+fn proposer() -> Proposer<u64> {
+    Proposer::new(PROPOSER, NodeContext::new(3), vec![1, 2, 3], 7, 100, 2).unwrap()
+}
+
+#[test]
+fn accept_timeout_restarts_only_its_slot() {
+    let mut p = proposer();
+    p.set_accept_retries(0);
+    p.propose(1, 10);
+    p.propose(2, 20);
+    promise_quorum(&mut p, 1, &[1, 2]);
+    let out = promise_quorum(&mut p, 2, &[1, 2]);
+    let (r1, r2) = (p.round_id(1), p.round_id(2));
+    let out = p.on_timeout(timer(&out));
+    assert_eq!(p.round_id(1), r1);
+    assert!(p.round_id(2) > r2);
+    let prepared: Vec<Slot> = sends(&out).into_iter().filter_map(|(_, _, m)| match m { PaxosMsg::Prepare { slot, .. } => Some(slot), _ => None }).collect();
+    assert_eq!(prepared, vec![2; 3]);
+}
+
+// True if `out` sends an AcceptProposal for `v`
+fn proposes(out: &[Action<u64>], v: u64) -> bool {
+    sends(out).iter().any(|(_, _, m)| matches!(m, PaxosMsg::AcceptProposal { value, .. } if *value == v))
+}
+
+#[test]
+fn compare_and_set() {
+    let mut p = proposer();
+    p.propose_if_empty(0, 5);
+    assert!(proposes(&promise_quorum(&mut p, 0, &[2, 3]), 5));
+    p.propose_if_empty(1, 5);
+    let pid = p.round_id(1).unwrap();
+    p.on_message(2, PaxosMsg::Promise { slot: 1, accepted_proposal: Some(Proposal { id: (0, 2), value: 9 }), proposal_response: pid });
+    let out = p.on_message(3, PaxosMsg::PromiseEmpty { slot: 1, proposal_response: pid });
+    assert!(out.contains(&Action::OtherValueAccepted { slot: 1, existing: 9 }));
+    assert!(!proposes(&out, 5));
+    assert!(p.round_id(1).is_none());
+}
+
+#[test]
+fn compare_and_set_retry_keeps_its_own_accepted_value() {
+    let mut p = proposer();
+    p.set_accept_retries(0);
+    p.propose_if_empty(0, 5);
+    let out = promise_quorum(&mut p, 0, &[2, 3]);
+    let first = p.round_id(0).unwrap();
+    p.on_message(2, accepted(0, first, 5));
+    // Acceptor 2 now holds our 5; the retry's Phase 1 hears about it
+    p.on_timeout(timer(&out));
+    let pid = p.round_id(0).unwrap();
+    p.on_message(2, PaxosMsg::Promise { slot: 0, accepted_proposal: Some(Proposal::new(first, 5)), proposal_response: pid });
+    let out = p.on_message(3, PaxosMsg::PromiseEmpty { slot: 0, proposal_response: pid });
+    assert!(!out.iter().any(|a| matches!(a, Action::OtherValueAccepted { .. })));
+    assert!(proposes(&out, 5));
+}
+
+#[test]
+fn prepare_timeouts_back_off_until_a_promise_quorum() {
+    let mut p = proposer();
+    let mut id = timer(&p.on_init());
+    for _ in 0..3 {
+        id = timer(&p.on_timeout(id));
+    }
+    assert_eq!(p.timer_ms(), 800);
+    let out = promise_quorum(&mut p, 0, &[2, 3]);
+    assert!(out.iter().any(|a| matches!(a, Action::SetTimer { ms: 100, .. })));
+    assert_eq!(p.timer_ms(), 100);
+
+    let mut p = proposer();
+    p.set_backoff(BackoffConfig { base_ms: 50, reset_on_success: false, ..BackoffConfig::default() });
+    p.on_init();
+    assert_eq!(p.timer_ms(), 50);
+}
+
+#[test]
+fn value_fn_sees_the_previous_slot() {
+    let mut p = proposer();
+    p.set_value_fn(|prev| prev.map_or(1, |v| v + 1));
+    p.propose(0, 0);
+    assert!(proposes(&promise_quorum(&mut p, 0, &[2, 3]), 1));
+    let pid = p.round_id(0).unwrap();
+    p.on_message(2, accepted(0, pid, 1));
+    p.on_message(3, accepted(0, pid, 1));
+    assert!(p.is_decided(0));
+    p.propose(1, 0);
+    assert!(proposes(&promise_quorum(&mut p, 1, &[2, 3]), 2));
+
+    // An accepted value still wins over the computed one.
+    p.propose(2, 0);
+    let pid = p.round_id(2).unwrap();
+    p.on_message(2, PaxosMsg::Promise { slot: 2, accepted_proposal: Some(Proposal { id: (0, 9), value: 55 }), proposal_response: pid });
+    assert!(proposes(&p.on_message(3, PaxosMsg::PromiseEmpty { slot: 2, proposal_response: pid }), 55));
+}
+
+#[test]
+fn too_few_peers_for_the_quorum_is_rejected() {
+    let r = Proposer::new(1, NodeContext::new(5), vec![2], 0u64, 100, 3);
+    assert_eq!(r.err(), Some(BuildError::InsufficientPeers { peers: 1, number_of_nodes: 5 }));
+    assert!(Proposer::new(1, NodeContext::new(3), vec![2, 3], 0u64, 100, 2).is_ok());
+}
+
+fn hash<T: Hash>(t: &T) -> u64 {
+    let mut s = DefaultHasher::new();
+    t.hash(&mut s);
+    s.finish()
+}
+
+#[test]
+fn proposals_compare_by_id_alone() {
+    let p = Proposal::new((3, 1), 5u64);
+    let q = p.clone().map(|v| v.to_string());
+    assert_eq!(q.id(), (3, 1));
+    assert_eq!(q.value(), "5");
+    let r = p.with_value(9u64);
+    assert_eq!(p, r);
+    assert_eq!(hash(&p), hash(&r));
+    assert_ne!(p.value(), r.value());
+}
+
+#[test]
+fn accept_timeouts_resend_before_falling_back_to_phase_1() {
+    let mut p = proposer();
+    p.set_accept_retries(2);
+    p.on_init();
+    let pid = p.round_id(0).unwrap();
+    let mut out = promise_quorum(&mut p, 0, &[2, 3]);
+    for _ in 0..2 {
+        out = p.on_timeout(timer(&out));
+        assert!(sends(&out).iter().any(|(_, _, m)| *m == accept(0, pid, 7)), "{out:?}");
+        assert_eq!(p.round_id(0), Some(pid));
+    }
+    out = p.on_timeout(timer(&out));
+    assert!(prepares(&out) > 0);
+    assert_ne!(p.round_id(0), Some(pid));
+}
+
+#[test]
+fn unreachable_quorum_parks_the_round_until_peers_return() {
+    let mut p = Proposer::new(1, NodeContext::new(5), vec![1, 2, 3, 4, 5], 7u64, 100, 3).unwrap();
+    let id = timer(&p.on_init());
+    let mut fd = FailureDetector::new(100);
+    for n in [3, 4, 5] {
+        fd.suspect(n);
+    }
+    let out = p.check_quorum(&fd, 0);
+    assert!(out.contains(&Action::QuorumUnreachable { live: 1, needed: 3 }), "{out:?}");
+    assert!(out.contains(&Action::CancelTimer { id }));
+    assert!(p.on_timeout(id).is_empty());
+    assert!(p.check_quorum(&fd, 0).is_empty());
+    fd.heartbeat(3, 1);
+    assert!(!sends(&p.check_quorum(&fd, 1)).is_empty());
+}
+
+#[test]
+fn quorum_check_goes_by_weight() {
+    // 1 carries 3 of 5: alone it's a quorum.
+    let mut p = Proposer::new(1, NodeContext::weighted(HashMap::from([(1, 3), (2, 1), (3, 1)])), vec![1, 2, 3], 7u64, 100, 2).unwrap();
+    p.on_init();
+    let mut fd = FailureDetector::new(100);
+    for n in [2, 3] {
+        fd.suspect(n);
+    }
+    assert!(p.check_quorum(&fd, 0).is_empty());
+    assert!(!p.is_idle());
+
+    // 3 carries 5 of 7: 1 and 2 can't outweigh it.
+    let mut p = Proposer::new(1, NodeContext::weighted(HashMap::from([(1, 1), (2, 1), (3, 5)])), vec![1, 2, 3], 7u64, 100, 2).unwrap();
+    p.on_init();
+    let mut fd = FailureDetector::new(100);
+    fd.suspect(3);
+    assert!(p.check_quorum(&fd, 0).iter().any(|a| matches!(a, Action::QuorumUnreachable { .. })));
+}
+
+#[test]
+fn seeded_slots_are_skipped_and_feed_the_value_fn() {
+    let mut p = proposer();
+    assert!(p.seed_chosen(0, 4).is_empty());
+    assert!(p.is_decided(0));
+    assert!(p.on_init().is_empty());
+    assert!(p.propose(0, 9).is_empty());
+    let out = p.propose(1, 9);
+    assert!(sends(&out).iter().any(|(_, _, m)| matches!(m, PaxosMsg::Prepare { slot: 1, .. })));
+    p.set_value_fn(|prev| prev.copied().unwrap_or(0) + 1);
+    let out = promise_quorum(&mut p, 1, &[2, 3]);
+    assert!(proposes(&out, 5), "{out:?}");
+}
+
+#[test]
+fn lone_node_decides_without_messages() {
+    let mut p = Proposer::new(1, NodeContext::new(1), vec![], 7u64, 100, 1).unwrap();
+    assert_eq!(p.on_init(), vec![Action::ChoseValue { slot: 0, v: 7, request_id: None }]);
+    assert!(p.is_decided(0));
+    assert_eq!(p.propose(1, 8), vec![Action::ChoseValue { slot: 1, v: 8, request_id: None }]);
+    assert_eq!(Proposer::new(1, NodeContext::new(3), vec![], 7u64, 100, 2).err(), Some(BuildError::NoPeers { number_of_nodes: 3 }));
+}
+
+// What `out` sends to `node`
+fn sent_to(out: &[Action<u64>], node: NodeId) -> Vec<PaxosMsg<u64>> {
+    sends(out).into_iter().filter(|(_, to, _)| *to == node).map(|(_, _, m)| m).collect()
+}
+
+#[test]
+fn lazy_promises_fetch_the_value_only_when_it_matters() {
+    let ctx = NodeContext::new(3);
+    let mut accs: Vec<Acceptor<u64>> = (1..=3).map(|i| Acceptor::new(i, ctx.clone(), HashSet::new())).collect();
+    for a in &mut accs {
+        a.set_lazy_promises(true);
+    }
+    accs[1].on_message(9, accept(0, (1, 9), 55));
+    let mut p = Proposer::new(1, ctx, vec![1, 2, 3], 7u64, 100, 2).unwrap();
+    p.set_loopback(true);
+    p.on_init();
+    let pid = p.round_id(0).unwrap();
+    p.on_message(2, PaxosMsg::Nack { slot: 0, proposal_id: pid, promised: (1, 9) });
+    let out = p.propose(0, 7);
+    assert!(p.round_id(0).unwrap() > (1, 9));
+    let replies: Vec<PaxosMsg<u64>> = (0..3).map(|i| {
+        let prepare = sent_to(&out, i as NodeId + 1).remove(0);
+        sent_to(&accs[i].on_message(1, prepare), 1).remove(0)
+    }).collect();
+    assert!(matches!(replies[0], PaxosMsg::PromiseEmpty { .. }));
+    assert!(p.on_message(1, replies[0].clone()).is_empty());
+    let out = p.on_message(2, replies[1].clone());
+    assert_eq!(sent_to(&out, 2), vec![PaxosMsg::FetchAccepted { slot: 0, proposal_id: (1, 9) }]);
+    assert_eq!(accepts(&out), 0);
+    let fetched = accs[1].on_message(1, PaxosMsg::FetchAccepted { slot: 0, proposal_id: (1, 9) });
+    let out = p.on_message(2, sent_to(&fetched, 1).remove(0));
+    assert!(proposes(&out, 55), "{out:?}");
+
+    for m in [PaxosMsg::<u64>::PromiseIdOnly { slot: 1, accepted_id: Some((1, 2)), proposal_response: (3, 4) }, PaxosMsg::FetchAccepted { slot: 1, proposal_id: (1, 2) }] {
+        assert_eq!(wire::decode::<u64>(&wire::encode(&m)).unwrap(), m);
+    }
+}
+
+// `from`'s promise for the round `p` runs in `slot`
+fn promise(p: &mut Proposer<u64>, from: NodeId, slot: Slot, accepted: Option<Proposal<u64>>) -> Vec<Action<u64>> {
+    let proposal_response = p.round_id(slot).unwrap();
+    p.on_message(from, PaxosMsg::Promise { slot, accepted_proposal: accepted, proposal_response })
+}
+
+#[test]
+fn candidate_can_change_until_phase_2_or_an_accepted_value() {
+    let mut p = proposer();
+    assert!(!p.try_replace_candidate(0, 8));
+    p.on_init();
+    promise(&mut p, 2, 0, None);
+    assert!(p.try_replace_candidate(0, 8));
+    assert!(proposes(&promise(&mut p, 3, 0, None), 8));
+    assert!(!p.try_replace_candidate(0, 9));
+
+    let mut p = proposer();
+    p.on_init();
+    promise(&mut p, 2, 0, Some(Proposal::new((0, 3), 55)));
+    assert!(!p.try_replace_candidate(0, 8));
+    assert!(proposes(&promise(&mut p, 3, 0, None), 55));
+}
+
+#[test]
+fn stale_promises_are_counted_and_reported_in_strict_mode() {
+    let mut p = proposer();
+    p.on_init();
+    let old = p.round_id(0).unwrap();
+    p.propose(0, 8);
+    let current = p.round_id(0);
+    let stale = |slot| PaxosMsg::Promise { slot, accepted_proposal: None, proposal_response: old };
+    assert!(p.on_message(2, stale(0)).is_empty());
+    assert_eq!(p.metrics().unknown_round_promises, 1);
+    p.set_strict(true);
+    assert_eq!(p.on_message(2, stale(0)), vec![Action::Error { error: PaxosError::PromiseForUnknownRound { slot: 0, got: old, current } }]);
+    assert_eq!(p.on_message(2, stale(5)), vec![Action::Error { error: PaxosError::PromiseForUnknownRound { slot: 5, got: old, current: None } }]);
+    assert_eq!(p.metrics().unknown_round_promises, 3);
+}
+
+#[test]
+fn external_rounds_must_climb() {
+    let mut p = proposer();
+    let out = p.start_round_with(0, 40, 5).unwrap();
+    assert!(sends(&out).iter().any(|(_, _, m)| matches!(m, PaxosMsg::Prepare { proposal_id: (40, PROPOSER), .. })));
+    assert_eq!(p.start_round_with(1, 40, 5), Err(PaxosError::RoundTooLow { round: 40, next: 41 }));
+    assert_eq!(p.start_round_with(1, 3, 5), Err(PaxosError::RoundTooLow { round: 3, next: 41 }));
+    p.propose(1, 9);
+    assert_eq!(p.round_id(1), Some((41, PROPOSER)));
+}
+
+struct RecordRounds(Arc<Mutex<Vec<u64>>>);
+
+impl RoundStore for RecordRounds {
+    fn persist(&mut self, round: u64) {
+        self.0.lock().unwrap().push(round);
+    }
+}
+
+#[test]
+fn rounds_are_persisted_before_the_prepare() {
+    let log = Arc::new(Mutex::new(vec![]));
+    let mut p = proposer();
+    p.set_round_store(RecordRounds(log.clone()));
+    assert!(log.lock().unwrap().is_empty());
+    let out = p.propose(0, 5);
+    let Some((_, _, PaxosMsg::Prepare { proposal_id, .. })) = sends(&out).into_iter().next() else { panic!("{out:?}") };
+    assert_eq!(*log.lock().unwrap(), [proposal_id.0]);
+    p.propose(1, 6);
+    assert_eq!(*log.lock().unwrap(), [0, 1]);
+}
+
+#[test]
+fn reset_forgets_rounds_but_keeps_the_configuration() {
+    let mut p = proposer();
+    p.seed_chosen(0, 4);
+    p.propose(1, 5);
+    p.reset();
+    assert!(!p.is_decided(0));
+    assert_eq!(p.round_id(1), None);
+    assert_eq!(p.quorum(), 2);
+    assert_eq!(p.peers(), &[1, 2, 3]);
+    p.propose(1, 5);
+    assert_eq!(p.round_id(1), Some((1, PROPOSER)));
+}
+
+// Promises that show `prior` already accepted in `slot`
+fn promises_with(p: &mut Proposer<u64>, slot: Slot, prior: u64) -> Vec<Action<u64>> {
+    let mut out = promise(p, 2, slot, Some(Proposal::new((0, 3), prior)));
+    out.extend(promise(p, 3, slot, None));
+    out
+}
+
+#[test]
+fn any_of_accepts_any_listed_value() {
+    let mut p = proposer();
+    p.propose_any_of(0, 10, [11, 12]);
+    let out = promises_with(&mut p, 0, 12);
+    assert!(!out.iter().any(|a| matches!(a, Action::OtherValueAccepted { .. })));
+    assert!(proposes(&out, 12));
+    p.propose_any_of(1, 10, [11, 12]);
+    assert!(promises_with(&mut p, 1, 13).contains(&Action::OtherValueAccepted { slot: 1, existing: 13 }));
+    p.propose_if_empty(2, 10);
+    assert!(promises_with(&mut p, 2, 10).contains(&Action::OtherValueAccepted { slot: 2, existing: 10 }));
+}
+
+#[test]
+fn backoff_stops_at_its_cap() {
+    let mut p = proposer();
+    p.set_backoff(BackoffConfig { base_ms: 100, max_ms: 1_000, reset_on_success: true });
+    let mut out = p.propose(0, 5);
+    for _ in 0..100 {
+        out = p.on_timeout(timer(&out));
+    }
+    assert_eq!(p.timer_ms(), 1_000);
+    assert_eq!(p.metrics().backoff_capped, 97);
+}
+
+#[test]
+fn batched_events_drop_a_repeated_send() {
+    let run = |dedup| {
+        let mut p = proposer();
+        p.set_accept_retries(1);
+        let prepare = timer(&p.propose(0, 5));
+        let pid = p.round_id(0).unwrap();
+        p.on_message(2, PaxosMsg::PromiseEmpty { slot: 0, proposal_response: pid });
+        // The quorum's accepts and the retry's, under the same id: `seq`
+        // runs on from the Prepare timer's
+        let resend = TimerId { seq: prepare.seq + 1, kind: TimerKind::Accept(0), ..prepare };
+        let batch = vec![Event::Message { from: 3, msg: PaxosMsg::PromiseEmpty { slot: 0, proposal_response: pid } }, Event::Timeout { id: resend }];
+        accepts(&p.on_events(batch, dedup))
+    };
+    assert_eq!(run(false), 6);
+    assert_eq!(run(true), 3);
+}
+
+#[test]
+fn learner_notice_decides_the_slot_once() {
+    let mut p = proposer();
+    p.propose(0, 5);
+    promise_quorum(&mut p, 0, &[2, 3]);
+    assert!(!p.is_decided(0));
+    let pid = p.round_id(0).unwrap();
+    let mut l: Learner<u64> = Learner::new(LEARNER, NodeContext::new(3), 2);
+    l.set_notify_proposers(true);
+    l.on_message(2, accepted(0, pid, 5));
+    let notice = sent_to(&l.on_message(3, accepted(0, pid, 5)), PROPOSER).remove(0);
+    assert_eq!(notice, PaxosMsg::Chosen { slot: 0, proposal_id: pid });
+    assert_eq!(wire::decode::<u64>(&wire::encode(&notice)).unwrap(), notice);
+    let out = p.on_message(LEARNER, notice.clone());
+    assert!(p.is_decided(0));
+    assert_eq!(chosen(&out), [5]);
+    assert!(p.on_message(LEARNER, notice).is_empty());
+}
+
+#[test]
+fn accept_quorum_reports_the_choice_once() {
+    let mut p = proposer();
+    p.propose_traced(0, 5, 42);
+    promise_quorum(&mut p, 0, &[2, 3]);
+    let pid = p.round_id(0).unwrap();
+    let ack = PaxosMsg::Accepted { slot: 0, proposal: Proposal::new(pid, 5), request_id: Some(42) };
+    let out: Vec<_> = [2, 3, 3].into_iter().flat_map(|f| p.on_message(f, ack.clone())).collect();
+    p.on_message(LEARNER, PaxosMsg::Chosen { slot: 0, proposal_id: pid });
+    let reported: Vec<_> = out.into_iter().filter(|a| matches!(a, Action::ChoseValue { .. })).collect();
+    assert_eq!(reported, [Action::ChoseValue { slot: 0, v: 5, request_id: Some(42) }]);
+    assert!(p.on_message(LEARNER, PaxosMsg::Chosen { slot: 0, proposal_id: pid }).is_empty());
+}
+
+// The distinct (slot, value)s `out` asks acceptors to accept
+fn accepts_of(out: &[Action<Cmd>]) -> Vec<(Slot, Cmd)> {
+    let mut v: Vec<_> = sends(out).into_iter().filter_map(|(_, _, m)| match m { PaxosMsg::AcceptProposal { slot, value, .. } => Some((slot, value)), _ => None }).collect();
+    v.dedup();
+    v
+}
+
+// Run `slot` to a Noop decision: one promise carries an accepted Noop.
+fn decide_noop(p: &mut Proposer<Cmd>, slot: Slot) -> (Vec<Action<Cmd>>, Vec<Action<Cmd>>) {
+    let pid = p.round_id(slot).unwrap();
+    let mut phase_2 = p.on_message(2, PaxosMsg::Promise { slot, accepted_proposal: Some(Proposal::new((0, 3), Cmd::Noop)), proposal_response: pid });
+    phase_2.extend(p.on_message(3, PaxosMsg::PromiseEmpty { slot, proposal_response: pid }));
+    let ack = PaxosMsg::Accepted { slot, proposal: Proposal::new(pid, Cmd::Noop), request_id: None };
+    p.on_message(2, ack.clone());
+    (phase_2, p.on_message(3, ack))
+}
+
+#[test]
+fn value_displaced_by_a_noop_is_requeued() {
+    let mut p = Proposer::new(PROPOSER, NodeContext::new(3), vec![1, 2, 3], Cmd::Noop, 100, 2).unwrap();
+    p.set_repropose_after_noop(true);
+    p.propose(4, Cmd::Put(5));
+    let (phase_2, out) = decide_noop(&mut p, 4);
+    assert_eq!(accepts_of(&phase_2), [(4, Cmd::Noop)]);
+    assert!(p.is_decided(4));
+    assert!(sends(&out).iter().any(|(_, _, m)| matches!(m, PaxosMsg::Prepare { slot: 5, .. })));
+    assert_eq!(accepts_of(&promise_quorum(&mut p, 5, &[2, 3])), [(5, Cmd::Put(5))]);
+
+    // Without the option nothing is requeued.
+    let mut p = Proposer::new(PROPOSER, NodeContext::new(3), vec![1, 2, 3], Cmd::Noop, 100, 2).unwrap();
+    p.propose(0, Cmd::Put(5));
+    let (_, out) = decide_noop(&mut p, 0);
+    assert_eq!(p.round_id(1), None);
+    assert!(sends(&out).is_empty());
+}
+
+fn at(round: u64, v: u64) -> Option<Proposal<u64>> {
+    Some(Proposal::new((round, 1), v))
+}
+
+#[test]
+fn merge_keeps_the_highest_proposal() {
+    assert_eq!(merge_highest::<u64>(None, None), None);
+    assert_eq!(merge_highest(at(1, 5), None).map(|p| p.value), Some(5));
+    assert_eq!(merge_highest(None, at(1, 5)).map(|p| p.value), Some(5));
+    assert_eq!(merge_highest(at(1, 5), at(2, 6)).map(|p| p.value), Some(6));
+    assert_eq!(merge_highest(at(2, 6), at(1, 5)).map(|p| p.value), Some(6));
+    assert_eq!(merge_highest(at(2, 6), at(2, 6)).map(|p| p.value), Some(6));
+}
+
+#[test]
+#[should_panic]
+fn merge_panics_on_two_values_under_one_id() {
+    merge_highest(at(2, 6), at(2, 7));
+}
+
+// True if `out` opens Phase 1 for slot 0
+fn prepares_slot_0(out: &[Action<u64>]) -> bool {
+    sends(out).iter().any(|(_, _, m)| matches!(m, PaxosMsg::Prepare { slot: 0, .. }))
+}
+
+#[test]
+fn initial_delay_holds_back_the_first_prepare() {
+    let mut p = proposer();
+    p.set_initial_delay(200, 99);
+    let out = p.on_init();
+    let [Action::SetTimer { id, ms }] = out[..] else { panic!("{out:?}") };
+    assert_eq!(id.kind, TimerKind::Startup);
+    assert!(ms <= 200);
+    assert_eq!(prepares(&p.on_timeout(id)), 3);
+    assert!(p.on_timeout(id).is_empty());
+
+    // Stepping down while waiting parks slot 0 until activated.
+    let mut p = proposer();
+    p.set_initial_delay(200, 99);
+    let id = timer(&p.on_init());
+    assert_eq!(p.step_down(), vec![Action::CancelTimer { id }]);
+    assert!(prepares_slot_0(&p.activate()));
+}
+
+#[test]
+fn initial_delay_is_never_zero() {
+    for seed in 0..500 {
+        let mut p = proposer();
+        p.set_initial_delay(1, seed);
+        assert!(matches!(p.on_init()[..], [Action::SetTimer { id: TimerId { kind: TimerKind::Startup, .. }, ms: 1 }]), "seed {seed}");
+    }
+    let mut p = proposer();
+    p.set_initial_delay(0, 7);
+    assert!(prepares_slot_0(&p.on_init()));
+}
+
+#[test]
+fn empty_promises_let_the_candidate_through() {
+    let mut p = proposer();
+    p.on_init();
+    let out = promise_quorum(&mut p, 0, &[2, 3]);
+    assert_eq!(accepts(&out), 3);
+    assert!(proposes(&out, 7), "{out:?}");
+}
+
+#[test]
+fn last_failure_says_why_the_round_ended() {
+    let mut p = proposer();
+    let id = timer(&p.on_init());
+    assert_eq!(p.last_failure(), None);
+    p.on_timeout(id);
+    assert_eq!(p.last_failure(), Some(RoundFailure::TimedOut));
+    let pid = p.round_id(0).unwrap();
+    p.on_message(2, PaxosMsg::Nack { slot: 0, proposal_id: pid, promised: (9, 3) });
+    assert_eq!(p.last_failure(), Some(RoundFailure::Preempted { by: (9, 3) }));
+
+    // A Nack that already ended the round isn't overwritten by its timer.
+    let mut p = proposer();
+    let id = timer(&p.on_init());
+    let pid = p.round_id(0).unwrap();
+    p.on_message(2, PaxosMsg::Nack { slot: 0, proposal_id: pid, promised: (4, 2) });
+    p.on_timeout(id);
+    assert_eq!(p.last_failure(), Some(RoundFailure::Preempted { by: (4, 2) }));
+}
+
+#[test]
+fn recovery_fills_a_range_and_keeps_accepted_values() {
+    let ctx = NodeContext::new(3);
+    let mut accs: Vec<Acceptor<u64>> = (1..=3).map(|i| Acceptor::new(i, ctx.clone(), HashSet::new())).collect();
+    accs[1].on_message(9, accept(1, (0, 0), 55));
+    let mut p = Proposer::new(1, ctx, vec![1, 2, 3], 7u64, 100, 2).unwrap();
+    p.set_loopback(true);
+    let out = p.recover(0, 3, 0);
+    let rest = exchange(1, &mut p, &mut accs, None, out, |_| {});
+    assert!((0..3).all(|s| p.is_decided(s)));
+    assert_eq!(chosen(&rest), [0, 55, 0]);
+    assert_eq!((0..3).map(|s| accs[2].accepted(s).map(|p| p.value)).collect::<Vec<_>>(), [Some(0), Some(55), Some(0)]);
+    assert_eq!(accs[0].accepted(1).map(|p| p.value), Some(55));
+    assert_eq!(accs[0].accepted(2).map(|p| p.value), Some(0));
+}
+
+#[test]
+fn adopted_value_counts_as_our_decision_when_enabled() {
+    let mut p = proposer();
+    p.set_detect_adopted_value(true);
+    let id = timer(&p.on_init());
+    assert!(p.on_message(2, accepted(0, (5, 2), 8)).is_empty());
+    assert!(p.on_message(2, accepted(0, (5, 2), 7)).is_empty());
+    let out = p.on_message(3, accepted(0, (5, 2), 7));
+    assert!(out.contains(&Action::ChoseValue { slot: 0, v: 7, request_id: None }), "{out:?}");
+    assert!(out.contains(&Action::CancelTimer { id }));
+    assert!(p.is_decided(0));
+    assert!(p.on_timeout(id).is_empty());
+
+    // Off by default.
+    let mut p = proposer();
+    p.on_init();
+    p.on_message(2, accepted(0, (5, 2), 7));
+    p.on_message(3, accepted(0, (5, 2), 7));
+    assert!(!p.is_decided(0));
+
+    // A learner's Chosen names the round; one ack tells us its value.
+    let mut p = proposer();
+    p.set_detect_adopted_value(true);
+    p.on_init();
+    p.on_message(2, accepted(0, (5, 2), 7));
+    assert!(p.on_message(LEARNER, PaxosMsg::Chosen { slot: 0, proposal_id: (5, 2) }).contains(&Action::ChoseValue { slot: 0, v: 7, request_id: None }));
+}
+
+#[test]
+fn accepts_piggyback_the_commit_point() {
+    let mut p = proposer();
+    p.set_piggyback_commits(true);
+    p.on_init();
+    promise_quorum(&mut p, 0, &[2, 3]);
+    let pid = p.round_id(0).unwrap();
+    for n in [2, 3] {
+        p.on_message(n, accepted(0, pid, 7));
+    }
+    assert!(p.is_decided(0));
+    p.propose(1, 8);
+    let out = promise_quorum(&mut p, 1, &[2, 3]);
+    assert!(sends(&out).iter().any(|(_, _, m)| matches!(m, PaxosMsg::AcceptProposal { slot: 1, committed_up_to: Some(0), .. })), "{out:?}");
+}
+
+#[test]
+fn debug_state_shows_each_round() {
+    let mut p = proposer();
+    p.on_init();
+    promise_quorum(&mut p, 0, &[1, 2]);
+    let pid = p.round_id(0).unwrap();
+    let d = p.debug_state();
+    assert_eq!(d.rounds, vec![RoundDebug { slot: 0, proposal_id: pid, phase: Phase::Accept, promises: 2, accept_acks: 0, broadcasts: 2 }]);
+    assert!(!d.idle && !d.gave_up);
+}
+
+#[test]
+fn self_is_kept_out_of_peers_unless_looped_back() {
+    let mut p = Proposer::new(1, NodeContext::new(3), vec![1, 2, 3], 7u64, 100, 2).unwrap();
+    assert_eq!(p.peers(), &[2, 3]);
+    assert_eq!(p.quorum(), 2);
+    assert!(sends(&p.on_init()).iter().all(|(_, to, _)| *to != 1));
+    // Our own acceptor's promise still counts when handed over.
+    let out = promise_quorum(&mut p, 0, &[1, 2]);
+    assert_eq!(accepts(&out), 2);
+    p.set_loopback(true);
+    p.set_loopback(true);
+    assert_eq!(p.peers(), &[2, 3, 1]);
+    assert!(Proposer::new(1, NodeContext::new(3), vec![1, 2], 7u64, 100, 2).is_err());
+}
+
+#[test]
+fn pending_lists_who_has_not_answered() {
+    let mut p: Proposer<u64> = Proposer::new(9, NodeContext::new(4), vec![1, 2, 3], 7, 100, 3).unwrap();
+    assert!(p.pending_promisers(0).is_empty());
+    p.on_init();
+    assert_eq!(p.pending_promisers(0), [1, 2, 3]);
+    promise_quorum(&mut p, 0, &[1, 3]);
+    assert_eq!(p.pending_promisers(0), [2]);
+    promise_quorum(&mut p, 0, &[2]);
+    assert_eq!(p.pending_accepters(0), [1, 2, 3]);
+    let pid = p.round_id(0).unwrap();
+    p.on_message(2, accepted(0, pid, 7));
+    assert_eq!(p.pending_accepters(0), [1, 3]);
+}
+
+#[test]
+fn pending_only_counts_the_targets_asked() {
+    let mut p: Proposer<u64> = Proposer::new(5, NodeContext::new(5), vec![1, 2, 3, 4], 7, 100, 3).unwrap();
+    p.set_broadcast_strategy(BroadcastStrategy::QuorumPlusBuffer(0));
+    p.propose(0, 7);
+    assert_eq!(p.pending_promisers(0), [1, 2, 3]);
+    promise_quorum(&mut p, 0, &[1, 2, 3]);
+    let pid = p.round_id(0).unwrap();
+    p.on_message(2, accepted(0, pid, 7));
+    assert_eq!(p.pending_accepters(0), [1, 3]);
+}
+
+// Who `out` sends Prepares and AcceptProposals to
+fn prepared_to(out: &[Action<u64>]) -> Vec<NodeId> {
+    sends(out).into_iter().filter(|(_, _, m)| matches!(m, PaxosMsg::Prepare { .. })).map(|(_, to, _)| to).collect()
+}
+
+fn accept_sent_to(out: &[Action<u64>]) -> Vec<NodeId> {
+    sends(out).into_iter().filter(|(_, _, m)| matches!(m, PaxosMsg::AcceptProposal { .. })).map(|(_, to, _)| to).collect()
+}
+
+#[test]
+fn broadcast_strategy_picks_the_targets() {
+    let five = || Proposer::<u64>::new(9, NodeContext::new(5), vec![1, 2, 3, 4, 5], 7, 100, 3).unwrap();
+    let mut p = five();
+    p.set_broadcast_strategy(BroadcastStrategy::QuorumPlusBuffer(1));
+    let out = p.on_init();
+    assert_eq!(prepared_to(&out), [1, 2, 3, 4]);
+    // A retry asks everyone.
+    assert_eq!(prepared_to(&p.on_timeout(timer(&out))), [1, 2, 3, 4, 5]);
+
+    let mut p = five();
+    p.set_broadcast_strategy(BroadcastStrategy::Custom(|peers, q| peers.iter().rev().take(q).copied().collect()));
+    assert_eq!(prepared_to(&p.on_init()), [5, 4, 3]);
+    let out = promise_quorum(&mut p, 0, &[5, 4, 3]);
+    assert_eq!(accept_sent_to(&out), [5, 4, 3]);
+}
+
+#[test]
+fn request_id_rides_along_to_the_decision() {
+    let ctx = NodeContext::new(3);
+    let mut accs: Vec<Acceptor<u64>> = (1..=3).map(|i| Acceptor::new(i, ctx.clone(), HashSet::from([LEARNER]))).collect();
+    // Someone else's value is already accepted: it wins, but our request id still rides along.
+    for a in &mut accs[..2] {
+        a.on_message(8, PaxosMsg::AcceptProposal { slot: 0, proposal_id: (0, 0), value: 55, committed_up_to: None, request_id: Some(1) });
+    }
+    let mut p = Proposer::new(1, ctx.clone(), vec![1, 2, 3], 7u64, 100, 2).unwrap();
+    p.set_loopback(true);
+    let mut l: Learner<u64> = Learner::new(LEARNER, ctx, 2);
+    let mut ids = vec![];
+    let out = p.propose_traced(0, 7, 42);
+    let rest = exchange(1, &mut p, &mut accs, Some(&mut l), out, |m| {
+        assert_eq!(wire::decode::<u64>(&wire::encode(m)).unwrap(), *m);
+        if let PaxosMsg::AcceptProposal { request_id, .. } | PaxosMsg::Accepted { request_id, .. } = m {
+            ids.push(*request_id);
+        }
+    });
+    assert!(!ids.is_empty() && ids.iter().all(|&i| i == Some(42)), "{ids:?}");
+    let decided: Vec<_> = rest.into_iter().filter_map(|a| match a { Action::ChoseValue { v, request_id, .. } => Some((v, request_id)), _ => None }).collect();
+    // Once from the proposer, once from the learner.
+    assert_eq!(decided, [(55, Some(42)); 2]);
+    assert_eq!(wire::encode(&accepted(1, (1, 1), 3))[0], 3);
+}
+
+#[test]
+fn handoff_passes_the_round_to_the_successor_only() {
+    let ctx = NodeContext::new(3);
+    let mut old = Proposer::new(1, ctx.clone(), vec![1, 2, 3], 5u64, 100, 2).unwrap();
+    old.on_init();
+    old.on_message(2, PaxosMsg::Nack { slot: 0, proposal_id: (0, 1), promised: (6, 3) });
+    old.propose(0, 5);
+    assert_eq!(old.round_id(0), Some((7, 1)));
+    let out = old.handoff(2);
+    let (_, 2, handoff) = sends(&out).remove(0) else { panic!("{out:?}") };
+    assert_eq!(handoff, PaxosMsg::LeaderHandoff { to: 2, round: 7 });
+    assert!(out.len() > 1 && out[1..].iter().all(|a| matches!(a, Action::CancelTimer { .. })));
+    assert!(old.is_idle());
+    assert_eq!(wire::decode::<u64>(&wire::encode(&handoff)).unwrap(), handoff);
+
+    let mut other = Proposer::new(3, ctx.clone(), vec![1, 2, 3], 8u64, 100, 2).unwrap();
+    other.on_message(1, handoff.clone());
+    other.propose(0, 8);
+    assert_eq!(other.round_id(0), Some((0, 3)));
+
+    let mut successor = Proposer::new(2, ctx.clone(), vec![1, 2, 3], 8u64, 100, 2).unwrap();
+    successor.on_message(1, handoff.clone());
+    let out = successor.propose(0, 8);
+    let rounds: HashSet<_> = sends(&out).into_iter().filter_map(|(_, _, m)| match m { PaxosMsg::Prepare { proposal_id, .. } => Some(proposal_id), _ => None }).collect();
+    assert_eq!(rounds, HashSet::from([(8, 2)]));
+
+    // A successor still parked as a follower takes the handoff all the same
+    let mut idle = Proposer::new(2, ctx, vec![1, 2, 3], 8u64, 100, 2).unwrap();
+    idle.step_down();
+    idle.on_message(1, handoff);
+    idle.propose(0, 8);
+    idle.activate();
+    assert!(idle.round_id(0).is_some_and(|(round, _)| round > 7), "{:?}", idle.round_id(0));
+}
+
+#[test]
+fn single_node_choice_names_its_slot() {
+    let mut p = Proposer::new(1, NodeContext::new(1), vec![], 7u64, 100, 1).unwrap();
+    assert_eq!(p.on_init(), [Action::ChoseValue { slot: 0, v: 7, request_id: None }]);
+    assert_eq!(p.propose(3, 8), [Action::ChoseValue { slot: 3, v: 8, request_id: None }]);
+}
+
+fn budgeted(budget: u32) -> Proposer<u64> {
+    let config = PaxosConfig { broadcast_budget: budget, ..PaxosConfig::default() };
+    Proposer::with_config(1, NodeContext::new(3), vec![2, 3], 5u64, &config).unwrap()
+}
+
+fn broadcasts(out: &[Action<u64>]) -> (usize, usize) {
+    (prepares(out), accepts(out))
+}
+
+/// Prepare and accept broadcasts per step while every accept goes unanswered.
+fn unanswered_accepts(budget: u32) -> Vec<(usize, usize)> {
+    let mut p = budgeted(budget);
+    let out = p.on_init();
+    let mut seen = vec![broadcasts(&out)];
+    let pid = p.round_id(0).unwrap();
+    let mut out = p.on_message(2, PaxosMsg::PromiseEmpty { slot: 0, proposal_response: pid });
+    out.extend(p.on_message(3, PaxosMsg::PromiseEmpty { slot: 0, proposal_response: pid }));
+    seen.push(broadcasts(&out));
+    let mut t = timer(&out);
+    for _ in 0..3 {
+        let out = p.on_timeout(t);
+        seen.push(broadcasts(&out));
+        t = timer(&out);
+    }
+    seen
+}
+
+#[test]
+fn broadcast_budget_caps_accept_retries() {
+    assert_eq!(unanswered_accepts(2), [(2, 0), (0, 2), (2, 0), (2, 0), (2, 0)]);
+    assert_eq!(unanswered_accepts(0), [(2, 0), (0, 2), (0, 2), (0, 2), (2, 0)]);
+    assert_eq!(unanswered_accepts(3), [(2, 0), (0, 2), (0, 2), (2, 0), (2, 0)]);
+}
+
+#[test]
+fn budget_of_one_allows_a_single_broadcast_per_round() {
+    let mut p = budgeted(1);
+    let out = p.on_init();
+    assert_eq!(broadcasts(&out), (2, 0));
+    let t = timer(&out);
+    let pid = p.round_id(0).unwrap();
+    for from in [2, 3] {
+        assert!(p.on_message(from, PaxosMsg::PromiseEmpty { slot: 0, proposal_response: pid }).is_empty());
+    }
+    let out = p.on_timeout(t);
+    assert_eq!(broadcasts(&out), (2, 0));
+    assert!(p.round_id(0) > Some(pid));
+
+    // A led round's one broadcast is its AcceptProposal.
+    let out = p.lead_from(1);
+    let Action::Send { msg: PaxosMsg::PrepareFrom { proposal_id, .. }, .. } = out[0] else { panic!("{out:?}") };
+    for from in [2, 3] {
+        p.on_message(from, PaxosMsg::PromiseFrom { from_slot: 1, proposal_response: proposal_id, accepted: vec![] });
+    }
+    let out = p.propose(1, 9);
+    assert_eq!(broadcasts(&out), (0, 2));
+    assert_eq!(broadcasts(&p.on_timeout(timer(&out))), (2, 0));
+}
+
+fn prepare_from_id(out: &[Action<u64>]) -> ProposalId {
+    match &out[0] {
+        Action::Send { msg: PaxosMsg::PrepareFrom { proposal_id, .. }, .. } => *proposal_id,
+        other => panic!("{other:?}"),
+    }
+}
+
+/// The values of `out`'s AcceptProposals under `pid`; any other send is a failure.
+fn led_accepts(out: &[Action<u64>], pid: ProposalId) -> Vec<u64> {
+    out.iter()
+        .filter_map(|a| match a {
+            Action::Send { msg: PaxosMsg::AcceptProposal { value, proposal_id, .. }, .. } if *proposal_id == pid => Some(*value),
+            Action::Send { msg, .. } => panic!("{msg:?}"),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn leading_skips_phase_one_until_a_slot_needs_a_fresh_id() {
+    let mut p = Proposer::new(1, NodeContext::new(3), vec![2, 3], 0u64, 50, 2).unwrap();
+    let out = p.lead_from(0);
+    assert_eq!(out.len(), 2);
+    let pid = prepare_from_id(&out);
+    p.on_message(2, PaxosMsg::PromiseFrom { from_slot: 0, proposal_response: pid, accepted: vec![(2, Proposal::new((0, 2), 22))] });
+    assert!(!p.is_leading());
+    p.on_message(3, PaxosMsg::PromiseFrom { from_slot: 0, proposal_response: pid, accepted: vec![] });
+    assert!(p.is_leading());
+    assert_eq!(led_accepts(&p.propose(0, 5), pid), [5, 5]);
+    assert_eq!(led_accepts(&p.propose(2, 7), pid), [22, 22]);
+
+    // Same slot again needs a fresh id, which ends the leadership.
+    let out = p.propose(0, 6);
+    assert!(prepares(&out) > 0);
+    assert!(!p.is_leading());
+
+    let pid = prepare_from_id(&p.lead_from(10));
+    for from in [2, 3] {
+        p.on_message(from, PaxosMsg::PromiseFrom { from_slot: 10, proposal_response: pid, accepted: vec![] });
+    }
+    assert!(p.is_leading());
+    p.on_message(3, PaxosMsg::Nack { slot: 10, proposal_id: pid, promised: (99, 3) });
+    assert!(!p.is_leading());
+}
@@ -0,0 +1,51 @@
+// Pluggable quorum checkers, driven through the learner and the proposer.
+mod common;
+
+use common::*;
+use paxos_state_machine::{learner::Learner, msg::PaxosMsg, node_set::NodeSet, proposer::Proposer, types::*};
+
+/// Nodes 0..9 on a 3x3 grid; any full row or column is a quorum.
+struct Grid;
+
+impl QuorumChecker for Grid {
+    fn is_quorum(&self, votes: &NodeSet) -> bool {
+        let row = |r: u64| (0..3).all(|c| votes.contains(r * 3 + c));
+        let col = |c: u64| (0..3).all(|r| votes.contains(r * 3 + c));
+        (0..3).any(row) || (0..3).any(col)
+    }
+}
+
+fn grid() -> NodeContext {
+    let mut ctx = NodeContext::new(9);
+    ctx.set_quorum_checker(Grid);
+    ctx
+}
+
+#[test]
+fn learner_decides_on_a_full_row_or_column() {
+    let mut l: Learner<u64> = Learner::new(20, grid(), 5);
+    for n in [0, 4, 8, 1, 2] {
+        l.on_message(n, accepted(0, (1, 0), 4));
+    }
+    assert_eq!(l.get_chosen(0), Some(&4));
+
+    // Five votes, but no row or column, until 7 completes column 1.
+    let mut l: Learner<u64> = Learner::new(20, grid(), 2);
+    for n in [0, 4, 8, 1, 5] {
+        l.on_message(n, accepted(0, (1, 0), 4));
+    }
+    assert_eq!(l.get_chosen(0), None);
+    l.on_message(7, accepted(0, (1, 0), 4));
+    assert_eq!(l.get_chosen(0), Some(&4));
+}
+
+#[test]
+fn proposer_waits_for_a_grid_quorum_of_promises() {
+    let mut p = Proposer::new(0, grid(), (1..9).collect(), 7u64, 50, 1).unwrap();
+    p.on_init();
+    let promise = || PaxosMsg::PromiseEmpty { slot: 0, proposal_response: (0, 0) };
+    let early: usize = [4, 8, 1, 0].into_iter().map(|n| accepts(&p.on_message(n, promise()))).sum();
+    assert_eq!(early, 0);
+    let out = p.on_message(2, promise());
+    assert!(accepts(&out) > 0, "{out:?}");
+}
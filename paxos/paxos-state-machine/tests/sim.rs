@@ -0,0 +1,242 @@
+mod common;
+
+use std::collections::HashSet;
+
+use common::*;
+use paxos_state_machine::{acceptor::{Acceptor, AcceptorPersisted, AcceptorStore, NackMode}, config::PaxosConfig, conformance::{run_seed, Config}, learner::Learner, msg::PaxosMsg, node::Node, proposer::Proposer, sim::*, types::*};
+
+#[test]
+fn a_slow_acceptor_does_not_hold_up_the_quorum() {
+    let mut net = nodes(3);
+    net.set_node_delay(3, 1000);
+    net.init_node(1);
+    net.run_until(99);
+    assert!(net.node(1).unwrap().proposer().is_decided(0));
+    assert_eq!(net.node(1).unwrap().applied(), &[10]);
+    net.run_to_quiescence(10_000);
+    assert!(net.timeouts_fired().is_empty(), "{:?}", net.timeouts_fired());
+}
+
+#[test]
+fn hosts_boxed_roles() {
+    let mut net: Roles = Network::new();
+    net.add_node(1, Box::new(Node::new(1, NodeContext::new(1), vec![1], HashSet::from([1]), 5, 100, 1).unwrap()));
+    net.init();
+    net.run_to_quiescence(100);
+    assert_eq!(net.outputs().len(), 1);
+}
+
+#[test]
+fn single_decree_end_to_end() {
+    let mut net = roles(3, Proposer::new(PROPOSER, NodeContext::new(3), vec![1, 2, 3], 7, 100, 2).unwrap());
+    net.init_node(PROPOSER);
+    assert!(net.run_to_quiescence(1_000));
+    assert_eq!(chosen_at(&net, LEARNER), vec![7]);
+}
+
+#[test]
+fn clock_fires_timers_in_deadline_order() {
+    let mut c = Clock::new();
+    let t = |seq, kind| TimerId { seq, node: 1, kind };
+    c.set(t(0, TimerKind::Watchdog), 30);
+    c.set(t(1, TimerKind::Prepare(0)), 10);
+    c.set(t(2, TimerKind::Accept(0)), 20);
+    c.cancel(t(2, TimerKind::Accept(0)));
+    assert_eq!(c.peek_next_deadline(), Some(10));
+    assert_eq!(c.advance(5), vec![]);
+    assert_eq!(c.now(), 5);
+    assert_eq!(c.advance(100), vec![t(1, TimerKind::Prepare(0)), t(0, TimerKind::Watchdog)]);
+    assert_eq!(c.now(), 105);
+    assert_eq!(c.peek_next_deadline(), None);
+
+    let mut p = Proposer::new(1, NodeContext::new(3), vec![1, 2, 3], 7u64, 100, 2).unwrap();
+    c.apply(&p.on_init());
+    assert_eq!(c.peek_next_deadline(), Some(205));
+    let fired = c.advance_to(205);
+    assert_eq!(fired.len(), 1);
+    c.apply(&p.on_timeout(fired[0]));
+    assert_eq!(c.peek_next_deadline(), Some(405));
+}
+
+// Everything a lossy run of `nodes(3)` did, for comparing runs
+fn lossy_run(seed: u64) -> String {
+    let mut net = nodes(3);
+    net.set_faults(Faults { drop_rate: 0.2, jitter_ms: 10 }, seed);
+    net.init();
+    net.run_until(2_000);
+    format!("{:?} {:?} {}", net.outputs(), net.timeouts_fired(), net.dropped())
+}
+
+#[test]
+fn runs_replay_from_their_seed() {
+    assert_eq!(lossy_run(7), lossy_run(7));
+    assert_ne!(lossy_run(7), lossy_run(8));
+    let c = Config::default();
+    assert_eq!(format!("{:?}", run_seed(&c, 3)), format!("{:?}", run_seed(&c, 3)));
+}
+
+#[test]
+fn tracker_follows_a_round_to_no_live_timers() {
+    let mut t = TimerTracker::new();
+    let mut p: Proposer<u64> = Proposer::new(1, NodeContext::new(3), vec![2, 3], 7, 100, 2).unwrap();
+    t.apply(&p.on_init());
+    let prepare = t.live().next().unwrap();
+    t.assert_live(&[prepare]);
+    t.apply(&promise_quorum(&mut p, 0, &[1, 2]));
+    t.assert_cancelled(prepare);
+    assert_eq!(t.live().count(), 1);
+    let pid = p.round_id(0).unwrap();
+    for f in [1, 2] {
+        t.apply(&p.on_message(f, accepted(0, pid, 7)));
+    }
+    assert!(p.is_decided(0));
+    t.assert_live(&[]);
+}
+
+/// An acceptor that, when buggy, drops the learner's copy of its Accepted.
+struct Lossy(Acceptor<u64>, bool);
+
+impl HandlesEvents<u64> for Lossy {
+    fn on_message(&mut self, from: NodeId, msg: PaxosMsg<u64>) -> Vec<Action<u64>> {
+        let mut out = self.0.on_message(from, msg);
+        if self.1 && out.len() > 1 {
+            out.truncate(1);
+        }
+        out
+    }
+}
+
+fn traced(buggy: Option<NodeId>) -> Trace<u64> {
+    let ctx = NodeContext::new(3);
+    let mut net: Roles = Network::new();
+    net.set_tracing(true);
+    net.set_faults(Faults { drop_rate: 0.0, jitter_ms: 5 }, 7);
+    for id in 1..=3 {
+        net.add_node(id, Box::new(Lossy(Acceptor::new(id, ctx.clone(), HashSet::from([LEARNER])), buggy == Some(id))));
+    }
+    net.add_node(PROPOSER, Box::new(Proposer::new(PROPOSER, ctx, vec![1, 2, 3], 5, 100, 2).unwrap()));
+    net.init();
+    net.run_until(1000);
+    net.trace().unwrap().clone()
+}
+
+#[test]
+fn trace_diff_points_at_the_first_divergence() {
+    let base = traced(None);
+    assert!(base.len() > 5);
+    assert_eq!(base.diff(&traced(None)), None);
+    let d = base.diff(&traced(Some(2))).unwrap();
+    let ours = d.ours.as_ref().unwrap();
+    assert_eq!(ours.node, 2);
+    assert!(matches!(ours.event, Some(Event::Message { msg: PaxosMsg::AcceptProposal { .. }, .. })));
+    assert!(matches!(&d.action, Some(ActionDiff { index: 1, ours: Some(Action::Send { to: LEARNER, .. }), theirs: None })), "{d}");
+    assert!(base.entries()[..d.index].iter().all(|e| e.node != 2 || !matches!(e.event, Some(Event::Message { msg: PaxosMsg::AcceptProposal { .. }, .. }))));
+}
+
+// Nacks sent before two dueling proposers agree on slot 0
+fn nacks_in_a_duel(nack_mode: NackMode, seed: u64) -> usize {
+    let ids: Vec<NodeId> = (1..=5).collect();
+    let config = PaxosConfig { nack_mode, ..PaxosConfig::default() };
+    let mut net: Network<u64, Node<u64>> = Network::new();
+    net.set_tracing(true);
+    net.set_latency(20);
+    net.set_faults(Faults { drop_rate: 0.0, jitter_ms: 30 }, seed);
+    for &id in &ids {
+        net.add_node(id, Node::with_config(id, NodeContext::new(5), ids.clone(), ids.iter().copied().collect(), id, &config).unwrap());
+    }
+    net.init_node(1);
+    net.init_node(2);
+    while !ids.iter().any(|&id| net.node(id).unwrap().learner().get_chosen(0).is_some()) {
+        assert!(net.next_event_at().is_some_and(|at| at < 120_000));
+        net.step();
+    }
+    net.trace().unwrap().entries().iter().flat_map(|e| &e.actions).filter(|a| matches!(a, Action::Send { msg: PaxosMsg::Nack { .. }, .. })).count()
+}
+
+#[test]
+fn duels_settle_with_or_without_nacks() {
+    let enabled: usize = (0..50).map(|seed| nacks_in_a_duel(NackMode::Enabled, seed)).sum();
+    let silent: usize = (0..50).map(|seed| nacks_in_a_duel(NackMode::Silent, seed)).sum();
+    assert!(enabled > 0);
+    assert_eq!(silent, 0);
+}
+
+/// Slot 0's decision with acceptor 1 waiting `delay` on every disk write:
+/// what the learner chose, how many rounds were tried, when, and the writes.
+fn slow_disk(delay: u64) -> (Vec<u64>, usize, u64, u64) {
+    let ctx = NodeContext::new(3);
+    let mut net: Roles = Network::new();
+    net.set_tracing(true);
+    let disk = MemStore::new();
+    for id in 1..=3 {
+        let store = if id == 1 { disk.clone() } else { MemStore::new() };
+        let mut a = Acceptor::new(id, ctx.clone(), HashSet::from([LEARNER]));
+        a.set_store(store.clone());
+        net.set_persist_delay(id, store, delay);
+        net.add_node(id, Box::new(a));
+    }
+    net.add_node(LEARNER, Box::new(Learner::<u64>::new(LEARNER, ctx.clone(), 2)));
+    net.add_node(PROPOSER, Box::new(Proposer::new(PROPOSER, ctx, vec![1, 2, 3], 5, 30, 2).unwrap()));
+    net.init_node(PROPOSER);
+    net.run_until(5_000);
+    let rounds: HashSet<_> = net
+        .trace()
+        .unwrap()
+        .entries()
+        .iter()
+        .flat_map(|e| &e.actions)
+        .filter_map(|a| match a {
+            Action::Send { msg: PaxosMsg::Prepare { proposal_id, .. }, .. } => Some(*proposal_id),
+            _ => None,
+        })
+        .collect();
+    let at = net.outputs().iter().find(|(_, n, _)| *n == LEARNER).map_or(0, |(t, _, _)| *t);
+    assert_eq!(disk.last().and_then(|s| s.accepted.get(&0).map(|p| p.value)), Some(5));
+    (chosen_at(&net, LEARNER), rounds.len(), at, disk.writes())
+}
+
+#[test]
+fn replies_wait_for_the_disk() {
+    let (chosen, rounds, at, _) = slow_disk(0);
+    assert_eq!((chosen, rounds), (vec![5], 1));
+    assert!(at < 30);
+    let (chosen, rounds, at, writes) = slow_disk(50);
+    assert_eq!(chosen, [5]);
+    assert!(rounds > 1);
+    assert!(at > 50);
+    assert!(writes > 1);
+}
+
+/// Writes on every message, then replies and arms a timer.
+struct Fsyncs(MemStore<u64>);
+
+impl HandlesEvents<u64> for Fsyncs {
+    fn on_message(&mut self, from: NodeId, _: PaxosMsg<u64>) -> Vec<Action<u64>> {
+        self.0.persist(&AcceptorPersisted { latest_promise: None, accepted: Default::default() });
+        let id = TimerId { seq: 0, node: 1, kind: TimerKind::Watchdog };
+        vec![Action::SetTimer { id, ms: 10 }, Action::Send { to: from, from: 1, msg: PaxosMsg::Chosen { slot: 0, proposal_id: (1, 1) } }]
+    }
+}
+
+struct Sink;
+
+impl HandlesEvents<u64> for Sink {
+    fn on_message(&mut self, _: NodeId, _: PaxosMsg<u64>) -> Vec<Action<u64>> {
+        vec![]
+    }
+}
+
+#[test]
+fn timers_do_not_wait_for_the_disk() {
+    let mut net: Roles = Network::new();
+    net.set_tracing(true);
+    let disk = MemStore::new();
+    net.add_node(1, Box::new(Fsyncs(disk.clone())));
+    net.add_node(2, Box::new(Sink));
+    net.set_persist_delay(1, disk, 50);
+    net.inject(1, Event::Message { from: 2, msg: PaxosMsg::Chosen { slot: 0, proposal_id: (1, 1) } });
+    net.run_until(1_000);
+    assert_eq!(net.timeouts_fired().iter().map(|(at, n, _)| (*at, *n)).collect::<Vec<_>>(), [(10, 1)]);
+    let reply_at = net.trace().unwrap().entries().iter().find(|e| e.node == 2).map(|e| e.at);
+    assert_eq!(reply_at, Some(51));
+}
@@ -0,0 +1,14 @@
+use std::collections::HashSet;
+
+use paxos_state_machine::{topology::*, types::*};
+
+#[test]
+fn validation_names_the_first_problem() {
+    let nodes: HashSet<NodeId> = [1, 2, 3].into();
+    let ctx = NodeContext::new(3);
+    assert_eq!(validate_topology(&ctx, &nodes, &nodes, &[(1, vec![1, 2, 3])]), Ok(()));
+    assert_eq!(validate_topology(&ctx, &HashSet::new(), &nodes, &[]), Err(TopologyError::NoAcceptors));
+    assert_eq!(validate_topology(&ctx, &nodes, &HashSet::new(), &[]), Err(TopologyError::NoLearners));
+    assert_eq!(validate_topology(&ctx, &nodes, &nodes, &[(1, vec![1, 4])]), Err(TopologyError::PeerNotAcceptor { proposer: 1, peer: 4 }));
+    assert_eq!(validate_topology(&NodeContext::new(5), &nodes, &nodes, &[]), Err(TopologyError::NodeCountMismatch { number_of_nodes: 5, acceptors: 3 }));
+}
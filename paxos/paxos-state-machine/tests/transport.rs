@@ -0,0 +1,186 @@
+// Framing, queues and connections, over in-memory streams and loopback TCP.
+mod common;
+
+use std::io::{self, Cursor, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use common::prepare;
+use paxos_state_machine::{failure_detector::FailureDetector, msg::PaxosMsg, transport::*};
+
+/// Reads come from a fixed buffer, writes pile up for the next reader.
+struct Mem {
+    r: Cursor<Vec<u8>>,
+    w: Vec<u8>,
+    closed: bool,
+}
+
+impl Read for Mem {
+    fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
+        self.r.read(b)
+    }
+}
+
+impl Write for Mem {
+    fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+        self.w.write(b)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FrameStream for Mem {
+    fn close(&mut self) {
+        self.closed = true;
+    }
+}
+
+fn mem(bytes: Vec<u8>) -> Mem {
+    Mem { r: Cursor::new(bytes), w: vec![], closed: false }
+}
+
+#[test]
+fn oversized_frame_closes_the_connection() {
+    let config = TransportConfig { max_frame_bytes: 10, ..Default::default() };
+    let mut c = Connection::new(mem(11u32.to_le_bytes().to_vec()), config);
+    assert!(matches!(c.read_frame(), Err(TransportError::FrameTooLarge { len: 11, max: 10 })));
+    assert!(c.is_closed());
+    assert!(c.into_inner().closed);
+    let mut at_limit = 10u32.to_le_bytes().to_vec();
+    at_limit.extend([7u8; 10]);
+    let mut c = Connection::new(mem(at_limit), config);
+    assert_eq!(c.read_frame().unwrap(), vec![7u8; 10]);
+}
+
+#[test]
+fn messages_round_trip() {
+    let mut c = Connection::new(mem(vec![]), TransportConfig::default());
+    c.send(3, &prepare(1, (1, 1))).unwrap();
+    let written = c.into_inner().w;
+    let mut c = Connection::new(mem(written), TransportConfig::default());
+    assert_eq!(c.recv::<u64>().unwrap(), (3, prepare(1, (1, 1))));
+    assert!(matches!(c.recv::<u64>(), Err(TransportError::Closed)));
+}
+
+#[test]
+fn full_queue_follows_its_backpressure_policy() {
+    let q = Outbound::new(&TransportConfig { outbound_capacity: 1, backpressure: BackpressurePolicy::DropOldest, ..Default::default() });
+    q.push(2, 1, prepare(1, (1, 1))).unwrap();
+    q.push(2, 1, prepare(2, (2, 1))).unwrap();
+    assert_eq!(q.len(), 1);
+    assert_eq!(q.try_pop().unwrap().2, prepare(2, (2, 1)));
+
+    let q = Outbound::new(&TransportConfig { outbound_capacity: 1, backpressure: BackpressurePolicy::Error, ..Default::default() });
+    q.push(2, 1, prepare(1, (1, 1))).unwrap();
+    assert!(matches!(q.push(2, 1, prepare(2, (2, 1))), Err(TransportError::Backpressure { capacity: 1 })));
+
+    // The default blocks the pusher until there's room.
+    let q = Outbound::new(&TransportConfig { outbound_capacity: 1, ..Default::default() });
+    q.push(2, 1, prepare(1, (1, 1))).unwrap();
+    let pusher = q.clone();
+    let h = std::thread::spawn(move || pusher.push(2, 1, prepare(2, (2, 1))).unwrap());
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    assert_eq!(q.pop().unwrap().2, prepare(1, (1, 1)));
+    h.join().unwrap();
+    assert_eq!(q.pop().unwrap().2, prepare(2, (2, 1)));
+    q.close();
+    assert!(q.pop().is_none());
+}
+
+/// A toy keyed hash; good enough to tell keys and bytes apart.
+struct Mac(u64);
+
+impl FrameAuth for Mac {
+    fn sign(&self, body: &[u8]) -> Vec<u8> {
+        body.iter().fold(self.0, |h, b| h.wrapping_mul(31).wrapping_add(*b as u64)).to_le_bytes().to_vec()
+    }
+
+    fn verify(&self, body: &[u8], tag: &[u8]) -> bool {
+        self.sign(body) == tag
+    }
+}
+
+fn signed(bytes: Vec<u8>, key: u64) -> Connection<Mem> {
+    let mut c = Connection::new(mem(bytes), TransportConfig::default());
+    c.set_auth(Mac(key));
+    c
+}
+
+#[test]
+fn signed_frames_reject_tampering_and_wrong_keys() {
+    let mut c = signed(vec![], 7);
+    c.send(3, &prepare(1, (1, 1))).unwrap();
+    let written = c.into_inner().w;
+    assert_eq!(signed(written.clone(), 7).recv::<u64>().unwrap(), (3, prepare(1, (1, 1))));
+    let mut tampered = written.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(matches!(signed(tampered, 7).recv::<u64>(), Err(TransportError::AuthFailed)));
+    assert!(matches!(signed(written, 8).recv::<u64>(), Err(TransportError::AuthFailed)));
+}
+
+fn query(slot: u64) -> PaxosMsg<u64> {
+    PaxosMsg::QueryChosen { slot }
+}
+
+#[test]
+fn pool_reconnects_and_delivers_the_backlog_in_order() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let config = PoolConfig { backoff_base: Duration::from_millis(5), backoff_max: Duration::from_millis(20), max_attempts: 0, ..PoolConfig::default() };
+    let mut pool: PeerPool<u64> = PeerPool::new([(2, addr)], config);
+    // Signed, reconnects included
+    pool.set_auth(|| Mac(7));
+    pool.send(2, 1, query(0)).unwrap();
+    let (s, _) = listener.accept().unwrap();
+    let mut c = Connection::new(s, TransportConfig::default());
+    c.set_auth(Mac(7));
+    assert_eq!(c.recv::<u64>().unwrap(), (1, query(0)));
+    drop(c);
+    drop(listener);
+
+    // Writes into the dead connection fail sooner or later; reconnects are refused.
+    for i in 1..50 {
+        pool.send(2, 1, query(i)).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert!(!pool.is_connected(2));
+    assert!(pool.queued(2) > 0);
+
+    let listener = TcpListener::bind(addr).unwrap();
+    let got = std::thread::scope(|sc| {
+        let h = sc.spawn(|| {
+            let (s, _) = listener.accept().unwrap();
+            let mut c = Connection::new(s, TransportConfig::default());
+            c.set_auth(Mac(7));
+            let mut got = vec![];
+            while let Ok((_, PaxosMsg::QueryChosen { slot })) = c.recv::<u64>() {
+                got.push(slot);
+                if slot == 49 {
+                    break;
+                }
+            }
+            got
+        });
+        while pool.queued(2) > 0 {
+            std::thread::sleep(Duration::from_millis(5));
+            pool.flush();
+        }
+        h.join().unwrap()
+    });
+    assert_eq!(got.last(), Some(&49));
+    assert!(got.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+fn pool_gives_up_on_a_dead_peer() {
+    let dead: SocketAddr = "127.0.0.1:1".parse().unwrap();
+    let mut pool: PeerPool<u64> = PeerPool::new([(3, dead)], PoolConfig { backoff_base: Duration::ZERO, max_attempts: 2, ..PoolConfig::default() });
+    pool.send(3, 1, query(0)).unwrap();
+    assert!(matches!(pool.send(3, 1, query(0)), Err(TransportError::Unreachable { peer: 3 })));
+    let mut fd = FailureDetector::new(100);
+    pool.report_unreachable(&mut fd);
+    assert!(fd.is_suspected(3, 0));
+    assert!(pool.send(9, 1, query(0)).is_err());
+}
@@ -0,0 +1,59 @@
+// Futures that resolve when a slot is learned.
+#![cfg(feature = "wait-for")]
+mod common;
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use common::*;
+use paxos_state_machine::{learner::Learner, types::*};
+
+/// Counts its wakeups.
+struct Wakes(AtomicUsize);
+
+impl Wake for Wakes {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn learner() -> Learner<u64> {
+    Learner::new(LEARNER, NodeContext::new(3), 2)
+}
+
+#[test]
+fn every_waiter_wakes_with_the_value() {
+    let mut l = learner();
+    let wakes = Arc::new(Wakes(AtomicUsize::new(0)));
+    let waker = Waker::from(wakes.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut a = pin!(l.wait_for(3));
+    let mut b = pin!(l.wait_for(3));
+    assert!(a.as_mut().poll(&mut cx).is_pending());
+    assert!(b.as_mut().poll(&mut cx).is_pending());
+    for n in [1, 2] {
+        l.on_message(n, accepted(3, (1, 1), 30));
+    }
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 2);
+    assert_eq!(a.as_mut().poll(&mut cx), Poll::Ready(30));
+    assert_eq!(b.as_mut().poll(&mut cx), Poll::Ready(30));
+    assert_eq!(pin!(l.wait_for(3)).poll(&mut cx), Poll::Ready(30));
+}
+
+#[test]
+fn dropped_waiters_are_pruned_and_reset_clears_the_rest() {
+    let mut l = learner();
+    for slot in 0..100 {
+        drop(l.wait_for(slot));
+    }
+    let kept = l.wait_for(500);
+    assert_eq!(l.waiting(), 1);
+    drop(l.wait_for(501));
+    assert_eq!(l.waiting(), 1);
+    l.reset();
+    assert_eq!(l.waiting(), 0);
+    drop(kept);
+}
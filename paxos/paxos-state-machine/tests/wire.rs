@@ -0,0 +1,49 @@
+// The wire format, as the fuzz targets see it: decode turns anything at
+// all into a message or a DecodeError, never a panic, and a message it
+// accepts encodes back to the very bytes it came from.
+use std::path::Path;
+
+use paxos_state_machine::wire;
+
+// Both decodes on `bytes`; true if either accepted them
+fn round_trips(bytes: &[u8]) -> bool {
+    let as_u64 = wire::decode::<u64>(bytes).map(|m| wire::encode(&m));
+    let as_bytes = wire::decode::<Vec<u8>>(bytes).map(|m| wire::encode(&m));
+    for encoded in [as_u64, as_bytes].into_iter().flatten() {
+        assert_eq!(encoded, bytes);
+    }
+    wire::decode::<u64>(bytes).is_ok() || wire::decode::<Vec<u8>>(bytes).is_ok()
+}
+
+#[test]
+fn fuzz_corpus_round_trips() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("../fuzz/corpus");
+    for target in ["decode", "decode_bytes"] {
+        for entry in std::fs::read_dir(corpus.join(target)).unwrap() {
+            let path = entry.unwrap().path();
+            round_trips(&std::fs::read(&path).unwrap());
+        }
+    }
+}
+
+#[test]
+fn random_bytes_never_panic() {
+    // xorshift, so the inputs are the same on every run
+    let mut x: u64 = 1;
+    let mut next = || {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    };
+    let mut accepted = 0;
+    for _ in 0..200_000 {
+        let len = next() % 40;
+        // A plausible tag up front, and small bytes where lengths and
+        // option flags go, so some of them get past the header
+        let mut bytes = vec![(next() % 6) as u8];
+        bytes.extend((0..len).map(|i| if i % 3 == 0 { (next() % 3) as u8 } else { next() as u8 }));
+        accepted += round_trips(&bytes) as u32;
+    }
+    assert!(accepted > 0);
+}
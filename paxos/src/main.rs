@@ -1,3 +1,63 @@
-fn main() {
-    println!("Hello, world!");
+use std::process::ExitCode;
+
+use paxos_state_machine::{conformance, dueling};
+
+const USAGE: &str = "usage: paxos (conformance | dueling) [iterations] [first-seed]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let parse = |s: &String| s.parse::<u64>().map_err(|_| format!("not a number: {s}"));
+    let (iterations, first_seed) = match (args.get(1).map(parse).transpose(), args.get(2).map(parse).transpose()) {
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{e}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+        (Ok(iterations), Ok(seed)) => (iterations, seed),
+    };
+    match args.first().map(String::as_str) {
+        Some("conformance") => {
+            let mut config = conformance::Config::default();
+            config.iterations = iterations.unwrap_or(config.iterations);
+            config.first_seed = first_seed.unwrap_or(config.first_seed);
+            run_conformance(&config)
+        }
+        Some("dueling") => {
+            let mut config = dueling::Config::default();
+            config.iterations = iterations.unwrap_or(config.iterations);
+            config.first_seed = first_seed.unwrap_or(config.first_seed);
+            run_dueling(&config)
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_conformance(config: &conformance::Config) -> ExitCode {
+    match conformance::run(config) {
+        Ok(r) => {
+            println!("ok: {} runs, {} steps, {} learned, {} dropped", r.runs, r.steps, r.learned, r.dropped);
+            ExitCode::SUCCESS
+        }
+        Err(v) => {
+            eprintln!("safety violation: {v}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Two proposers, then three.
+fn run_dueling(config: &dueling::Config) -> ExitCode {
+    for proposers in [2, 3] {
+        let config = dueling::Config { proposers, ..config.clone() };
+        match dueling::run(&config) {
+            Ok(r) => println!("{proposers} proposers: {r}"),
+            Err(e) => {
+                eprintln!("livelock: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
 }